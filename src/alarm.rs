@@ -0,0 +1,559 @@
+use crate::prelude::*;
+use crate::config::{AlarmRepeat, Colors, Sensor, SensorKind};
+use crate::exec;
+use crate::notify;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    #[default]
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Warning => "warning",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmState {
+    #[default]
+    Normal,
+
+    /// Value is above `warn_high`
+    WarnHigh,
+
+    /// Value is below `warn_low`
+    WarnLow,
+
+    /// Value is above `alarm_high`
+    High,
+
+    /// Value is below `alarm_low`
+    Low,
+}
+
+impl AlarmState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::WarnHigh => "warn_high",
+            Self::WarnLow => "warn_low",
+            Self::High => "high",
+            Self::Low => "low",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::Normal => Severity::Normal,
+            Self::WarnHigh | Self::WarnLow => Severity::Warning,
+            Self::High | Self::Low => Severity::Critical,
+        }
+    }
+
+    /// Threshold of the sensor that causes the state
+    pub fn threshold(&self, sensor: &Sensor) -> Option<f64> {
+        match self {
+            Self::Normal => None,
+            Self::WarnHigh => sensor.warn_high,
+            Self::WarnLow => sensor.warn_low,
+            Self::High => sensor.alarm_high,
+            Self::Low => sensor.alarm_low,
+        }
+    }
+
+    /// Compare the value against thresholds of the sensor, the most severe one wins
+    ///
+    /// Value exactly equal to the threshold does not trigger the alarm
+    pub fn evaluate(sensor: &Sensor, value: f64) -> Self {
+        if sensor.alarm_high.is_some_and(|x| value > x) {
+            Self::High
+        } else if sensor.alarm_low.is_some_and(|x| value < x) {
+            Self::Low
+        } else if sensor.warn_high.is_some_and(|x| value > x) {
+            Self::WarnHigh
+        } else if sensor.warn_low.is_some_and(|x| value < x) {
+            Self::WarnLow
+        } else {
+            Self::Normal
+        }
+    }
+
+    /// Next state after the value was read, active alarm stays until value is `alarm_hysteresis` past the threshold
+    pub fn next(self, sensor: &Sensor, value: f64) -> Self {
+        let hysteresis = sensor.alarm_hysteresis.unwrap_or_default();
+
+        let held = match self {
+            Self::High | Self::WarnHigh => self.threshold(sensor).is_some_and(|x| value > x - hysteresis),
+            Self::Low | Self::WarnLow => self.threshold(sensor).is_some_and(|x| value < x + hysteresis),
+            Self::Normal => false,
+        };
+
+        // escalating is never held back
+        let evaluated = Self::evaluate(sensor, value);
+        if held && evaluated.severity() <= self.severity() {
+            self
+        } else {
+            evaluated
+        }
+    }
+}
+
+/// Alarm that is currently active
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveAlarm {
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Alarm state of a sensor kept across ticks
+#[derive(Debug, Clone, Default)]
+pub struct AlarmTracker {
+    pub state: AlarmState,
+
+    /// State the value has been in since the instant, until it lasts for `alarm_for`
+    pending: Option<(AlarmState, Instant)>,
+
+    /// When the alarm was last emitted, for `alarm_repeat`
+    emitted: Option<Instant>,
+
+    /// Last reading of a fan was a 0 that was not evaluated
+    zero: bool,
+}
+
+impl AlarmTracker {
+    /// Update with a new value read at `now`, returns the new state
+    ///
+    /// With `alarm_for` the alarm only fires once the value stays past the threshold for that long
+    ///
+    /// Fans read 0 for a tick while PWM changes, so unless the sensor is `strict` a 0 only counts once it repeats
+    pub fn update(&mut self, sensor: &Sensor, value: f64, now: Instant) -> AlarmState {
+        if sensor.kind == Some(SensorKind::Fan) && !sensor.strict && value == 0.0 && !self.zero {
+            self.zero = true;
+            return self.state;
+        }
+
+        self.zero = value == 0.0;
+        let next = self.state.next(sensor, value);
+
+        // only escalation is delayed, hysteresis takes care of clearing the alarm
+        if next.severity() > self.state.severity() && let Some(duration) = sensor.alarm_for {
+            let since = match self.pending {
+                Some((state, since)) if state == next => since,
+                _ => {
+                    self.pending = Some((next, now));
+                    now
+                },
+            };
+
+            if now.duration_since(since) < duration {
+                return self.state;
+            }
+        }
+
+        if next != self.state {
+            self.emitted = (next != AlarmState::Normal).then_some(now);
+        }
+
+        self.pending = None;
+        self.state = next;
+        next
+    }
+
+    /// Returns true if the active alarm should be emitted again according to `alarm_repeat`
+    pub fn repeat_due(&mut self, sensor: &Sensor, now: Instant) -> bool {
+        let Some(AlarmRepeat::Every(interval)) = sensor.alarm_repeat else {
+            return false;
+        };
+
+        match self.emitted {
+            Some(emitted) if self.state != AlarmState::Normal && now.duration_since(emitted) >= interval => {
+                self.emitted = Some(now);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Get message describing the alarm, returns `None` if there is no alarm
+pub fn alarm_message(sensor: &Sensor, state: AlarmState, value: f64) -> Option<ActiveAlarm> {
+    let threshold = state.threshold(sensor)?;
+    let direction = match state {
+        AlarmState::Normal => return None,
+        AlarmState::High | AlarmState::WarnHigh => "above",
+        AlarmState::Low | AlarmState::WarnLow => "below",
+    };
+
+    let kind = match state.severity() {
+        Severity::Warning => "warning threshold",
+        _ => "threshold",
+    };
+
+    Some(ActiveAlarm {
+        severity: state.severity(),
+        message: format!("{} is {direction} the {kind} {threshold}", sensor.format_labeled(value)),
+    })
+}
+
+/// Send notification when alarm state changes, both on alarm and on recovery
+pub fn notify_transition(sensor: &Sensor, previous: AlarmState, state: AlarmState, value: f64) {
+    if previous == state {
+        return;
+    }
+
+    let result = match alarm_message(sensor, state, value) {
+        Some(alarm) if alarm.severity == Severity::Warning =>
+            notify::send("Kelvin warning", &alarm.message, notify::Urgency::Normal),
+        Some(alarm) => notify::send("Kelvin alarm", &alarm.message, notify::Urgency::Critical),
+        None => notify::send(
+            "Kelvin alarm cleared",
+            &format!("{} is back to normal", sensor.format_labeled(value)),
+            notify::Urgency::Normal,
+        ),
+    };
+
+    if let Err(err) = result {
+        crate::log::error!("{err:#}");
+    }
+}
+
+impl std::fmt::Display for ActiveAlarm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "WARNING: {}", self.message),
+            _ => write!(f, "ALARM: {}", self.message),
+        }
+    }
+}
+
+/// Make the alarm line stand out in the terminal, colors are not used if `colors` is not set
+pub fn highlight(alarm: &ActiveAlarm, colors: Option<&Colors>) -> String {
+    match colors {
+        Some(colors) => crate::color::paint(&alarm.to_string(), &format!("1;{}", colors.severity(alarm.severity))),
+        None => alarm.to_string(),
+    }
+}
+
+/// Alarm commands of a sensor that have not exited yet
+#[derive(Debug, Default)]
+pub struct AlarmCommands {
+    running: Vec<exec::Background>,
+}
+
+impl AlarmCommands {
+    /// Log exit status of commands that have exited
+    pub fn reap(&mut self, sensor: &Sensor) {
+        self.running.retain_mut(|command| {
+            match command.finished() {
+                Ok(None) => return true,
+                Ok(Some(status)) if status.success() =>
+                    crate::log::info!("Alarm command {:?} of sensor {:?} exited with {status}", command.name(), sensor.name),
+                Ok(Some(status)) =>
+                    crate::log::warning!("Alarm command {:?} of sensor {:?} exited with {status}", command.name(), sensor.name),
+                Err(err) => crate::log::error!("{err:#}"),
+            }
+
+            false
+        });
+    }
+
+    /// Run `alarm_command` or `alarm_clear_command` when alarm state changes
+    pub fn run_transition(&mut self, sensor: &Sensor, previous: AlarmState, state: AlarmState, value: f64) -> Result<()> {
+        if previous == state {
+            return Ok(());
+        }
+
+        let (command, threshold) = match state {
+            AlarmState::Normal => (&sensor.alarm_clear_command, previous.threshold(sensor)),
+            _ => (&sensor.alarm_command, state.threshold(sensor)),
+        };
+
+        let Some(command) = command else {
+            return Ok(());
+        };
+
+        if !sensor.alarm_command_parallel && let Some(running) = self.running.first() {
+            bail!("Skipping alarm command of sensor {:?} as {:?} is still running", sensor.name, running.name());
+        }
+
+        let env = [
+            ("KELVIN_SENSOR", sensor.name.clone()),
+            ("KELVIN_VALUE", value.to_string()),
+            ("KELVIN_THRESHOLD", threshold.map(|x| x.to_string()).unwrap_or_default()),
+            ("KELVIN_STATE", state.as_str().to_string()),
+            ("KELVIN_SEVERITY", state.severity().as_str().to_string()),
+        ];
+
+        self.running.push(exec::spawn(command, &env)?);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn sensor(alarm_low: Option<f64>, alarm_high: Option<f64>) -> Sensor {
+        Sensor {
+            alarm_low,
+            alarm_high,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let both = sensor(Some(10.0), Some(90.0));
+        assert_eq!(AlarmState::evaluate(&both, 50.0), AlarmState::Normal);
+        assert_eq!(AlarmState::evaluate(&both, 90.5), AlarmState::High);
+        assert_eq!(AlarmState::evaluate(&both, 9.5), AlarmState::Low);
+
+        // exactly at the threshold is not an alarm
+        assert_eq!(AlarmState::evaluate(&both, 90.0), AlarmState::Normal);
+        assert_eq!(AlarmState::evaluate(&both, 10.0), AlarmState::Normal);
+    }
+
+    #[test]
+    fn test_evaluate_single_threshold() {
+        let high = sensor(None, Some(90.0));
+        assert_eq!(AlarmState::evaluate(&high, -100.0), AlarmState::Normal);
+        assert_eq!(AlarmState::evaluate(&high, 91.0), AlarmState::High);
+
+        let low = sensor(Some(10.0), None);
+        assert_eq!(AlarmState::evaluate(&low, 1000.0), AlarmState::Normal);
+        assert_eq!(AlarmState::evaluate(&low, 9.0), AlarmState::Low);
+
+        let none = sensor(None, None);
+        assert_eq!(AlarmState::evaluate(&none, 1000.0), AlarmState::Normal);
+    }
+
+    /// Feed values one by one returning state after each
+    fn states(sensor: &Sensor, values: &[f64]) -> Vec<AlarmState> {
+        let mut state = AlarmState::Normal;
+        values.iter()
+            .map(|x| {
+                state = state.next(sensor, *x);
+                state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_alarm_commands() {
+        use AlarmState::{High, Normal};
+
+        let dir = TempDir::new("alarm-command");
+        let path = dir.join("output");
+        let script = format!("echo $KELVIN_SENSOR $KELVIN_VALUE $KELVIN_THRESHOLD $KELVIN_STATE >> {path:?}; sleep 0.2");
+        let command = Some(vec!["sh".to_string(), "-c".to_string(), script]);
+
+        let sensor = Sensor {
+            name: "cpu".to_string(),
+            alarm_command: command.clone(),
+            alarm_clear_command: command,
+            ..sensor(None, Some(85.0))
+        };
+
+        let mut commands = AlarmCommands::default();
+        commands.run_transition(&sensor, Normal, Normal, 50.0).unwrap();
+        commands.run_transition(&sensor, Normal, High, 90.5).unwrap();
+
+        // previous one is still running
+        assert!(commands.run_transition(&sensor, High, Normal, 80.0).is_err());
+
+        while !commands.running.is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            commands.reap(&sensor);
+        }
+
+        commands.run_transition(&sensor, High, Normal, 80.0).unwrap();
+
+        // parallel commands are allowed to overlap
+        let parallel = Sensor { alarm_command_parallel: true, ..sensor.clone() };
+        commands.run_transition(&parallel, Normal, High, 90.0).unwrap();
+
+        while !commands.running.is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            commands.reap(&sensor);
+        }
+
+        // last two ran at the same time
+        let output = std::fs::read_to_string(&path).unwrap();
+        let mut lines = output.lines().collect::<Vec<_>>();
+        lines.sort();
+        assert_eq!(lines, ["cpu 80 85 normal", "cpu 90 85 high", "cpu 90.5 85 high"]);
+    }
+
+    #[test]
+    fn test_alarm_for() {
+        use AlarmState::{High, Normal};
+        use std::time::Duration;
+
+        let sensor = Sensor {
+            alarm_for: Some(Duration::from_secs(30)),
+            alarm_hysteresis: Some(5.0),
+            ..sensor(Some(10.0), Some(85.0))
+        };
+
+        let start = Instant::now();
+        let mut tracker = AlarmTracker::default();
+        let mut feed = |secs: u64, value: f64| tracker.update(&sensor, value, start + Duration::from_secs(secs));
+
+        // short spike is ignored
+        assert_eq!(feed(0, 95.0), Normal);
+        assert_eq!(feed(10, 95.0), Normal);
+        assert_eq!(feed(20, 70.0), Normal);
+
+        // timer was reset by the value inside the range
+        assert_eq!(feed(30, 95.0), Normal);
+        assert_eq!(feed(59, 95.0), Normal);
+        assert_eq!(feed(60, 95.0), High);
+
+        // clearing follows hysteresis without delay
+        assert_eq!(feed(61, 82.0), High);
+        assert_eq!(feed(62, 79.0), Normal);
+
+        // needs to be sustained again
+        assert_eq!(feed(63, 90.0), Normal);
+        assert_eq!(feed(93, 90.0), High);
+    }
+
+    #[test]
+    fn test_fan_zero() {
+        use AlarmState::{Low, Normal};
+
+        let fan = Sensor { kind: Some(SensorKind::Fan), ..sensor(Some(300.0), None) };
+        let now = Instant::now();
+        let mut tracker = AlarmTracker::default();
+
+        // single 0 while the duty changes is skipped, a stopped fan is not
+        assert_eq!(tracker.update(&fan, 1200.0, now), Normal);
+        assert_eq!(tracker.update(&fan, 0.0, now), Normal);
+        assert_eq!(tracker.update(&fan, 1150.0, now), Normal);
+        assert_eq!(tracker.update(&fan, 0.0, now), Normal);
+        assert_eq!(tracker.update(&fan, 0.0, now), Low);
+        assert_eq!(tracker.update(&fan, 0.0, now), Low);
+
+        // low readings other than 0 count right away
+        let mut tracker = AlarmTracker::default();
+        assert_eq!(tracker.update(&fan, 200.0, now), Low);
+
+        let strict = Sensor { strict: true, ..fan };
+        let mut tracker = AlarmTracker::default();
+        assert_eq!(tracker.update(&strict, 0.0, now), Low);
+    }
+
+    #[test]
+    fn test_alarm_repeat() {
+        use std::time::Duration;
+
+        let mut sensor = Sensor {
+            alarm_repeat: Some(AlarmRepeat::Every(Duration::from_secs(600))),
+            ..sensor(None, Some(85.0))
+        };
+
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut tracker = AlarmTracker::default();
+
+        tracker.update(&sensor, 50.0, at(0));
+        assert!(!tracker.repeat_due(&sensor, at(1000)));
+
+        // the transition itself is not a repeat
+        tracker.update(&sensor, 90.0, at(10));
+        assert!(!tracker.repeat_due(&sensor, at(10)));
+        assert!(!tracker.repeat_due(&sensor, at(609)));
+        assert!(tracker.repeat_due(&sensor, at(610)));
+        assert!(!tracker.repeat_due(&sensor, at(611)));
+        assert!(tracker.repeat_due(&sensor, at(1210)));
+
+        // cleared alarm is not repeated
+        tracker.update(&sensor, 50.0, at(1300));
+        assert!(!tracker.repeat_due(&sensor, at(5000)));
+
+        sensor.alarm_repeat = Some(AlarmRepeat::Never);
+        tracker.update(&sensor, 90.0, at(6000));
+        assert!(!tracker.repeat_due(&sensor, at(100_000)));
+
+        assert_eq!(AlarmRepeat::try_from("never".to_string()).unwrap(), AlarmRepeat::Never);
+        assert_eq!(AlarmRepeat::try_from("10m".to_string()).unwrap(), AlarmRepeat::Every(Duration::from_secs(600)));
+        assert!(AlarmRepeat::try_from("sometimes".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_severity() {
+        use AlarmState::{High, Low, Normal, WarnHigh, WarnLow};
+
+        assert!(Severity::Normal < Severity::Warning && Severity::Warning < Severity::Critical);
+
+        let sensor = Sensor {
+            name: "cpu".to_string(),
+            warn_high: Some(80.0),
+            warn_low: Some(20.0),
+            ..sensor(Some(10.0), Some(95.0))
+        };
+
+        assert_eq!(
+            states(&sensor, &[50.0, 85.0, 96.0, 90.0, 70.0, 15.0, 5.0, 15.0, 50.0]),
+            [Normal, WarnHigh, High, WarnHigh, Normal, WarnLow, Low, WarnLow, Normal],
+        );
+
+        assert_eq!(WarnHigh.severity(), Severity::Warning);
+        assert_eq!(Low.severity(), Severity::Critical);
+
+        // hysteresis holds each level separately, escalation is immediate
+        let sensor = Sensor { alarm_hysteresis: Some(5.0), ..sensor };
+        assert_eq!(
+            states(&sensor, &[85.0, 78.0, 96.0, 91.0, 89.0, 76.0, 74.0]),
+            [WarnHigh, WarnHigh, High, High, WarnHigh, WarnHigh, Normal],
+        );
+
+        let alarm = alarm_message(&sensor, WarnHigh, 85.0).unwrap();
+        assert_eq!(alarm.severity, Severity::Warning);
+        assert_eq!(alarm.to_string(), "WARNING: cpu: 85 is above the warning threshold 80");
+        assert_eq!(alarm_message(&sensor, High, 99.0).unwrap().to_string(), "ALARM: cpu: 99 is above the threshold 95");
+        assert!(alarm_message(&sensor, Normal, 50.0).is_none());
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        use AlarmState::{High, Low, Normal};
+
+        let hot = Sensor {
+            alarm_hysteresis: Some(5.0),
+            ..sensor(Some(10.0), Some(85.0))
+        };
+
+        // oscillating around the threshold does not clear the alarm
+        assert_eq!(
+            states(&hot, &[80.0, 86.0, 84.0, 86.0, 80.5, 80.0, 84.0, 86.0]),
+            [Normal, High, High, High, High, Normal, Normal, High],
+        );
+
+        assert_eq!(
+            states(&hot, &[12.0, 9.0, 11.0, 9.5, 14.5, 15.0, 11.0, 9.0]),
+            [Normal, Low, Low, Low, Low, Normal, Normal, Low],
+        );
+
+        // jumping straight to the other threshold
+        assert_eq!(states(&hot, &[90.0, 5.0, 90.0]), [High, Low, High]);
+
+        // without hysteresis it clears immediately
+        let plain = sensor(Some(10.0), Some(85.0));
+        assert_eq!(states(&plain, &[86.0, 84.0, 86.0, 85.0]), [High, Normal, High, Normal]);
+    }
+}
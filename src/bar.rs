@@ -0,0 +1,38 @@
+//! Bars made of characters showing where the value is in its range
+
+use crate::config::Bar;
+
+/// Render the bar for the value between `min` and `max`, values outside of the range are clamped
+pub fn render(bar: &Bar, min: f64, max: f64, value: f64) -> String {
+    let fraction = if max > min { (value - min) / (max - min) } else { 0.0 };
+
+    // NaN is clamped to NaN, so it has to be checked separately
+    let fraction = if fraction.is_nan() { 0.0 } else { fraction.clamp(0.0, 1.0) };
+    let filled = ((fraction * bar.width as f64).round() as usize).min(bar.width);
+
+    bar.filled.repeat(filled) + &bar.empty.repeat(bar.width - filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let bar = Bar { width: 10, filled: "▓".into(), empty: "░".into() };
+
+        assert_eq!(render(&bar, 0.0, 100.0, 62.0), "▓▓▓▓▓▓░░░░");
+        assert_eq!(render(&bar, 20.0, 120.0, 20.0), "░░░░░░░░░░");
+        assert_eq!(render(&bar, 0.0, 100.0, 100.0), "▓▓▓▓▓▓▓▓▓▓");
+
+        // out of range and broken ranges do not panic
+        assert_eq!(render(&bar, 0.0, 100.0, 150.0), "▓▓▓▓▓▓▓▓▓▓");
+        assert_eq!(render(&bar, 0.0, 100.0, -20.0), "░░░░░░░░░░");
+        assert_eq!(render(&bar, 100.0, 0.0, 50.0), "░░░░░░░░░░");
+        assert_eq!(render(&bar, 0.0, 100.0, f64::NAN), "░░░░░░░░░░");
+
+        let ascii = Bar { width: 4, filled: "#".into(), empty: "-".into() };
+        assert_eq!(render(&ascii, 0.0, 1.0, 0.5), "##--");
+        assert_eq!(render(&Bar { width: 0, ..ascii }, 0.0, 1.0, 0.5), "");
+    }
+}
@@ -1,5 +1,5 @@
 use std::path::PathBuf;
-use clap::{Parser};
+use clap::{Parser, Subcommand, ValueEnum};
 
 const HELP_DAEMON: &str = "Daemon Related";
 
@@ -9,7 +9,9 @@ const HELP_DAEMON: &str = "Daemon Related";
 pub struct Cli {
     /// Load config from file instead of default paths
     ///
-    /// By default looks for config in this order
+    /// Format is chosen by the extension, `.toml`, `.yaml`, `.yml` or `.json`, `-` reads toml from stdin
+    ///
+    /// By default looks for config in this order, trying each extension
     ///   ~/.config/kelvin/<hostname>.toml
     ///   ~/.config/kelvin/default.toml
     ///   /etc/kelvin/<hostname>.toml
@@ -17,6 +19,16 @@ pub struct Cli {
     #[clap(short, long, verbatim_doc_comment)]
     pub config: Option<PathBuf>,
 
+    /// Read sensors data from json file instead of running `sensors`, `-` reads it once from stdin
+    ///
+    /// Takes output of `sensors -j` to reproduce problems of another machine, the file is read again every tick
+    #[clap(long, global = true, value_name = "PATH")]
+    pub sensors_json: Option<PathBuf>,
+
+    /// Merge the host config over the default one, like `merge = true` in the host config
+    #[clap(long, global = true)]
+    pub merge_config: bool,
+
     /// Do not use custom format
     ///
     /// Meant for use when writing sensor configuration
@@ -26,10 +38,36 @@ pub struct Cli {
     /// Run in the background, alarm is always enabled in this mode
     ///
     /// Uses systemd if available to show logs in systemctl, if there is a
-    /// process running it will be restarted
+    /// process running it will be restarted when using `--replace`
     #[clap(short, long, help_heading = HELP_DAEMON)]
     pub daemon: bool,
 
+    /// Stop already running daemon and take its place
+    #[clap(long, requires = "daemon", help_heading = HELP_DAEMON)]
+    pub replace: bool,
+
+    /// How often to check the temperature like `2s`, overrides `poll_rate` in config
+    #[clap(long, value_name = "DURATION", value_parser = crate::duration::parse)]
+    pub interval: Option<std::time::Duration>,
+
+    /// Override a top-level setting of the config, like `--set format='{cpu}'`, can be repeated
+    #[clap(long, value_name = "KEY=VALUE")]
+    pub set: Vec<String>,
+
+    /// Only read and show this sensor, can be repeated
+    ///
+    /// Inputs of selected virtual sensors are read as well, the format is not used
+    #[clap(long = "sensor", value_name = "NAME")]
+    pub sensor: Vec<String>,
+
+    /// Order of sensors in the output, overrides `sort` in config
+    #[clap(long, value_enum)]
+    pub sort: Option<crate::config::SortOrder>,
+
+    /// Timestamp on every line of the output, overrides `timestamp` in config
+    #[clap(long, value_enum, value_name = "FORMAT")]
+    pub timestamp: Option<crate::config::TimestampFormat>,
+
     /// Enable alarm
     ///
     /// Note that if you have a daemon process running this will won't do
@@ -41,9 +79,259 @@ pub struct Cli {
     #[clap(long, help_heading = HELP_DAEMON)]
     pub kill: bool,
 
+    /// How long to wait for the daemon to exit after killing it (in seconds)
+    #[clap(long, default_value_t = 5, value_name = "SECONDS", help_heading = HELP_DAEMON)]
+    pub kill_timeout: u64,
+
+    /// Compute values of outputs and log them without writing, like `dry_run = true` on every output
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Append inputs of every tick to a new `.jsonl` file in the directory, to be replayed with `--replay`
+    ///
+    /// Keeps lm_sensors data and the text read for sensors of other sources
+    #[clap(long, value_name = "DIR", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Read sensors from a file made by `--record` instead of live sources, quits after the last tick
+    ///
+    /// Alarms and rates use the recorded time so results are the same on every run, fans are never written
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["daemon", "sensors_json"])]
+    pub replay: Option<PathBuf>,
+
+    /// How much faster than recorded to replay, like `10x`, `max` does not wait between ticks
+    #[clap(long, value_name = "SPEED", default_value = "1x", value_parser = crate::replay::parse_speed, requires = "replay")]
+    pub speed: f64,
+
+    /// Put the PWM channel back to automatic mode, like `/sys/class/hwmon/hwmon2/pwm1`
+    ///
+    /// For fans left in manual mode by a run that did not exit cleanly
+    #[clap(long, value_name = "PWM_PATH")]
+    pub restore: Option<PathBuf>,
+
+    /// Reload the config when the file changes, same as sending SIGHUP
+    #[clap(long)]
+    pub watch_config: bool,
+
+    /// Show more diagnostics on stderr, can be repeated
+    #[clap(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Only show errors on stderr
+    #[clap(short, long, conflicts_with = "verbose", global = true)]
+    pub quiet: bool,
+
     /// Print the output once and quit
     #[clap(long)]
     pub once: bool,
+
+    /// Exit with status of the sensors, for scripts
+    ///
+    /// 0 when all sensors are normal, 1 when any warning threshold is exceeded,
+    /// 2 when any alarm threshold is exceeded and 3 when a required sensor could
+    /// not be read, which takes precedence as the result is incomplete
+    #[clap(long, requires = "once", verbatim_doc_comment)]
+    pub check: bool,
+
+    /// Output format, in loop mode each tick is printed on a single line
+    #[clap(long, value_enum, default_value_t)]
+    pub output: OutputFormat,
+
+    /// Write the output to a file instead of stdout, replacing it atomically each tick
+    ///
+    /// Meant for node_exporter textfile collector with `--output prometheus`
+    #[clap(long, value_name = "PATH")]
+    pub textfile: Option<PathBuf>,
+
+    /// Append readings of each tick to a CSV file, overrides `log_csv` in config
+    #[clap(long, value_name = "PATH")]
+    pub log_csv: Option<PathBuf>,
+
+    /// Color values by their thresholds when writing to a terminal, auto mode respects `NO_COLOR`
+    #[clap(long, value_enum, default_value_t, global = true)]
+    pub color: ColorMode,
+
+    /// Output readings of all sensors as json, same as `--output json`
+    #[clap(long, conflicts_with = "output")]
+    pub json: bool,
+
+    /// Add every step of reading each sensor to json output, from the path and text read to the thresholds
+    #[clap(long)]
+    pub verbose_json: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Manage the configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+
+    /// List all available sensor paths with their current values
+    List {
+        /// Only show paths containing the text
+        #[clap(long)]
+        filter: Option<String>,
+
+        /// Also list sysfs hwmon files
+        #[clap(long)]
+        hwmon: bool,
+
+        /// Also list thermal zones by their type
+        #[clap(long)]
+        thermal: bool,
+
+        /// Only print names of the sensors in the config, one per line
+        #[clap(long, conflicts_with_all = ["filter", "hwmon", "thermal"])]
+        names: bool,
+    },
+
+    /// Show latest readings of the running daemon
+    Status {
+        /// Output readings as json
+        #[clap(long)]
+        json: bool,
+
+        /// Reset min, max and average of all sensors in the daemon
+        #[clap(long, conflicts_with = "json")]
+        reset_stats: bool,
+
+        /// Ask the daemon over D-Bus instead of its socket, uses the bus of `[dbus]` in config
+        #[clap(long, conflicts_with = "reset_stats")]
+        dbus: bool,
+    },
+
+    /// Send the alarm webhook of a sensor as if its alarm fired, to check it is delivered
+    TestAlarm {
+        /// Name of the sensor
+        sensor: String,
+    },
+
+    /// Show readings of a sensor recorded by the daemon, requires `history` in config
+    History {
+        /// Name of the sensor
+        sensor: String,
+
+        /// How far back to show, like `30m` or `2d`
+        #[clap(long, default_value = "1h", value_parser = crate::duration::parse)]
+        since: std::time::Duration,
+
+        /// Output readings as json
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Print shell completion script to stdout
+    ///
+    /// For example `kelvin completions bash > ~/.local/share/bash-completion/completions/kelvin`
+    Completions {
+        #[clap(value_enum)]
+        shell: crate::completions::Shell,
+    },
+
+    /// Print man page in roff format to stdout, for packaging
+    #[command(hide = true)]
+    Man,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Generate starter config from sensors detected on this machine
+    Init {
+        /// Overwrite the config if it already exists
+        #[clap(long)]
+        force: bool,
+
+        /// Format of the config, defaults to the extension of the path
+        #[clap(long, value_enum)]
+        format: Option<crate::config::ConfigFormat>,
+
+        /// Write config to this path instead of ~/.config/kelvin/<hostname>.toml
+        path: Option<PathBuf>,
+    },
+
+    /// Check config for mistakes, exits with non-zero code if there are any errors
+    Validate {
+        /// Do not check sensor paths against live lm_sensors output
+        #[clap(long)]
+        offline: bool,
+
+        /// Config to validate instead of the default one
+        path: Option<PathBuf>,
+    },
+
+    /// Print the config that would be used
+    Show {
+        /// Print the config after includes, merging and defaults are applied instead of the file
+        #[clap(long)]
+        resolved: bool,
+
+        /// Config to show instead of the default one
+        path: Option<PathBuf>,
+    },
+
+    /// Convert fancontrol config from lm_sensors to kelvin config printed to stdout
+    ///
+    /// Directives that cannot be converted are listed as warnings in comments of the output
+    ImportFancontrol {
+        /// Path of the fancontrol config
+        #[clap(default_value = crate::fancontrol::DEFAULT_PATH)]
+        path: PathBuf,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Only when writing to a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable output using the format
+    #[default]
+    Text,
+
+    /// Format on a single line each tick without clearing the screen, for polybar and i3blocks
+    ///
+    /// Send SIGUSR1 to refresh right away
+    Line,
+
+    /// All sensors in an aligned table
+    Table,
+
+    /// Readings of all sensors as json
+    Json,
+
+    /// Waybar custom module json protocol
+    Waybar,
+
+    /// Prometheus text exposition format
+    Prometheus,
+
+    /// Nagios plugin output, with `--once` exits with the plugin status code
+    Nagios,
+
+    /// InfluxDB line protocol, for telegraf's execd input
+    Influx,
+
+    /// Graphite plaintext protocol, prefixed like the `[graphite]` config
+    Graphite,
+}
+
+impl Cli {
+    pub fn output_format(&self) -> OutputFormat {
+        if self.json {
+            OutputFormat::Json
+        } else {
+            self.output
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,134 @@
+//! ANSI colors for terminal output
+
+use crate::alarm::Severity;
+use crate::cli::ColorMode;
+use crate::config::{Colors, Sensor};
+
+/// Fraction of the `min` to `max` range above which values are colored as warning
+const WARNING_POSITION: f64 = 0.6;
+
+/// Fraction of the `min` to `max` range above which values are colored as critical
+const CRITICAL_POSITION: f64 = 0.85;
+
+/// Whether to color output going to a terminal, `NO_COLOR` only matters in auto mode
+pub fn enabled(mode: ColorMode, is_terminal: bool) -> bool {
+    match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        // https://no-color.org
+        ColorMode::Auto => is_terminal && std::env::var_os("NO_COLOR").is_none_or(|x| x.is_empty()),
+    }
+}
+
+pub fn paint(text: &str, code: &str) -> String {
+    format!("\x1b[{code}m{text}\x1b[0m")
+}
+
+/// Text without the color codes of `paint`
+fn strip(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("\x1b[") {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        match rest.find('m') {
+            Some(end) => rest = &rest[end + 1..],
+            None => break,
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Mark value kept from an earlier reading with an asterisk, also dimmed when colored
+///
+/// Color of the value is dropped, its reset would end the dimming
+pub fn stale(text: &str, colored: bool) -> String {
+    match colored {
+        true => paint(&format!("{}*", strip(text)), "2"),
+        false => format!("{text}*"),
+    }
+}
+
+impl Colors {
+    pub fn severity(&self, severity: Severity) -> &str {
+        match severity {
+            Severity::Normal => &self.normal,
+            Severity::Warning => &self.warning,
+            Severity::Critical => &self.critical,
+        }
+    }
+}
+
+/// Color of the value by its alarm state, or by its position between `min` and `max` when there are no thresholds
+///
+/// Sensors with neither are not colored
+pub fn value_color<'a>(colors: &'a Colors, sensor: &Sensor, value: f64, severity: Severity) -> Option<&'a str> {
+    let thresholds = [sensor.alarm_high, sensor.alarm_low, sensor.warn_high, sensor.warn_low];
+    if thresholds.iter().any(Option::is_some) {
+        return Some(colors.severity(severity));
+    }
+
+    let (Some(min), Some(max)) = (sensor.min, sensor.max) else {
+        return None;
+    };
+
+    if max <= min {
+        return None;
+    }
+
+    let position = (value - min) / (max - min);
+    let severity = if position >= CRITICAL_POSITION {
+        Severity::Critical
+    } else if position >= WARNING_POSITION {
+        Severity::Warning
+    } else {
+        Severity::Normal
+    };
+
+    Some(colors.severity(severity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_color() {
+        let colors = Colors { warning: "38;5;214".into(), ..Default::default() };
+
+        let sensor = Sensor { name: "cpu".into(), ..Default::default() };
+        assert_eq!(value_color(&colors, &sensor, 50.0, Severity::Normal), None);
+
+        // thresholds win over the range
+        let thresholds = Sensor { alarm_high: Some(90.0), min: Some(0.0), max: Some(100.0), ..sensor.clone() };
+        assert_eq!(value_color(&colors, &thresholds, 95.0, Severity::Normal), Some("32"));
+        assert_eq!(value_color(&colors, &thresholds, 95.0, Severity::Critical), Some("31"));
+
+        let range = Sensor { min: Some(20.0), max: Some(120.0), ..sensor };
+        assert_eq!(value_color(&colors, &range, 30.0, Severity::Normal), Some("32"));
+        assert_eq!(value_color(&colors, &range, 85.0, Severity::Normal), Some("38;5;214"));
+        assert_eq!(value_color(&colors, &range, 200.0, Severity::Normal), Some("31"));
+        assert_eq!(value_color(&colors, &range, -10.0, Severity::Normal), Some("32"));
+    }
+
+    #[test]
+    fn test_stale() {
+        let colored = paint("45°C", "31");
+        assert_eq!(strip(&colored), "45°C");
+        assert_eq!(strip("no codes"), "no codes");
+
+        assert_eq!(stale(&colored, true), "\x1b[2m45°C*\x1b[0m");
+        assert_eq!(stale("45°C", false), "45°C*");
+    }
+
+    #[test]
+    fn test_enabled() {
+        assert!(enabled(ColorMode::Always, false));
+        assert!(!enabled(ColorMode::Never, true));
+        assert!(!enabled(ColorMode::Auto, false));
+    }
+}
@@ -0,0 +1,415 @@
+//! Shell completion scripts generated from the command line definition
+//!
+//! Sensor names are completed by calling `kelvin list --names`, so they follow the config
+
+use clap::{Arg, ArgAction, Command, ValueHint};
+use std::fmt::Write;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// What can be completed as the value of an argument
+#[derive(Debug, Clone, PartialEq)]
+enum Values {
+    Sensors,
+    Paths,
+    Choices(Vec<String>),
+    Any,
+}
+
+impl Values {
+    /// Returns None for flags
+    fn of(arg: &Arg) -> Option<Self> {
+        if !arg.get_action().takes_values() {
+            return None;
+        }
+
+        if arg.get_id() == "sensor" {
+            return Some(Self::Sensors);
+        }
+
+        let choices = arg.get_possible_values().iter()
+            .filter(|x| !x.is_hide_set())
+            .map(|x| x.get_name().to_string())
+            .collect::<Vec<_>>();
+
+        Some(if !choices.is_empty() {
+            Self::Choices(choices)
+        } else if matches!(arg.get_value_hint(), ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath) {
+            Self::Paths
+        } else {
+            Self::Any
+        })
+    }
+}
+
+/// First line of the help, descriptions in completion menus are a single line
+fn about(cmd_or_arg: Option<&clap::builder::StyledStr>) -> String {
+    cmd_or_arg.map(|x| x.to_string().lines().next().unwrap_or("").trim_end_matches('.').to_string())
+        .unwrap_or_default()
+}
+
+fn options(cmd: &Command) -> impl Iterator<Item = &Arg> {
+    cmd.get_arguments().filter(|x| !x.is_positional() && !x.is_hide_set())
+}
+
+fn positionals(cmd: &Command) -> impl Iterator<Item = &Arg> {
+    cmd.get_positionals().filter(|x| !x.is_hide_set())
+}
+
+fn subcommands(cmd: &Command) -> impl Iterator<Item = &Command> {
+    cmd.get_subcommands().filter(|x| !x.is_hide_set())
+}
+
+/// Every spelling of the option, like `-c` and `--config`
+fn flags(arg: &Arg) -> Vec<String> {
+    arg.get_short_and_visible_aliases().into_iter().flatten().map(|x| format!("-{x}"))
+        .chain(arg.get_long_and_visible_aliases().into_iter().flatten().map(|x| format!("--{x}")))
+        .collect()
+}
+
+/// All commands with the names leading to them, starting with the root
+fn walk<'a>(cmd: &'a Command, path: Vec<&'a str>, out: &mut Vec<(Vec<&'a str>, &'a Command)>) {
+    out.push((path.clone(), cmd));
+
+    for sub in subcommands(cmd) {
+        let mut path = path.clone();
+        path.push(sub.get_name());
+        walk(sub, path, out);
+    }
+}
+
+/// Generate the completion script, `cmd` should be built so global arguments are propagated
+pub fn generate(shell: Shell, cmd: &Command) -> String {
+    let mut commands = vec![];
+    walk(cmd, vec![cmd.get_name()], &mut commands);
+
+    match shell {
+        Shell::Bash => bash(&commands),
+        Shell::Zsh => zsh(&commands),
+        Shell::Fish => fish(&commands),
+    }
+}
+
+fn bash(commands: &[(Vec<&str>, &Command)]) -> String {
+    let bin = commands[0].0[0];
+    let state = |path: &[&str]| path.join("__").replace('-', "_");
+
+    let mut out = String::new();
+    writeln!(out, "_{bin}_sensors() {{").unwrap();
+    writeln!(out, "    local i args=()").unwrap();
+    writeln!(out, "    for ((i = 1; i < COMP_CWORD; i++)); do").unwrap();
+    writeln!(out, "        case \"${{COMP_WORDS[i]}}\" in").unwrap();
+    writeln!(out, "            -c|--config) args=(--config \"${{COMP_WORDS[i+1]}}\") ;;").unwrap();
+    writeln!(out, "        esac").unwrap();
+    writeln!(out, "    done").unwrap();
+    writeln!(out, "    {bin} \"${{args[@]}}\" list --names 2>/dev/null").unwrap();
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "_{bin}() {{").unwrap();
+    writeln!(out, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"").unwrap();
+    writeln!(out, "    local cmd={} i opts", state(&commands[0].0)).unwrap();
+    writeln!(out, "    COMPREPLY=()\n").unwrap();
+
+    writeln!(out, "    for ((i = 1; i < COMP_CWORD; i++)); do").unwrap();
+    writeln!(out, "        case \"$cmd:${{COMP_WORDS[i]}}\" in").unwrap();
+    for (path, cmd) in commands {
+        for sub in subcommands(cmd) {
+            writeln!(out, "            {}:{}) cmd={}__{} ;;", state(path), sub.get_name(), state(path), sub.get_name().replace('-', "_")).unwrap();
+        }
+    }
+    writeln!(out, "        esac").unwrap();
+    writeln!(out, "    done\n").unwrap();
+
+    let reply = |values: &Values| match values {
+        Values::Sensors => format!("COMPREPLY=($(compgen -W \"$(_{bin}_sensors)\" -- \"$cur\"))"),
+        Values::Paths => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+        Values::Choices(x) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", x.join(" ")),
+        Values::Any => "COMPREPLY=()".to_string(),
+    };
+
+    // values of options
+    writeln!(out, "    case \"$cmd:$prev\" in").unwrap();
+    for (path, cmd) in commands {
+        for arg in options(cmd) {
+            if let Some(values) = Values::of(arg) {
+                let cases = flags(arg).iter().map(|x| format!("{}:{x}", state(path))).collect::<Vec<_>>();
+                writeln!(out, "        {}) {}; return ;;", cases.join("|"), reply(&values)).unwrap();
+            }
+        }
+    }
+    writeln!(out, "    esac\n").unwrap();
+
+    writeln!(out, "    case \"$cmd\" in").unwrap();
+    for (path, cmd) in commands {
+        let words = options(cmd).flat_map(flags)
+            .chain(subcommands(cmd).map(|x| x.get_name().to_string()))
+            .collect::<Vec<_>>();
+
+        writeln!(out, "        {})", state(path)).unwrap();
+        writeln!(out, "            opts=\"{}\"", words.join(" ")).unwrap();
+
+        // only the first positional is completed, no command takes more than one
+        match positionals(cmd).next().and_then(Values::of) {
+            Some(values @ (Values::Sensors | Values::Paths)) => {
+                writeln!(out, "            if [[ \"$cur\" != -* ]]; then {}; return; fi", reply(&values)).unwrap();
+            },
+            Some(Values::Choices(x)) => {
+                writeln!(out, "            opts=\"$opts {}\"", x.join(" ")).unwrap();
+            },
+            _ => {},
+        }
+
+        writeln!(out, "            ;;").unwrap();
+    }
+    writeln!(out, "    esac\n").unwrap();
+
+    writeln!(out, "    COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))").unwrap();
+    writeln!(out, "}}\n").unwrap();
+    writeln!(out, "complete -o filenames -F _{bin} {bin}").unwrap();
+
+    out
+}
+
+/// Quote for single quoted zsh strings, brackets are escaped as they end the description
+fn zsh_quote(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "'\\''").replace('[', "\\[").replace(']', "\\]")
+}
+
+fn zsh(commands: &[(Vec<&str>, &Command)]) -> String {
+    let bin = commands[0].0[0];
+    let function = |path: &[&str]| format!("_{}", path.join("_").replace('-', "_"));
+
+    let action = |values: &Values| match values {
+        Values::Sensors => format!("_{bin}_sensors"),
+        Values::Paths => "_files".to_string(),
+        Values::Choices(x) => format!("({})", x.join(" ")),
+        Values::Any => " ".to_string(),
+    };
+
+    let mut out = String::new();
+    writeln!(out, "#compdef {bin}\n").unwrap();
+
+    writeln!(out, "_{bin}_sensors() {{").unwrap();
+    writeln!(out, "    local -a words_ names args").unwrap();
+    writeln!(out, "    local i").unwrap();
+    writeln!(out, "    words_=(${{(z)BUFFER}})").unwrap();
+    writeln!(out, "    for ((i = 1; i < ${{#words_}}; i++)); do").unwrap();
+    writeln!(out, "        case \"$words_[i]\" in").unwrap();
+    writeln!(out, "            -c|--config) args=(--config \"${{(Q)words_[i+1]}}\") ;;").unwrap();
+    writeln!(out, "        esac").unwrap();
+    writeln!(out, "    done").unwrap();
+    writeln!(out, "    names=(${{(f)\"$({bin} \"${{args[@]}}\" list --names 2>/dev/null)\"}})").unwrap();
+    writeln!(out, "    _describe 'sensor' names").unwrap();
+    writeln!(out, "}}").unwrap();
+
+    for (path, cmd) in commands {
+        writeln!(out, "\n{}() {{", function(path)).unwrap();
+        writeln!(out, "    local context state state_descr line").unwrap();
+        writeln!(out, "    typeset -A opt_args\n").unwrap();
+        writeln!(out, "    _arguments -s -C \\").unwrap();
+
+        for arg in options(cmd) {
+            let flags = flags(arg);
+            let repeat = matches!(arg.get_action(), ArgAction::Append | ArgAction::Count);
+
+            // repeated options do not exclude their other spelling
+            let names = match flags.as_slice() {
+                [x] if repeat => format!("'*{x}"),
+                [x] => format!("'{x}"),
+                many if repeat => format!("'*'{{{}}}'", many.join(",")),
+                many => format!("'({})'{{{}}}'", many.join(" "), many.join(",")),
+            };
+
+            let value = match Values::of(arg) {
+                Some(values) => {
+                    let name = arg.get_value_names().and_then(|x| x.first()).map(|x| x.to_string())
+                        .unwrap_or_else(|| arg.get_id().to_string());
+                    format!(":{}:{}", name.to_lowercase(), action(&values))
+                },
+                None => String::new(),
+            };
+
+            writeln!(out, "        {names}[{}]{}' \\", zsh_quote(&about(arg.get_help())), value.replace('\'', "'\\''")).unwrap();
+        }
+
+        for (i, arg) in positionals(cmd).enumerate() {
+            let optional = if arg.is_required_set() { "" } else { ":" };
+            let values = Values::of(arg).unwrap_or(Values::Any);
+            writeln!(out, "        '{}:{optional}{}:{}' \\", i + 1, arg.get_id(), action(&values)).unwrap();
+        }
+
+        if subcommands(cmd).next().is_some() {
+            writeln!(out, "        ': :->command' \\").unwrap();
+            writeln!(out, "        '*:: :->args'\n").unwrap();
+
+            writeln!(out, "    case $state in").unwrap();
+            writeln!(out, "        command)").unwrap();
+            writeln!(out, "            local -a commands").unwrap();
+            writeln!(out, "            commands=(").unwrap();
+            for sub in subcommands(cmd) {
+                writeln!(out, "                '{}:{}'", sub.get_name(), zsh_quote(&about(sub.get_about()))).unwrap();
+            }
+            writeln!(out, "            )").unwrap();
+            writeln!(out, "            _describe 'command' commands").unwrap();
+            writeln!(out, "            ;;").unwrap();
+            writeln!(out, "        args)").unwrap();
+            writeln!(out, "            case $line[1] in").unwrap();
+            for sub in subcommands(cmd) {
+                let mut sub_path = path.clone();
+                sub_path.push(sub.get_name());
+                writeln!(out, "                {}) {} ;;", sub.get_name(), function(&sub_path)).unwrap();
+            }
+            writeln!(out, "            esac").unwrap();
+            writeln!(out, "            ;;").unwrap();
+            writeln!(out, "    esac").unwrap();
+        } else {
+            // ends the line continuation of the last spec
+            writeln!(out, "        && return 0").unwrap();
+        }
+
+        writeln!(out, "}}").unwrap();
+    }
+
+    writeln!(out, "\nif [ \"$funcstack[1]\" = \"_{bin}\" ]; then").unwrap();
+    writeln!(out, "    _{bin} \"$@\"").unwrap();
+    writeln!(out, "else").unwrap();
+    writeln!(out, "    compdef _{bin} {bin}").unwrap();
+    writeln!(out, "fi").unwrap();
+
+    out
+}
+
+/// Quote for single quoted fish strings
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish(commands: &[(Vec<&str>, &Command)]) -> String {
+    let bin = commands[0].0[0];
+
+    // seen every subcommand on the path, or none at all for the root
+    let condition = |path: &[&str]| match path {
+        [_] => "__fish_use_subcommand".to_string(),
+        [_, rest @ ..] => rest.iter().map(|x| format!("__fish_seen_subcommand_from {x}")).collect::<Vec<_>>().join("; and "),
+        [] => unreachable!(),
+    };
+
+    let values = |values: &Values| match values {
+        Values::Sensors => format!(" -x -a '(__{bin}_sensors)'"),
+        Values::Paths => " -r -F".to_string(),
+        Values::Choices(x) => format!(" -x -a {}", fish_quote(&x.join(" "))),
+        Values::Any => " -x".to_string(),
+    };
+
+    let mut out = String::new();
+    writeln!(out, "function __{bin}_sensors").unwrap();
+    writeln!(out, "    set -l words (commandline -opc)").unwrap();
+    writeln!(out, "    set -l args").unwrap();
+    writeln!(out, "    for i in (seq (count $words))").unwrap();
+    writeln!(out, "        if contains -- $words[$i] -c --config; and test $i -lt (count $words)").unwrap();
+    writeln!(out, "            set args --config $words[(math $i + 1)]").unwrap();
+    writeln!(out, "        end").unwrap();
+    writeln!(out, "    end").unwrap();
+    writeln!(out, "    {bin} $args list --names 2>/dev/null").unwrap();
+    writeln!(out, "end\n").unwrap();
+
+    writeln!(out, "complete -c {bin} -f").unwrap();
+
+    for (path, cmd) in commands {
+        let condition = condition(path);
+
+        let subs = subcommands(cmd).collect::<Vec<_>>();
+        if !subs.is_empty() {
+            // subcommands are offered until one of them is used
+            let names = subs.iter().map(|x| x.get_name()).collect::<Vec<_>>().join(" ");
+            let condition = match path.len() {
+                1 => condition.clone(),
+                _ => format!("{condition}; and not __fish_seen_subcommand_from {names}"),
+            };
+
+            for sub in subs {
+                writeln!(out, "complete -c {bin} -n {} -a {} -d {}", fish_quote(&condition), sub.get_name(), fish_quote(&about(sub.get_about()))).unwrap();
+            }
+        }
+
+        for arg in options(cmd) {
+            let mut line = format!("complete -c {bin} -n {}", fish_quote(&condition));
+
+            if let Some(x) = arg.get_short() {
+                write!(line, " -s {x}").unwrap();
+            }
+
+            if let Some(x) = arg.get_long() {
+                write!(line, " -l {x}").unwrap();
+            }
+
+            if let Some(x) = Values::of(arg) {
+                line.push_str(&values(&x));
+            }
+
+            writeln!(out, "{line} -d {}", fish_quote(&about(arg.get_help()))).unwrap();
+        }
+
+        let positional = match positionals(cmd).next().and_then(Values::of) {
+            Some(Values::Sensors) => format!(" -f -a '(__{bin}_sensors)'"),
+            Some(Values::Paths) => " -F".to_string(),
+            Some(Values::Choices(x)) => format!(" -f -a {}", fish_quote(&x.join(" "))),
+            Some(Values::Any) | None => continue,
+        };
+
+        writeln!(out, "complete -c {bin} -n {}{positional}", fish_quote(&condition)).unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn script(shell: Shell) -> String {
+        let mut cmd = crate::cli::Cli::command();
+        cmd.build();
+        generate(shell, &cmd)
+    }
+
+    #[test]
+    fn test_bash() {
+        let script = script(Shell::Bash);
+        assert!(script.contains("kelvin:config) cmd=kelvin__config ;;"), "{script}");
+        assert!(script.contains("kelvin__config:init) cmd=kelvin__config__init ;;"), "{script}");
+        assert!(script.contains("kelvin:--sensor) COMPREPLY=($(compgen -W \"$(_kelvin_sensors)\" -- \"$cur\")); return ;;"), "{script}");
+        assert!(script.contains("kelvin:-c|kelvin:--config) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;"), "{script}");
+        assert!(script.contains("kelvin:--sort) COMPREPLY=($(compgen -W \"config name value value_desc\" -- \"$cur\")); return ;;"), "{script}");
+        assert!(script.contains("complete -o filenames -F _kelvin kelvin"));
+
+        // sensor name of history is completed too
+        let history = script.split("        kelvin__history)").nth(1).unwrap();
+        assert!(history.lines().nth(2).unwrap().contains("_kelvin_sensors"), "{history}");
+    }
+
+    #[test]
+    fn test_zsh() {
+        let script = script(Shell::Zsh);
+        assert!(script.starts_with("#compdef kelvin\n"));
+        assert!(script.contains("'*--sensor[Only read and show this sensor, can be repeated]:name:_kelvin_sensors' \\"), "{script}");
+        assert!(script.contains("'(-c --config)'{-c,--config}'[Load config from file instead of default paths]:config:_files' \\"), "{script}");
+        assert!(script.contains("                init) _kelvin_config_init ;;"), "{script}");
+        assert!(script.contains("'--since[How far back to show, like `30m` or `2d`]:since: ' \\"), "{script}");
+    }
+
+    #[test]
+    fn test_fish() {
+        let script = script(Shell::Fish);
+        assert!(script.contains("complete -c kelvin -n '__fish_use_subcommand' -a config -d 'Manage the configuration'"), "{script}");
+        assert!(script.contains("complete -c kelvin -n '__fish_seen_subcommand_from config; and not __fish_seen_subcommand_from init validate show import-fancontrol help' -a init"), "{script}");
+        assert!(script.contains("complete -c kelvin -n '__fish_use_subcommand' -l sensor -x -a '(__kelvin_sensors)'"), "{script}");
+        assert!(script.contains("complete -c kelvin -n '__fish_seen_subcommand_from history' -f -a '(__kelvin_sensors)'"), "{script}");
+    }
+}
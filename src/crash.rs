@@ -0,0 +1,92 @@
+//! Crash reports, so a daemon dying in the background does not go unnoticed
+
+use crate::prelude::*;
+use crate::notify;
+use std::path::PathBuf;
+
+const ISSUES_URL: &str = "https://github.com/sandorex/kelvin/issues";
+
+/// Write crash report to the state directory, returns its path
+pub fn write_report(message: &str) -> Result<PathBuf> {
+    let dir = crate::daemon::state_dir();
+    std::fs::create_dir_all(&dir)
+        .with_context(|| anyhow!("Unable to create state directory {dir:?}"))?;
+
+    let path = dir.join(format!("crash-{}.log", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+    let report = format!(
+        "kelvin {} crashed at {}\n\n{message}\n",
+        env!("CARGO_PKG_VERSION"),
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+    );
+
+    std::fs::write(&path, report)
+        .with_context(|| anyhow!("Unable to write crash report to {path:?}"))?;
+
+    Ok(path)
+}
+
+/// Save the report and let the user know, in daemon mode with a notification as nobody is looking at the terminal
+pub fn report(message: &str, daemon: bool) {
+    let saved = match write_report(message) {
+        Ok(path) => format!("report saved to {path:?}"),
+        Err(err) => format!("report could not be saved: {err:#}"),
+    };
+
+    eprintln!("\nkelvin crashed, please report it at {ISSUES_URL}\n{saved}");
+
+    if daemon && let Err(err) = notify::send("Kelvin crashed", &saved, notify::Urgency::Critical) {
+        eprintln!("Unable to send notification: {err:#}");
+    }
+}
+
+/// Report panics with a backtrace, the process still exits with non-zero code afterwards
+pub fn install_panic_hook(daemon: bool) {
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        // before anything else that could fail, fans must not stay pinned
+        for err in crate::fan::restore() {
+            eprintln!("{err:#}");
+        }
+
+        let backtrace = std::backtrace::Backtrace::force_capture();
+        report(&format!("{info}\n\nBacktrace:\n{backtrace}"), daemon);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const ENV_PANIC: &str = "KELVIN_TEST_PANIC";
+
+    #[test]
+    fn test_panic_hook() {
+        if std::env::var_os(ENV_PANIC).is_some() {
+            install_panic_hook(false);
+            panic!("deliberate panic");
+        }
+
+        let state = TempDir::new("crash");
+
+        // panic in a child process so the hook does not affect other tests
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "crash::tests::test_panic_hook", "--nocapture"])
+            .env(ENV_PANIC, "1")
+            .env("XDG_STATE_HOME", &*state)
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("kelvin crashed"));
+
+        let reports = std::fs::read_dir(state.join("kelvin")).unwrap().collect::<Vec<_>>();
+        assert_eq!(reports.len(), 1);
+
+        let report = std::fs::read_to_string(reports[0].as_ref().unwrap().path()).unwrap();
+        assert!(report.contains("deliberate panic") && report.contains("Backtrace"), "{report}");
+    }
+}
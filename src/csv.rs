@@ -0,0 +1,158 @@
+//! Append readings to a CSV file, one row per tick
+
+use crate::prelude::*;
+use crate::Widgets;
+use crate::output::Timestamp;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct CsvLogger {
+    path: PathBuf,
+    file: File,
+
+    /// Names of sensors in the columns, widgets may be sorted differently on every tick
+    columns: Vec<String>,
+}
+
+/// Quote the field if it contains a separator, quote or newline
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn row<'a>(fields: impl IntoIterator<Item = &'a str>) -> String {
+    let fields = fields.into_iter().map(escape).collect::<Vec<_>>();
+    format!("{}\n", fields.join(","))
+}
+
+impl CsvLogger {
+    /// Open the file for appending, header is written if the file is empty
+    ///
+    /// Fails if the file has a different header, so columns never shift
+    pub fn open(path: &Path, widgets: &Widgets) -> Result<Self> {
+        let columns = widgets.iter().filter_map(|(_, x)| x.sensor()).map(|x| x.name.clone()).collect::<Vec<_>>();
+        let header = row(std::iter::once("timestamp").chain(columns.iter().map(String::as_str)));
+
+        if let Some(dir) = path.parent() && !dir.as_os_str().is_empty() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| anyhow!("Unable to create directory {dir:?}"))?;
+        }
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(path)
+            .with_context(|| anyhow!("Unable to open CSV log {path:?}"))?;
+
+        let mut existing = String::new();
+        BufReader::new(&file).read_line(&mut existing)
+            .with_context(|| anyhow!("Unable to read CSV log {path:?}"))?;
+
+        if existing.is_empty() {
+            file.write_all(header.as_bytes())
+                .with_context(|| anyhow!("Unable to write CSV log {path:?}"))?;
+        } else if existing != header {
+            bail!("CSV log {path:?} has different columns than the sensors, use another file");
+        }
+
+        Ok(Self { path: path.to_path_buf(), file, columns })
+    }
+
+    /// Append readings of the tick, failed sensors are left empty
+    ///
+    /// Configured timestamp replaces the time in ISO 8601
+    pub fn write(&mut self, time: chrono::DateTime<chrono::Local>, timestamp: Option<&Timestamp>, widgets: &Widgets) -> Result<()> {
+        let timestamp = match timestamp {
+            Some(x) => x.to_string(),
+            None => time.to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        };
+
+        let values = self.columns.iter()
+            .map(|name| {
+                widgets.iter()
+                    .find(|(_, x)| x.sensor().is_some_and(|x| x.name == *name))
+                    .and_then(|(_, x)| x.reading())
+                    .map(|x| x.formatted.as_str())
+                    .unwrap_or_default()
+            });
+
+        let line = row(std::iter::once(timestamp.as_str()).chain(values));
+
+        // single write per row, so a crash cannot leave half a line
+        self.file.write_all(line.as_bytes())
+            .with_context(|| anyhow!("Unable to write CSV log {:?}", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::config::Sensor;
+    use crate::output::Reading;
+
+    struct TestWidget(Sensor, Option<Reading>);
+
+    impl crate::Widget for TestWidget {
+        fn value(&mut self, _ctx: &crate::Context) -> Result<String> {
+            bail!("unreachable")
+        }
+
+        fn sensor(&self) -> Option<&Sensor> {
+            Some(&self.0)
+        }
+
+        fn reading(&self) -> Option<&Reading> {
+            self.1.as_ref()
+        }
+    }
+
+    fn widgets(gpu: Option<f64>) -> Widgets {
+        let widget = |name: &str, value: Option<f64>| -> (String, Box<dyn crate::Widget>) {
+            let sensor = Sensor { name: name.into(), round: Some(1), ..Default::default() };
+            let reading = value.map(|x| Reading::new(&sensor, x, x, Default::default()));
+            (format!("{{{name}}}"), Box::new(TestWidget(sensor, reading)))
+        };
+
+        vec![widget("cpu", Some(45.25)), widget("gpu, edge", gpu)]
+    }
+
+    #[test]
+    fn test_csv() {
+        let dir = TempDir::new("csv");
+        let path = dir.join("readings.csv");
+
+        let time = chrono::DateTime::parse_from_rfc3339("2024-05-01T12:00:00+02:00").unwrap().with_timezone(&chrono::Local);
+
+        let mut logger = CsvLogger::open(&path, &widgets(Some(60.0))).unwrap();
+        logger.write(time, None, &widgets(Some(60.0))).unwrap();
+        drop(logger);
+
+        // reopening does not repeat the header, failed sensor leaves empty cell
+        let mut logger = CsvLogger::open(&path, &widgets(None)).unwrap();
+        logger.write(time, None, &widgets(None)).unwrap();
+
+        // columns do not follow the sort order
+        logger.write(time, None, &widgets(Some(70.0)).into_iter().rev().collect()).unwrap();
+        logger.write(time, Some(&Timestamp::Seconds(12.5)), &widgets(Some(70.0))).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "timestamp,cpu,\"gpu, edge\"");
+        assert!(lines[1].ends_with(",45.2,60.0"), "{}", lines[1]);
+        assert!(lines[2].ends_with(",45.2,"), "{}", lines[2]);
+        assert!(lines[3].ends_with(",45.2,70.0"), "{}", lines[3]);
+        assert_eq!(lines[4], "12.500,45.2,70.0");
+
+        // different sensors would shift the columns
+        let widgets = widgets(None).into_iter().take(1).collect::<Widgets>();
+        assert!(CsvLogger::open(&path, &widgets).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use crate::prelude::*;
+use crate::signal::SIGTERM;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::os::unix::process::CommandExt;
+use std::time::{Duration, Instant};
+
+const LOCK_EX: i32 = 2;
+const LOCK_NB: i32 = 4;
+
+unsafe extern "C" {
+    fn kill(pid: i32, sig: i32) -> i32;
+    fn flock(fd: i32, operation: i32) -> i32;
+}
+
+/// Environment variable set on the detached child so it knows not to detach again
+const ENV_DAEMON_CHILD: &str = "KELVIN_DAEMON_CHILD";
+
+/// Directory for runtime files like the PID file
+pub fn runtime_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+/// Directory for persistent state like logs
+pub fn state_dir() -> PathBuf {
+    let base = match std::env::var_os("XDG_STATE_HOME") {
+        Some(x) => PathBuf::from(x),
+        None => PathBuf::from(std::env::var_os("HOME").unwrap_or_else(|| "/tmp".into()))
+            .join(".local/state"),
+    };
+
+    base.join("kelvin")
+}
+
+pub fn pid_file_path() -> PathBuf {
+    runtime_dir().join("kelvin.pid")
+}
+
+pub fn lock_file_path() -> PathBuf {
+    runtime_dir().join("kelvin.lock")
+}
+
+pub fn log_file_path() -> PathBuf {
+    state_dir().join("kelvin.log")
+}
+
+/// Home Assistant entities announced over MQTT, to remove the ones of sensors dropped from the config
+#[cfg(feature = "mqtt")]
+pub fn discovery_file_path() -> PathBuf {
+    state_dir().join("ha-discovery.json")
+}
+
+/// Returns true if process with the pid exists
+pub fn is_process_alive(pid: u32) -> bool {
+    PathBuf::from(format!("/proc/{pid}")).exists()
+}
+
+/// Returns true if process with the pid is an instance of kelvin
+///
+/// Used to detect stale PID files where the pid was recycled by another process
+pub fn is_kelvin_process(pid: u32) -> bool {
+    let Ok(comm) = std::fs::read_to_string(format!("/proc/{pid}/comm")) else {
+        return false;
+    };
+
+    let exe_name = std::env::current_exe()
+        .ok()
+        .and_then(|x| x.file_name().map(|x| x.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "kelvin".to_string());
+
+    is_same_name(&comm, &exe_name)
+}
+
+/// Whether the process name from `comm` is the executable, empty one is never a match
+fn is_same_name(comm: &str, exe_name: &str) -> bool {
+    // kernel truncates the process name to 15 characters, shorter one has to match fully
+    const MAX_COMM_LEN: usize = 15;
+
+    let comm = comm.trim();
+    !comm.is_empty() && (comm == exe_name || (comm.len() == MAX_COMM_LEN && exe_name.starts_with(comm)))
+}
+
+/// Read pid from the PID file, returns `None` if the file is missing or the process is dead
+pub fn running_daemon_pid() -> Option<u32> {
+    let pid = std::fs::read_to_string(pid_file_path()).ok()?
+        .trim()
+        .parse::<u32>()
+        .ok()?;
+
+    // ignore stale pid files
+    if pid != std::process::id() && is_process_alive(pid) && is_kelvin_process(pid) {
+        Some(pid)
+    } else {
+        None
+    }
+}
+
+pub fn write_pid_file() -> Result<()> {
+    let path = pid_file_path();
+
+    std::fs::write(&path, format!("{}\n", std::process::id()))
+        .with_context(|| anyhow!("Unable to write PID file {path:?}"))
+}
+
+pub fn remove_pid_file() {
+    // the file may have been already removed, nothing to do about it
+    let _ = std::fs::remove_file(pid_file_path());
+}
+
+/// Exclusive lock held for the whole life of the daemon
+///
+/// Kernel releases it when the process exits, so a crashed daemon cannot leave a stale lock behind
+#[derive(Debug)]
+pub struct InstanceLock {
+    /// Only kept open, closing it releases the lock
+    _file: std::fs::File,
+}
+
+/// Try to lock the file, returns `None` if another process holds the lock
+pub fn lock_file(path: &Path) -> Result<Option<InstanceLock>> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| anyhow!("Unable to open lock file {path:?}"))?;
+
+    if unsafe { flock(file.as_raw_fd(), LOCK_EX | LOCK_NB) } != 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() == std::io::ErrorKind::WouldBlock {
+            return Ok(None);
+        }
+
+        return Err(err).with_context(|| anyhow!("Unable to lock {path:?}"));
+    }
+
+    Ok(Some(InstanceLock { _file: file }))
+}
+
+/// Lock ensuring only one daemon is running
+pub fn lock_instance() -> Result<Option<InstanceLock>> {
+    lock_file(&lock_file_path())
+}
+
+/// Send SIGTERM to the running daemon and wait for it to exit
+///
+/// Returns pid of the killed daemon
+pub fn kill_daemon(grace_period: Duration) -> Result<u32> {
+    let Some(pid) = running_daemon_pid() else {
+        // cleanup stale pid file if there is one
+        remove_pid_file();
+        bail!("No daemon is running");
+    };
+
+    if unsafe { kill(pid as i32, SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| anyhow!("Unable to send SIGTERM to daemon with pid {pid}"));
+    }
+
+    let start = Instant::now();
+    while is_process_alive(pid) {
+        if start.elapsed() >= grace_period {
+            bail!("Daemon with pid {pid} did not exit after {}s", grace_period.as_secs());
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    remove_pid_file();
+
+    Ok(pid)
+}
+
+/// Returns true if running as a systemd service, in which case the process stays in foreground
+pub fn is_systemd_service() -> bool {
+    std::env::var_os("INVOCATION_ID").is_some()
+}
+
+/// Returns true if this process is the already detached daemon
+pub fn is_detached_child() -> bool {
+    std::env::var_os(ENV_DAEMON_CHILD).is_some()
+}
+
+/// Spawn the daemon in background with same arguments, output is redirected to the log file
+///
+/// Returns pid of the spawned process
+pub fn detach() -> Result<u32> {
+    let log_path = log_file_path();
+    if let Some(parent) = log_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| anyhow!("Unable to create log directory {parent:?}"))?;
+    }
+
+    let log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| anyhow!("Unable to open log file {log_path:?}"))?;
+
+    let exe = std::env::current_exe()
+        .with_context(|| anyhow!("Unable to get path of current executable"))?;
+
+    let child = Command::new(exe)
+        .args(std::env::args_os().skip(1))
+        .env(ENV_DAEMON_CHILD, "1")
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        // move into its own process group so terminal signals do not reach it
+        .process_group(0)
+        .spawn()
+        .with_context(|| anyhow!("Unable to spawn daemon process"))?;
+
+    Ok(child.id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_is_kelvin_process() {
+        assert!(is_kelvin_process(std::process::id()));
+        assert!(!is_kelvin_process(u32::MAX));
+
+        assert!(is_same_name("kelvin\n", "kelvin"));
+        assert!(is_same_name("kelvin-87ae6ded\n", "kelvin-87ae6ded0bfe91af"));
+        assert!(!is_same_name("\n", "kelvin"));
+        assert!(!is_same_name("", "kelvin"));
+        assert!(!is_same_name("bash\n", "kelvin"));
+        assert!(!is_same_name("kel\n", "kelvin"));
+        assert!(!is_same_name("kelvin-87ae\n", "kelvin-87ae6ded0bfe91af"));
+    }
+
+    #[test]
+    fn test_lock_file() {
+        let dir = TempDir::new("lock");
+        let path = dir.join("kelvin.lock");
+
+        let lock = lock_file(&path).unwrap();
+        assert!(lock.is_some());
+
+        // second instance cannot take it
+        assert!(lock_file(&path).unwrap().is_none());
+
+        // released when the owner goes away
+        drop(lock);
+        assert!(lock_file(&path).unwrap().is_some());
+    }
+}
@@ -0,0 +1,783 @@
+//! D-Bus service of the daemon, readings can be queried and changes are sent as signals
+//!
+//! The library is loaded at runtime so kelvin still works without it, the connection lives in its own thread
+
+use crate::prelude::*;
+use crate::alarm::AlarmState;
+use crate::config::{DbusBus, DbusConfig};
+use crate::Widgets;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, c_char, c_int, c_uint, c_void};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+
+const LIBRARY: &CStr = c"libdbus-1.so.3";
+const RTLD_NOW: c_int = 2;
+
+pub const BUS_NAME: &CStr = c"org.kelvin.Monitor";
+const OBJECT_PATH: &CStr = c"/org/kelvin/Monitor";
+const INTERFACE: &CStr = c"org.kelvin.Monitor";
+const INTROSPECTABLE: &str = "org.freedesktop.DBus.Introspectable";
+
+const BUS_SESSION: c_int = 0;
+const BUS_SYSTEM: c_int = 1;
+
+const NAME_FLAG_DO_NOT_QUEUE: c_uint = 4;
+const NAME_REPLY_PRIMARY_OWNER: c_int = 1;
+const NAME_REPLY_ALREADY_OWNER: c_int = 4;
+
+const MESSAGE_METHOD_CALL: c_int = 1;
+
+const TYPE_INVALID: c_int = 0;
+const TYPE_STRING: c_int = b's' as c_int;
+const TYPE_DOUBLE: c_int = b'd' as c_int;
+const TYPE_ARRAY: c_int = b'a' as c_int;
+const TYPE_STRUCT: c_int = b'r' as c_int;
+
+/// How long to wait for calls before sending queued signals, also how long stopping can take
+const POLL_MS: c_int = 100;
+
+const CALL_TIMEOUT_MS: c_int = 2000;
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.kelvin.Monitor">
+    <method name="GetReadings">
+      <arg name="readings" type="a(sdss)" direction="out"/>
+    </method>
+    <signal name="ValueChanged">
+      <arg name="sensor" type="s"/>
+      <arg name="value" type="d"/>
+    </signal>
+    <signal name="AlarmStateChanged">
+      <arg name="sensor" type="s"/>
+      <arg name="state" type="s"/>
+      <arg name="severity" type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+unsafe extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+/// Same layout as `DBusError` in dbus-errors.h
+#[repr(C)]
+struct DbusError {
+    name: *const c_char,
+    message: *const c_char,
+    dummy: c_uint,
+    padding: *mut c_void,
+}
+
+/// Space for `DBusMessageIter`, larger than the one in dbus-message.h
+#[repr(C, align(8))]
+struct MessageIter([u8; 128]);
+
+impl MessageIter {
+    fn new() -> Self {
+        Self([0; 128])
+    }
+}
+
+type InitFn = unsafe extern "C" fn() -> c_uint;
+type ErrorFn = unsafe extern "C" fn(*mut DbusError);
+type BusGetFn = unsafe extern "C" fn(c_int, *mut DbusError) -> *mut c_void;
+type OpenFn = unsafe extern "C" fn(*const c_char, *mut DbusError) -> *mut c_void;
+type RegisterFn = unsafe extern "C" fn(*mut c_void, *mut DbusError) -> c_uint;
+type SetFlagFn = unsafe extern "C" fn(*mut c_void, c_uint);
+type RequestNameFn = unsafe extern "C" fn(*mut c_void, *const c_char, c_uint, *mut DbusError) -> c_int;
+type ReadWriteFn = unsafe extern "C" fn(*mut c_void, c_int) -> c_uint;
+type PtrFn = unsafe extern "C" fn(*mut c_void) -> *mut c_void;
+type SendFn = unsafe extern "C" fn(*mut c_void, *mut c_void, *mut u32) -> c_uint;
+type CallFn = unsafe extern "C" fn(*mut c_void, *mut c_void, c_int, *mut DbusError) -> *mut c_void;
+type FreeFn = unsafe extern "C" fn(*mut c_void);
+type GetTypeFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+type GetStrFn = unsafe extern "C" fn(*mut c_void) -> *const c_char;
+type NewErrorFn = unsafe extern "C" fn(*mut c_void, *const c_char, *const c_char) -> *mut c_void;
+type NewSignalFn = unsafe extern "C" fn(*const c_char, *const c_char, *const c_char) -> *mut c_void;
+type NewCallFn = unsafe extern "C" fn(*const c_char, *const c_char, *const c_char, *const c_char) -> *mut c_void;
+type IterInitFn = unsafe extern "C" fn(*mut c_void, *mut MessageIter) -> c_uint;
+type IterAppendFn = unsafe extern "C" fn(*mut MessageIter, c_int, *const c_void) -> c_uint;
+type IterOpenFn = unsafe extern "C" fn(*mut MessageIter, c_int, *const c_char, *mut MessageIter) -> c_uint;
+type IterCloseFn = unsafe extern "C" fn(*mut MessageIter, *mut MessageIter) -> c_uint;
+type IterTypeFn = unsafe extern "C" fn(*mut MessageIter) -> c_int;
+type IterInitAppendFn = unsafe extern "C" fn(*mut c_void, *mut MessageIter);
+type IterRecurseFn = unsafe extern "C" fn(*mut MessageIter, *mut MessageIter);
+type IterGetFn = unsafe extern "C" fn(*mut MessageIter, *mut c_void);
+
+/// Functions loaded from the library
+struct Library {
+    threads_init: InitFn,
+    error_init: ErrorFn,
+    error_free: ErrorFn,
+    bus_get: BusGetFn,
+    open: OpenFn,
+    register: RegisterFn,
+    set_exit_on_disconnect: SetFlagFn,
+    request_name: RequestNameFn,
+    read_write: ReadWriteFn,
+    pop_message: PtrFn,
+    send: SendFn,
+    call: CallFn,
+    flush: FreeFn,
+    close: FreeFn,
+    connection_unref: FreeFn,
+    message_type: GetTypeFn,
+    message_path: GetStrFn,
+    message_interface: GetStrFn,
+    message_member: GetStrFn,
+    new_return: PtrFn,
+    new_error: NewErrorFn,
+    new_signal: NewSignalFn,
+    new_call: NewCallFn,
+    message_unref: FreeFn,
+    iter_init_append: IterInitAppendFn,
+    iter_append: IterAppendFn,
+    iter_open: IterOpenFn,
+    iter_close: IterCloseFn,
+    iter_init: IterInitFn,
+    iter_type: IterTypeFn,
+    iter_recurse: IterRecurseFn,
+    iter_next: IterTypeFn,
+    iter_get: IterGetFn,
+}
+
+static LIBRARY_INSTANCE: OnceLock<Option<Library>> = OnceLock::new();
+
+impl Library {
+    fn load() -> Option<Self> {
+        // SAFETY: symbols are cast to their signatures from the dbus headers
+        unsafe {
+            let handle = dlopen(LIBRARY.as_ptr(), RTLD_NOW);
+            if handle.is_null() {
+                return None;
+            }
+
+            macro_rules! symbol {
+                ($name:literal, $type:ty) => {{
+                    let ptr = dlsym(handle, $name.as_ptr());
+                    if ptr.is_null() {
+                        return None;
+                    }
+                    std::mem::transmute::<*mut c_void, $type>(ptr)
+                }};
+            }
+
+            let lib = Self {
+                threads_init: symbol!(c"dbus_threads_init_default", InitFn),
+                error_init: symbol!(c"dbus_error_init", ErrorFn),
+                error_free: symbol!(c"dbus_error_free", ErrorFn),
+                bus_get: symbol!(c"dbus_bus_get_private", BusGetFn),
+                open: symbol!(c"dbus_connection_open_private", OpenFn),
+                register: symbol!(c"dbus_bus_register", RegisterFn),
+                set_exit_on_disconnect: symbol!(c"dbus_connection_set_exit_on_disconnect", SetFlagFn),
+                request_name: symbol!(c"dbus_bus_request_name", RequestNameFn),
+                read_write: symbol!(c"dbus_connection_read_write", ReadWriteFn),
+                pop_message: symbol!(c"dbus_connection_pop_message", PtrFn),
+                send: symbol!(c"dbus_connection_send", SendFn),
+                call: symbol!(c"dbus_connection_send_with_reply_and_block", CallFn),
+                flush: symbol!(c"dbus_connection_flush", FreeFn),
+                close: symbol!(c"dbus_connection_close", FreeFn),
+                connection_unref: symbol!(c"dbus_connection_unref", FreeFn),
+                message_type: symbol!(c"dbus_message_get_type", GetTypeFn),
+                message_path: symbol!(c"dbus_message_get_path", GetStrFn),
+                message_interface: symbol!(c"dbus_message_get_interface", GetStrFn),
+                message_member: symbol!(c"dbus_message_get_member", GetStrFn),
+                new_return: symbol!(c"dbus_message_new_method_return", PtrFn),
+                new_error: symbol!(c"dbus_message_new_error", NewErrorFn),
+                new_signal: symbol!(c"dbus_message_new_signal", NewSignalFn),
+                new_call: symbol!(c"dbus_message_new_method_call", NewCallFn),
+                message_unref: symbol!(c"dbus_message_unref", FreeFn),
+                iter_init_append: symbol!(c"dbus_message_iter_init_append", IterInitAppendFn),
+                iter_append: symbol!(c"dbus_message_iter_append_basic", IterAppendFn),
+                iter_open: symbol!(c"dbus_message_iter_open_container", IterOpenFn),
+                iter_close: symbol!(c"dbus_message_iter_close_container", IterCloseFn),
+                iter_init: symbol!(c"dbus_message_iter_init", IterInitFn),
+                iter_type: symbol!(c"dbus_message_iter_get_arg_type", IterTypeFn),
+                iter_recurse: symbol!(c"dbus_message_iter_recurse", IterRecurseFn),
+                iter_next: symbol!(c"dbus_message_iter_next", IterTypeFn),
+                iter_get: symbol!(c"dbus_message_iter_get_basic", IterGetFn),
+            };
+
+            // connections move to their own thread
+            (lib.threads_init)();
+            Some(lib)
+        }
+    }
+
+    fn get() -> Result<&'static Self> {
+        LIBRARY_INSTANCE.get_or_init(Self::load).as_ref()
+            .with_context(|| anyhow!("Unable to load {LIBRARY:?}, is D-Bus installed?"))
+    }
+
+    fn new_error(&self) -> DbusError {
+        let mut error = DbusError { name: std::ptr::null(), message: std::ptr::null(), dummy: 0, padding: std::ptr::null_mut() };
+
+        // SAFETY: the struct has the layout of `DBusError`
+        unsafe { (self.error_init)(&mut error) };
+        error
+    }
+
+    /// Message of the error, it is freed
+    fn take_error(&self, error: &mut DbusError) -> String {
+        // SAFETY: the error was initialized, the strings are valid until it is freed
+        unsafe {
+            let message = match (error.message.is_null(), error.name.is_null()) {
+                (false, _) => CStr::from_ptr(error.message).to_string_lossy().into_owned(),
+                (true, false) => CStr::from_ptr(error.name).to_string_lossy().into_owned(),
+                (true, true) => "unknown error".to_string(),
+            };
+
+            (self.error_free)(error);
+            message
+        }
+    }
+}
+
+/// Text for a D-Bus string, they cannot contain NUL
+fn c_string(text: &str) -> CString {
+    CString::new(text.replace('\0', "")).unwrap_or_default()
+}
+
+/// Message owned by kelvin, unreferenced when dropped
+struct Message {
+    lib: &'static Library,
+    ptr: *mut c_void,
+}
+
+impl Message {
+    fn new(lib: &'static Library, ptr: *mut c_void) -> Result<Self> {
+        match ptr.is_null() {
+            true => bail!("Unable to create D-Bus message, out of memory"),
+            false => Ok(Self { lib, ptr }),
+        }
+    }
+
+    fn field(&self, get: GetStrFn) -> Option<String> {
+        // SAFETY: message is valid, returned string lives as long as the message
+        unsafe {
+            let ptr = get(self.ptr);
+            (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_string_lossy().into_owned())
+        }
+    }
+
+    /// Iterator appending to the end of the arguments
+    fn append_iter(&self) -> MessageIter {
+        let mut iter = MessageIter::new();
+
+        // SAFETY: message is valid and the iterator has enough space
+        unsafe { (self.lib.iter_init_append)(self.ptr, &mut iter) };
+        iter
+    }
+
+    fn append_string(&self, iter: &mut MessageIter, text: &str) {
+        let text = c_string(text);
+        let ptr = text.as_ptr();
+
+        // SAFETY: strings are passed as a pointer to the pointer, libdbus copies it
+        unsafe { (self.lib.iter_append)(iter, TYPE_STRING, (&raw const ptr).cast()) };
+    }
+
+    fn append_double(&self, iter: &mut MessageIter, value: f64) {
+        // SAFETY: value is copied
+        unsafe { (self.lib.iter_append)(iter, TYPE_DOUBLE, (&raw const value).cast()) };
+    }
+
+    /// Append a container, `append` fills it through the iterator it is given
+    fn append_container(&self, iter: &mut MessageIter, kind: c_int, signature: Option<&CStr>, append: impl FnOnce(&mut MessageIter)) {
+        let mut sub = MessageIter::new();
+
+        // SAFETY: sub iterator is closed before the parent is used again
+        unsafe {
+            (self.lib.iter_open)(iter, kind, signature.map_or(std::ptr::null(), |x| x.as_ptr()), &mut sub);
+            append(&mut sub);
+            (self.lib.iter_close)(iter, &mut sub);
+        }
+    }
+
+    fn append_readings(&self, readings: &[DbusReading]) {
+        let mut iter = self.append_iter();
+        self.append_container(&mut iter, TYPE_ARRAY, Some(c"(sdss)"), |array| {
+            for reading in readings {
+                self.append_container(array, TYPE_STRUCT, None, |item| {
+                    self.append_string(item, &reading.name);
+                    self.append_double(item, reading.value);
+                    self.append_string(item, &reading.unit);
+                    self.append_string(item, &reading.alarm);
+                });
+            }
+        });
+    }
+
+    /// First argument as readings, only accepts the signature of `GetReadings`
+    fn read_readings(&self) -> Result<Vec<DbusReading>> {
+        let lib = self.lib;
+        let mut iter = MessageIter::new();
+        let mut readings = vec![];
+
+        // SAFETY: types are checked before each value is read
+        unsafe {
+            if (lib.iter_init)(self.ptr, &mut iter) == 0 || (lib.iter_type)(&mut iter) != TYPE_ARRAY {
+                bail!("Unexpected reply, expected an array of readings");
+            }
+
+            let mut array = MessageIter::new();
+            (lib.iter_recurse)(&mut iter, &mut array);
+
+            while (lib.iter_type)(&mut array) == TYPE_STRUCT {
+                let mut item = MessageIter::new();
+                (lib.iter_recurse)(&mut array, &mut item);
+
+                let mut strings = vec![];
+                let mut value = None;
+                loop {
+                    match (lib.iter_type)(&mut item) {
+                        TYPE_STRING => {
+                            let mut ptr: *const c_char = std::ptr::null();
+                            (lib.iter_get)(&mut item, (&raw mut ptr).cast());
+                            strings.push(CStr::from_ptr(ptr).to_string_lossy().into_owned());
+                        },
+                        TYPE_DOUBLE => {
+                            let mut x = 0f64;
+                            (lib.iter_get)(&mut item, (&raw mut x).cast());
+                            value = Some(x);
+                        },
+                        TYPE_INVALID => break,
+                        _ => bail!("Unexpected reply, invalid reading"),
+                    }
+
+                    (lib.iter_next)(&mut item);
+                }
+
+                let (Ok([name, unit, alarm]), Some(value)) = (<[String; 3]>::try_from(strings), value) else {
+                    bail!("Unexpected reply, invalid reading");
+                };
+
+                readings.push(DbusReading { name, value, unit, alarm });
+                (lib.iter_next)(&mut array);
+            }
+        }
+
+        Ok(readings)
+    }
+}
+
+impl Drop for Message {
+    fn drop(&mut self) {
+        // SAFETY: kelvin holds a reference to the message
+        unsafe { (self.lib.message_unref)(self.ptr) };
+    }
+}
+
+/// Private connection to a bus, closed when dropped
+pub struct Connection {
+    lib: &'static Library,
+    ptr: *mut c_void,
+}
+
+// SAFETY: libdbus is initialized for threads and the connection is only used by the thread that owns it
+unsafe impl Send for Connection {}
+
+impl Connection {
+    /// Connect to the bus of the config
+    pub fn connect(config: &DbusConfig) -> Result<Self> {
+        match &config.address {
+            Some(x) => Self::open(x),
+            None => Self::bus(config.bus),
+        }
+    }
+
+    pub fn bus(bus: DbusBus) -> Result<Self> {
+        let lib = Library::get()?;
+        let mut error = lib.new_error();
+        let kind = match bus {
+            DbusBus::Session => BUS_SESSION,
+            DbusBus::System => BUS_SYSTEM,
+        };
+
+        // SAFETY: error is initialized
+        let ptr = unsafe { (lib.bus_get)(kind, &mut error) };
+        Self::new(lib, ptr, &mut error).with_context(|| anyhow!("Unable to connect to the {} bus", bus.as_str()))
+    }
+
+    /// Connect to the bus at the address, like the `--print-address` of `dbus-daemon`
+    pub fn open(address: &str) -> Result<Self> {
+        let lib = Library::get()?;
+        let mut error = lib.new_error();
+        let address_c = c_string(address);
+
+        // SAFETY: error is initialized, the connection is closed by the returned value even if registering fails
+        unsafe {
+            let ptr = (lib.open)(address_c.as_ptr(), &mut error);
+            let connection = Self::new(lib, ptr, &mut error).with_context(|| anyhow!("Unable to connect to {address:?}"))?;
+
+            if (lib.register)(connection.ptr, &mut error) == 0 {
+                bail!("Unable to register with the bus at {address:?}: {}", lib.take_error(&mut error));
+            }
+
+            Ok(connection)
+        }
+    }
+
+    fn new(lib: &'static Library, ptr: *mut c_void, error: &mut DbusError) -> Result<Self> {
+        if ptr.is_null() {
+            bail!("{}", lib.take_error(error));
+        }
+
+        // the default is to exit the whole process when the bus goes away
+        // SAFETY: connection is valid
+        unsafe { (lib.set_exit_on_disconnect)(ptr, 0) };
+        Ok(Self { lib, ptr })
+    }
+
+    fn request_name(&self) -> Result<()> {
+        let mut error = self.lib.new_error();
+
+        // SAFETY: connection and error are valid
+        let reply = unsafe { (self.lib.request_name)(self.ptr, BUS_NAME.as_ptr(), NAME_FLAG_DO_NOT_QUEUE, &mut error) };
+        match reply {
+            NAME_REPLY_PRIMARY_OWNER | NAME_REPLY_ALREADY_OWNER => Ok(()),
+            -1 => bail!("Unable to own {BUS_NAME:?}: {}", self.lib.take_error(&mut error)),
+            _ => bail!("{BUS_NAME:?} is already owned by another process"),
+        }
+    }
+
+    fn send(&self, message: &Message) {
+        // SAFETY: connection and message are valid, the connection takes its own reference
+        unsafe { (self.lib.send)(self.ptr, message.ptr, std::ptr::null_mut()) };
+    }
+
+    /// Call a method and wait for the reply, errors replied with are returned as errors
+    fn call(&self, message: &Message) -> Result<Message> {
+        let mut error = self.lib.new_error();
+
+        // SAFETY: connection, message and error are valid
+        let reply = unsafe { (self.lib.call)(self.ptr, message.ptr, CALL_TIMEOUT_MS, &mut error) };
+        match reply.is_null() {
+            true => Err(anyhow!("{}", self.lib.take_error(&mut error))),
+            false => Message::new(self.lib, reply),
+        }
+    }
+
+    /// Wait for messages and write queued ones, returns false when the connection is lost
+    fn read_write(&self, timeout_ms: c_int) -> bool {
+        // SAFETY: connection is valid
+        unsafe { (self.lib.read_write)(self.ptr, timeout_ms) != 0 }
+    }
+
+    fn pop(&self) -> Option<Message> {
+        // SAFETY: connection is valid, the message is owned by the caller
+        let ptr = unsafe { (self.lib.pop_message)(self.ptr) };
+        Message::new(self.lib, ptr).ok()
+    }
+
+    fn signal(&self, name: &CStr, append: impl FnOnce(&Message, &mut MessageIter)) {
+        // SAFETY: all strings are valid
+        let ptr = unsafe { (self.lib.new_signal)(OBJECT_PATH.as_ptr(), INTERFACE.as_ptr(), name.as_ptr()) };
+        if let Ok(message) = Message::new(self.lib, ptr) {
+            let mut iter = message.append_iter();
+            append(&message, &mut iter);
+            self.send(&message);
+        }
+    }
+
+    /// Answer a method call with the readings or introspection data, anything else is an error
+    fn answer(&self, call: &Message, readings: &Mutex<Vec<DbusReading>>) -> Result<()> {
+        // SAFETY: message is valid
+        if unsafe { (self.lib.message_type)(call.ptr) } != MESSAGE_METHOD_CALL {
+            return Ok(());
+        }
+
+        let path = call.field(self.lib.message_path);
+        let interface = call.field(self.lib.message_interface);
+        let member = call.field(self.lib.message_member).unwrap_or_default();
+
+        let error = |name: &CStr, text: String| {
+            let text = c_string(&text);
+
+            // SAFETY: message and strings are valid
+            Message::new(self.lib, unsafe { (self.lib.new_error)(call.ptr, name.as_ptr(), text.as_ptr()) })
+        };
+
+        let reply = if path.as_deref() != OBJECT_PATH.to_str().ok() {
+            error(c"org.freedesktop.DBus.Error.UnknownObject", format!("No object at {path:?}"))?
+        } else {
+            match (interface.as_deref(), member.as_str()) {
+                (Some("org.kelvin.Monitor") | None, "GetReadings") => {
+                    // SAFETY: message is valid
+                    let reply = Message::new(self.lib, unsafe { (self.lib.new_return)(call.ptr) })?;
+                    reply.append_readings(&readings.lock().unwrap());
+                    reply
+                },
+                (Some(INTROSPECTABLE) | None, "Introspect") => {
+                    // SAFETY: message is valid
+                    let reply = Message::new(self.lib, unsafe { (self.lib.new_return)(call.ptr) })?;
+                    let mut iter = reply.append_iter();
+                    reply.append_string(&mut iter, INTROSPECTION);
+                    reply
+                },
+                _ => error(c"org.freedesktop.DBus.Error.UnknownMethod", format!("Unknown method {member:?}"))?,
+            }
+        };
+
+        self.send(&reply);
+        Ok(())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // SAFETY: private connections have to be closed before the last reference is dropped
+        unsafe {
+            (self.lib.flush)(self.ptr);
+            (self.lib.close)(self.ptr);
+            (self.lib.connection_unref)(self.ptr);
+        }
+    }
+}
+
+/// Reading as returned by `GetReadings`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DbusReading {
+    pub name: String,
+    pub value: f64,
+
+    /// Empty for sensors without a unit
+    pub unit: String,
+
+    /// Alarm state like `normal` or `warn_high`
+    pub alarm: String,
+}
+
+enum Signal {
+    Value(String, f64),
+    Alarm(String, AlarmState),
+}
+
+/// Value and alarm state last sent in signals
+#[derive(Debug, Clone, Copy)]
+struct Signalled {
+    value: f64,
+    alarm: AlarmState,
+}
+
+/// Service thread, answers calls and sends signals until the sender is dropped
+fn run(connection: Connection, receiver: Receiver<Signal>, readings: &Mutex<Vec<DbusReading>>) {
+    loop {
+        loop {
+            match receiver.try_recv() {
+                Ok(Signal::Value(name, value)) => connection.signal(c"ValueChanged", |message, iter| {
+                    message.append_string(iter, &name);
+                    message.append_double(iter, value);
+                }),
+                Ok(Signal::Alarm(name, alarm)) => connection.signal(c"AlarmStateChanged", |message, iter| {
+                    message.append_string(iter, &name);
+                    message.append_string(iter, alarm.as_str());
+                    message.append_string(iter, alarm.severity().as_str());
+                }),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+
+        if !connection.read_write(POLL_MS) {
+            crate::log::warning!("Lost connection to D-Bus, readings are no longer served");
+            return;
+        }
+
+        while let Some(message) = connection.pop() {
+            if let Err(err) = connection.answer(&message, readings) {
+                crate::log::debug!("Unable to answer D-Bus call: {err:#}");
+            }
+        }
+    }
+}
+
+/// Serves readings on the bus as `org.kelvin.Monitor`
+pub struct Service {
+    config: DbusConfig,
+    sender: Option<Sender<Signal>>,
+    readings: Arc<Mutex<Vec<DbusReading>>>,
+    thread: Option<JoinHandle<()>>,
+
+    /// By sensor name
+    signalled: HashMap<String, Signalled>,
+}
+
+impl Service {
+    pub fn start(config: &DbusConfig) -> Result<Self> {
+        let connection = Connection::connect(config)?;
+        connection.request_name()?;
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let readings = Arc::new(Mutex::new(vec![]));
+
+        let thread = std::thread::Builder::new()
+            .name("dbus".into())
+            .spawn({
+                let readings = readings.clone();
+                move || run(connection, receiver, &readings)
+            })
+            .with_context(|| anyhow!("Unable to start D-Bus thread"))?;
+
+        Ok(Self { config: config.clone(), sender: Some(sender), readings, thread: Some(thread), signalled: HashMap::new() })
+    }
+
+    pub fn config(&self) -> &DbusConfig {
+        &self.config
+    }
+
+    /// Serve readings of the latest tick and signal what changed since the last signals
+    ///
+    /// Values only count as changed by at least the rounding of the sensor, not to signal changes that are not shown
+    pub fn update(&mut self, widgets: &Widgets) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+
+        let mut readings = vec![];
+
+        for (_, widget) in widgets {
+            let (Some(sensor), Some(reading)) = (widget.sensor(), widget.reading()) else {
+                continue;
+            };
+
+            let granularity = sensor.round.map_or(0.0, |x| 10f64.powi(-(x as i32)));
+            let signalled = self.signalled.get(&reading.name).copied();
+
+            let value = match signalled {
+                Some(x) if x.value == reading.value || (x.value - reading.value).abs() < granularity => x.value,
+                _ => {
+                    let _ = sender.send(Signal::Value(reading.name.clone(), reading.value));
+                    reading.value
+                },
+            };
+
+            // sensors start out as normal, so only an alarm is signalled for new ones
+            if signalled.map_or(AlarmState::Normal, |x| x.alarm) != reading.alarm {
+                let _ = sender.send(Signal::Alarm(reading.name.clone(), reading.alarm));
+            }
+
+            self.signalled.insert(reading.name.clone(), Signalled { value, alarm: reading.alarm });
+            readings.push(DbusReading {
+                name: reading.name.clone(),
+                value: reading.value,
+                unit: reading.unit.clone().unwrap_or_default(),
+                alarm: reading.alarm.as_str().to_string(),
+            });
+        }
+
+        *self.readings.lock().unwrap() = readings;
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        // closing the channel stops the thread, which releases the name
+        self.sender = None;
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Readings of the daemon over the connection
+pub fn get_readings(connection: &Connection) -> Result<Vec<DbusReading>> {
+    let lib = connection.lib;
+    let method = c"GetReadings";
+
+    // SAFETY: all strings are valid
+    let ptr = unsafe { (lib.new_call)(BUS_NAME.as_ptr(), OBJECT_PATH.as_ptr(), INTERFACE.as_ptr(), method.as_ptr()) };
+    let reply = connection.call(&Message::new(lib, ptr)?)
+        .with_context(|| anyhow!("No daemon answered as {BUS_NAME:?}"))?;
+
+    reply.read_readings()
+}
+
+/// Readings of the daemon on the bus of the config
+pub fn query(config: &DbusConfig) -> Result<Vec<DbusReading>> {
+    get_readings(&Connection::connect(config)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Sensor;
+    use crate::output::Reading;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    /// Widget with a fixed reading
+    struct ReadingWidget(Sensor, Reading);
+
+    impl crate::Widget for ReadingWidget {
+        fn value(&mut self, _ctx: &crate::Context) -> Result<String> {
+            bail!("unreachable")
+        }
+
+        fn sensor(&self) -> Option<&Sensor> {
+            Some(&self.0)
+        }
+
+        fn reading(&self) -> Option<&Reading> {
+            Some(&self.1)
+        }
+    }
+
+    fn widgets(value: f64, alarm: AlarmState) -> Widgets {
+        let sensor = Sensor { name: "cpu".into(), round: Some(1), ..Default::default() };
+        let reading = Reading::new(&sensor, value, value, alarm);
+        vec![("{cpu}".into(), Box::new(ReadingWidget(sensor, reading)))]
+    }
+
+    #[test]
+    fn test_service() {
+        // private bus, so the test does not depend on a session
+        let mut daemon = match Command::new("dbus-daemon").args(["--session", "--nofork", "--print-address"]).stdout(Stdio::piped()).stderr(Stdio::null()).spawn() {
+            Ok(x) => x,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return,
+            Err(err) => panic!("{err}"),
+        };
+
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+        let config = DbusConfig { address: Some(address.trim().to_string()), ..Default::default() };
+
+        let mut service = Service::start(&config).unwrap();
+        let err = format!("{:#}", Service::start(&config).err().unwrap());
+        assert!(err.contains("already owned"), "{err}");
+
+        let client = Connection::connect(&config).unwrap();
+        assert_eq!(get_readings(&client).unwrap(), []);
+
+        service.update(&widgets(45.0, AlarmState::Normal));
+        assert_eq!(get_readings(&client).unwrap(), [DbusReading { name: "cpu".into(), value: 45.0, unit: String::new(), alarm: "normal".into() }]);
+
+        // below the rounding of the sensor
+        service.update(&widgets(45.04, AlarmState::Normal));
+        assert_eq!(service.signalled["cpu"].value, 45.0);
+
+        service.update(&widgets(95.0, AlarmState::High));
+        assert_eq!((service.signalled["cpu"].value, service.signalled["cpu"].alarm), (95.0, AlarmState::High));
+        assert_eq!(get_readings(&client).unwrap()[0].alarm, "high");
+
+        drop(service);
+        let err = format!("{:#}", get_readings(&client).unwrap_err());
+        assert!(err.contains("No daemon answered"), "{err}");
+
+        drop(client);
+        daemon.kill().unwrap();
+        daemon.wait().unwrap();
+    }
+}
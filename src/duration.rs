@@ -0,0 +1,162 @@
+//! Durations in the config written like `30s` or `500ms`
+
+use crate::prelude::*;
+use serde::{Deserialize, Deserializer, Serializer};
+use std::time::Duration;
+
+const UNITS: &[(&str, u64)] = &[
+    ("ms", 1),
+    ("s", 1000),
+    ("m", 60 * 1000),
+    ("h", 60 * 60 * 1000),
+    ("d", 24 * 60 * 60 * 1000),
+];
+
+/// Parse numbers each followed by an unit, `ms`, `s`, `m`, `h` or `d`, like `1h30m`
+pub fn parse(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let mut rest = text;
+    let mut millis = 0u64;
+
+    if rest.is_empty() {
+        bail!("Invalid duration {text:?}, expected a number followed by an unit like \"30s\"");
+    }
+
+    while !rest.is_empty() {
+        let split = rest.find(|x: char| !x.is_ascii_digit()).unwrap_or(rest.len());
+        let (number, after) = rest.split_at(split);
+
+        let Ok(number) = number.parse::<u64>() else {
+            bail!("Invalid duration {text:?}, expected a number followed by an unit like \"30s\"");
+        };
+
+        let after = after.trim_start();
+        let split = after.find(|x: char| !x.is_ascii_alphabetic()).unwrap_or(after.len());
+        let (unit, after) = after.split_at(split);
+
+        let Some((_, size)) = UNITS.iter().find(|(x, _)| *x == unit) else {
+            bail!("Invalid duration unit in {text:?}, expected one of ms, s, m, h or d");
+        };
+
+        millis = number.checked_mul(*size)
+            .and_then(|x| x.checked_add(millis))
+            .with_context(|| anyhow!("Duration {text:?} is too long"))?;
+
+        rest = after.trim_start();
+    }
+
+    Ok(Duration::from_millis(millis))
+}
+
+/// Format using the largest unit that represents it exactly
+pub fn format(duration: Duration) -> String {
+    let millis = duration.as_millis() as u64;
+
+    let (unit, size) = UNITS.iter()
+        .rev()
+        .find(|(_, x)| millis.is_multiple_of(*x) && millis > 0)
+        .unwrap_or(&UNITS[0]);
+
+    format!("{}{unit}", millis / size)
+}
+
+/// Integer is taken as millis, for configs written before durations had units
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DurationValue {
+    Millis(u64),
+    Text(String),
+}
+
+/// For use with `#[serde(with = "crate::duration")]`
+pub fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format(*value))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    match DurationValue::deserialize(deserializer)? {
+        DurationValue::Millis(x) => Ok(Duration::from_millis(x)),
+        DurationValue::Text(x) => parse(&x).map_err(serde::de::Error::custom),
+    }
+}
+
+/// For use with `#[serde(with = "crate::duration::option")]`
+pub mod option {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(value: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(x) => super::serialize(x, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        #[derive(Deserialize)]
+        struct Value(#[serde(with = "super")] Duration);
+
+        Ok(Option::<Value>::deserialize(deserializer)?.map(|x| x.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse(" 1h ").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse("30d").unwrap(), Duration::from_secs(30 * 24 * 3600));
+        assert_eq!(parse("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse("1m 30s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse("2s500ms").unwrap(), Duration::from_millis(2500));
+
+        assert!(parse("30").is_err());
+        assert!(parse("s").is_err());
+        assert!(parse("5 parsecs").is_err());
+        assert!(parse("-5s").is_err());
+        assert!(parse("").is_err());
+        assert!(parse("1h30").is_err());
+        assert!(parse("99999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(Duration::from_secs(30)), "30s");
+        assert_eq!(format(Duration::from_millis(1500)), "1500ms");
+        assert_eq!(format(Duration::from_secs(7200)), "2h");
+        assert_eq!(format(Duration::ZERO), "0ms");
+    }
+
+    #[test]
+    fn test_deserialize() {
+        #[derive(Debug, Deserialize)]
+        struct Test {
+            #[serde(with = "super")]
+            interval: Duration,
+
+            #[serde(default, with = "option")]
+            timeout: Option<Duration>,
+        }
+
+        let parse = |text: &str| toml::from_str::<Test>(text);
+
+        // integers are millis like before
+        let test = parse("interval = 5000").unwrap();
+        assert_eq!((test.interval, test.timeout), (Duration::from_secs(5), None));
+
+        let test = parse("interval = \"5m\"\ntimeout = \"1h30m\"").unwrap();
+        assert_eq!((test.interval, test.timeout), (Duration::from_secs(300), Some(Duration::from_secs(5400))));
+
+        let test = parse("interval = \"1s\"\ntimeout = 250").unwrap();
+        assert_eq!(test.timeout, Some(Duration::from_millis(250)));
+
+        assert!(parse("interval = \"5 parsecs\"").is_err());
+        assert!(parse("interval = -5").is_err());
+        assert!(parse("interval = 1.5").is_err());
+    }
+}
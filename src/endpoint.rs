@@ -0,0 +1,307 @@
+//! HTTP endpoint of the daemon serving the readings of the latest tick
+//!
+//! Requests are answered from the last snapshot in a background thread, they never cause sensors to be read
+
+use crate::prelude::*;
+use crate::config::HttpConfig;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How long a client may take to send the request and read the answer
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest request accepted, only the request line and headers are read
+const MAX_REQUEST: usize = 8192;
+
+const PROMETHEUS_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const JSON_TYPE: &str = "application/json";
+const TEXT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// Outputs of the latest tick
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    /// Prometheus text format for `/metrics`
+    pub metrics: String,
+
+    /// Readings, statistics and alarms like the status socket for `/json`
+    pub json: String,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    latest: Mutex<Option<Snapshot>>,
+    stop: AtomicBool,
+}
+
+#[derive(Debug)]
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+/// Connection from either listener, they both work the same
+trait Connection: Read + Write {
+    fn set_timeouts(&self, timeout: Duration) -> std::io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_timeouts(&self, timeout: Duration) -> std::io::Result<()> {
+        self.set_read_timeout(Some(timeout))?;
+        self.set_write_timeout(Some(timeout))
+    }
+}
+
+impl Connection for UnixStream {
+    fn set_timeouts(&self, timeout: Duration) -> std::io::Result<()> {
+        self.set_read_timeout(Some(timeout))?;
+        self.set_write_timeout(Some(timeout))
+    }
+}
+
+impl Listener {
+    fn accept(&self) -> std::io::Result<Box<dyn Connection>> {
+        match self {
+            Self::Tcp(x) => Ok(Box::new(x.accept()?.0)),
+            Self::Unix(x, _) => Ok(Box::new(x.accept()?.0)),
+        }
+    }
+
+    /// Connect to the listener so a blocked accept returns
+    fn wake(&self) {
+        let _ = match self {
+            Self::Tcp(x) => x.local_addr().and_then(TcpStream::connect).map(|_| ()),
+            Self::Unix(_, path) => UnixStream::connect(path).map(|_| ()),
+        };
+    }
+}
+
+/// Serves the latest snapshot until dropped
+#[derive(Debug)]
+pub struct HttpServer {
+    config: HttpConfig,
+    listener: Arc<Listener>,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HttpServer {
+    pub fn start(config: &HttpConfig) -> Result<Self> {
+        let listener = match &config.socket {
+            Some(path) => {
+                if path.exists() {
+                    std::fs::remove_file(path)
+                        .with_context(|| anyhow!("Unable to remove stale socket {path:?}"))?;
+                }
+
+                let listener = UnixListener::bind(path).with_context(|| anyhow!("Unable to bind HTTP socket {path:?}"))?;
+                Listener::Unix(listener, path.clone())
+            },
+            None => {
+                let address = (config.address.as_str(), config.port);
+                let listener = TcpListener::bind(address)
+                    .with_context(|| anyhow!("Unable to listen on {}:{}", config.address, config.port))?;
+                Listener::Tcp(listener)
+            },
+        };
+
+        let listener = Arc::new(listener);
+        let shared = Arc::new(Shared::default());
+
+        let thread = std::thread::Builder::new()
+            .name("http".into())
+            .spawn({
+                let listener = listener.clone();
+                let shared = shared.clone();
+                move || serve(&listener, &shared)
+            })
+            .with_context(|| anyhow!("Unable to start HTTP thread"))?;
+
+        Ok(Self { config: config.clone(), listener, shared, thread: Some(thread) })
+    }
+
+    pub fn config(&self) -> &HttpConfig {
+        &self.config
+    }
+
+    /// Replace the snapshot with outputs of the latest tick
+    pub fn update(&self, snapshot: Snapshot) {
+        *self.shared.latest.lock().unwrap() = Some(snapshot);
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        self.listener.wake();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+
+        if let Listener::Unix(_, path) = self.listener.as_ref() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn serve(listener: &Listener, shared: &Shared) {
+    loop {
+        let result = listener.accept();
+        if shared.stop.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let result = result
+            .with_context(|| anyhow!("Unable to accept HTTP connection"))
+            .and_then(|mut x| answer(x.as_mut(), shared));
+
+        if let Err(err) = result {
+            crate::log::debug!("HTTP request failed: {err:#}");
+        }
+    }
+}
+
+/// Read the request line and headers, the body is ignored as only GET is supported
+fn read_request(stream: &mut dyn Read) -> Result<String> {
+    let mut data = vec![];
+    let mut buffer = [0u8; 1024];
+
+    while !data.windows(4).any(|x| x == b"\r\n\r\n") {
+        if data.len() > MAX_REQUEST {
+            bail!("Request is too long");
+        }
+
+        match stream.read(&mut buffer)? {
+            0 => bail!("Connection closed before the request was complete"),
+            x => data.extend_from_slice(&buffer[..x]),
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Status, content type and body of the answer to the request line
+fn route(request: &str, shared: &Shared) -> (u16, &'static str, String) {
+    let mut parts = request.lines().next().unwrap_or_default().split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let path = target.split('?').next().unwrap_or_default();
+
+    if !matches!(method, "GET" | "HEAD") {
+        return (405, TEXT_TYPE, "Only GET is supported\n".into());
+    }
+
+    let latest = shared.latest.lock().unwrap().clone();
+    match (path, latest) {
+        ("/", _) => (200, TEXT_TYPE, "kelvin\n\n/metrics readings in prometheus format\n/json readings, statistics and alarms\n".into()),
+        ("/metrics" | "/json", None) => (503, TEXT_TYPE, "No readings yet\n".into()),
+        ("/metrics", Some(x)) => (200, PROMETHEUS_TYPE, format!("{}\n", x.metrics)),
+        ("/json", Some(x)) => (200, JSON_TYPE, x.json),
+        _ => (404, TEXT_TYPE, "Not found\n".into()),
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+fn answer(stream: &mut dyn Connection, shared: &Shared) -> Result<()> {
+    stream.set_timeouts(TIMEOUT)?;
+
+    let request = read_request(stream)?;
+    let (status, content_type, body) = route(&request, shared);
+
+    let mut response = format!(
+        "HTTP/1.1 {status} {}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        reason(status),
+        body.len(),
+    );
+
+    if status == 405 {
+        response.push_str("Allow: GET, HEAD\r\n");
+    }
+
+    response.push_str("\r\n");
+    if !request.starts_with("HEAD ") {
+        response.push_str(&body);
+    }
+
+    stream.write_all(response.as_bytes()).with_context(|| anyhow!("Unable to send response"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn get(stream: &mut dyn Connection, request: &str) -> String {
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_route() {
+        let shared = Shared::default();
+        assert_eq!(route("GET /metrics HTTP/1.1\r\n", &shared).0, 503);
+        assert_eq!(route("POST /json HTTP/1.1\r\n", &shared).0, 405);
+        assert_eq!(route("GET /other HTTP/1.1\r\n", &shared).0, 404);
+        assert_eq!(route("GET / HTTP/1.1\r\n", &shared).0, 200);
+
+        *shared.latest.lock().unwrap() = Some(Snapshot { metrics: "m 1".into(), json: "{}".into() });
+        assert_eq!(route("GET /metrics?x=1 HTTP/1.1\r\n", &shared), (200, PROMETHEUS_TYPE, "m 1\n".into()));
+        assert_eq!(route("HEAD /json HTTP/1.0\r\n", &shared), (200, JSON_TYPE, "{}".into()));
+    }
+
+    #[test]
+    fn test_tcp() {
+        let config: HttpConfig = toml::from_str("port = 0").unwrap();
+        let server = HttpServer::start(&config).unwrap();
+        let Listener::Tcp(listener) = server.listener.as_ref() else {
+            panic!("not listening on tcp");
+        };
+
+        let address = listener.local_addr().unwrap();
+        server.update(Snapshot { metrics: "kelvin_sensor_value{name=\"cpu\"} 45".into(), json: "{\"readings\":[]}".into() });
+
+        let response = get(&mut TcpStream::connect(address).unwrap(), "GET /json HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("\r\nContent-Type: application/json\r\n"), "{response}");
+        assert!(response.ends_with("\r\n\r\n{\"readings\":[]}"), "{response}");
+
+        let response = get(&mut TcpStream::connect(address).unwrap(), "HEAD /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.ends_with("\r\n\r\n"), "{response}");
+
+        // listener is closed so the port can be bound again
+        drop(server);
+        TcpListener::bind(address).unwrap();
+    }
+
+    #[test]
+    fn test_unix() {
+        let dir = TempDir::new("http");
+        let path = dir.join("http.sock");
+        let config: HttpConfig = toml::from_str(&format!("socket = {path:?}")).unwrap();
+        let server = HttpServer::start(&config).unwrap();
+
+        let response = get(&mut UnixStream::connect(&path).unwrap(), "GET /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{response}");
+
+        drop(server);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,166 @@
+//! Expansion of environment variables in config values, like `$HOME` or `${HWMON:-/sys/class/hwmon}`
+
+use crate::prelude::*;
+use serde_json::Value as JsonValue;
+
+/// Expand variables in the text, `$$` is a literal dollar sign
+///
+/// Dollar sign that is not followed by a name is kept, so `$1` or `$(cmd)` in shell commands stay as they are
+pub fn expand(text: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+            continue;
+        }
+
+        if let Some(after) = rest.strip_prefix('{') {
+            let Some(end) = after.find('}') else {
+                bail!("Unterminated ${{ in {text:?}");
+            };
+
+            let (name, default) = match after[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&after[..end], None),
+            };
+
+            if !is_name(name) {
+                bail!("Invalid variable name {name:?} in {text:?}");
+            }
+
+            // like in shell the default is also used for empty variables
+            match (lookup(name).filter(|x| !x.is_empty() || default.is_none()), default) {
+                (Some(value), _) => result.push_str(&value),
+                (None, Some(default)) => result.push_str(default),
+                (None, None) => bail!("Environment variable {name:?} is not set"),
+            }
+
+            rest = &after[end + 1..];
+            continue;
+        }
+
+        let len = rest.find(|x: char| !x.is_ascii_alphanumeric() && x != '_').unwrap_or(rest.len());
+        let name = &rest[..len];
+
+        if !is_name(name) {
+            result.push('$');
+            continue;
+        }
+
+        match lookup(name) {
+            Some(value) => result.push_str(&value),
+            None => bail!("Environment variable {name:?} is not set"),
+        }
+
+        rest = &rest[len..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|x: char| x.is_ascii_alphabetic() || x == '_')
+        && name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_')
+}
+
+/// Key of a command like `command` or `alarm_command`
+fn is_command(name: &str) -> bool {
+    name == "command" || name.ends_with("_command")
+}
+
+/// Expand variables in all strings of the value, `key` is where it is in the config for errors
+///
+/// Items of arrays are named by their `name` if they have one, like `sensors.cpu.path`
+///
+/// Commands are left as they are, they get variables like `$KELVIN_VALUE` only when they run
+pub fn expand_value(value: &mut JsonValue, key: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<()> {
+    let join = |x: &str| if key.is_empty() { x.to_string() } else { format!("{key}.{x}") };
+
+    match value {
+        JsonValue::String(x) => {
+            *x = expand(x, lookup).with_context(|| anyhow!("Unable to expand {key}"))?;
+        },
+        JsonValue::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                let key = match item.get("name").and_then(|x| x.as_str()) {
+                    Some(name) => join(name),
+                    None => format!("{key}[{i}]"),
+                };
+
+                expand_value(item, &key, lookup)?;
+            }
+        },
+        JsonValue::Object(map) => {
+            for (name, item) in map.iter_mut().filter(|(name, _)| !is_command(name)) {
+                expand_value(item, &join(name), lookup)?;
+            }
+        },
+        _ => {},
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/user".into()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let expand = |x: &str| expand(x, &lookup);
+
+        assert_eq!(expand("$HOME/kelvin.csv").unwrap(), "/home/user/kelvin.csv");
+        assert_eq!(expand("${HOME}data").unwrap(), "/home/userdata");
+        assert_eq!(expand("${HWMON:-/sys/class/hwmon}/temp1").unwrap(), "/sys/class/hwmon/temp1");
+        assert_eq!(expand("${EMPTY:-default}").unwrap(), "default");
+        assert_eq!(expand("${EMPTY}").unwrap(), "");
+        assert_eq!(expand("cost $$5, $1 $(date) $").unwrap(), "cost $5, $1 $(date) $");
+
+        assert!(expand("$MISSING/a").unwrap_err().to_string().contains("\"MISSING\""));
+        assert!(expand("${MISSING}").is_err());
+        assert!(expand("${HOME").is_err());
+        assert!(expand("${1x}").is_err());
+    }
+
+    #[test]
+    fn test_expand_value() {
+        let mut value = json!({
+            "log_csv": "$HOME/kelvin.csv",
+            "poll_rate": 1000,
+            "sensors": [{ "name": "cpu", "path": "${HWMON:-/sys}/temp1_input", "command": ["echo", "$HOME"] }],
+            "alarm_command": ["sh", "-c", "logger hot $KELVIN_VALUE $$"],
+        });
+
+        expand_value(&mut value, "", &lookup).unwrap();
+        assert_eq!(value, json!({
+            "log_csv": "/home/user/kelvin.csv",
+            "poll_rate": 1000,
+            "sensors": [{ "name": "cpu", "path": "/sys/temp1_input", "command": ["echo", "$HOME"] }],
+            "alarm_command": ["sh", "-c", "logger hot $KELVIN_VALUE $$"],
+        }));
+
+        let mut value = json!({ "sensors": [{ "name": "gpu", "path": "$HWMON/temp1_input" }] });
+        let err = format!("{:#}", expand_value(&mut value, "", &lookup).unwrap_err());
+        assert!(err.contains("sensors.gpu.path") && err.contains("\"HWMON\""), "{err}");
+
+        let mut value = json!({ "sound": ["a", "$X"] });
+        let err = format!("{:#}", expand_value(&mut value, "", &lookup).unwrap_err());
+        assert!(err.contains("sound[1]"), "{err}");
+    }
+}
@@ -0,0 +1,152 @@
+use crate::prelude::*;
+use std::io::Read;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// How often to check if the command has exited
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+fn spawn_error(err: std::io::Error, program: &str, name: &str) -> anyhow::Error {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => anyhow!("Command {program:?} not found in PATH"),
+        _ => anyhow!(err).context(format!("Unable to run command {name:?}")),
+    }
+}
+
+/// Run the command and capture its stdout, killing it if it runs longer than `timeout`
+pub fn run(command: &[String], timeout: Duration) -> Result<String> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("Command is empty");
+    };
+
+    let name = command.join(" ");
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| spawn_error(err, program, &name))?;
+
+    // read in the background so the command cannot block on a full pipe
+    fn reader(mut pipe: impl Read + Send + 'static) -> std::thread::JoinHandle<std::io::Result<Vec<u8>>> {
+        std::thread::spawn(move || {
+            let mut buffer = vec![];
+            pipe.read_to_end(&mut buffer).map(|_| buffer)
+        })
+    }
+
+    let stdout = reader(child.stdout.take().unwrap());
+    let stderr = reader(child.stderr.take().unwrap());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait().with_context(|| anyhow!("Unable to wait for command {name:?}"))? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            // ignoring errors as the command may have exited in the meantime
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("Command {name:?} timed out after {}ms", timeout.as_millis());
+        }
+
+        std::thread::sleep(WAIT_INTERVAL);
+    };
+
+    let stdout = stdout.join()
+        .map_err(|_| anyhow!("Unable to read output of command {name:?}"))?
+        .with_context(|| anyhow!("Unable to read output of command {name:?}"))?;
+
+    if !status.success() {
+        let stderr = stderr.join().ok().and_then(|x| x.ok()).unwrap_or_default();
+
+        bail!("Command {name:?} exited with {status}: {}", String::from_utf8_lossy(&stderr).trim());
+    }
+
+    String::from_utf8(stdout)
+        .with_context(|| anyhow!("Output of command {name:?} is not valid UTF-8"))
+}
+
+/// Command running in the background
+#[derive(Debug)]
+pub struct Background {
+    name: String,
+    child: Child,
+}
+
+impl Background {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Exit status if the command has exited, does not block
+    pub fn finished(&mut self) -> Result<Option<ExitStatus>> {
+        self.child.try_wait()
+            .with_context(|| anyhow!("Unable to wait for command {:?}", self.name))
+    }
+}
+
+/// Start the command without waiting for it, stderr is inherited so it ends up in the log
+pub fn spawn(command: &[String], env: &[(&str, String)]) -> Result<Background> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("Command is empty");
+    };
+
+    let name = command.join(" ");
+
+    let child = Command::new(program)
+        .args(args)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|err| spawn_error(err, program, &name))?;
+
+    Ok(Background { name, child })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_run() {
+        let timeout = Duration::from_secs(5);
+
+        assert_eq!(run(&command(&["echo", "42"]), timeout).unwrap(), "42\n");
+
+        let err = run(&command(&["sh", "-c", "echo oops >&2; exit 3"]), timeout).unwrap_err().to_string();
+        assert!(err.contains("sh -c") && err.contains("oops"), "{err}");
+
+        let err = run(&command(&["sleep", "10"]), Duration::from_millis(100)).unwrap_err().to_string();
+        assert!(err.contains("timed out"), "{err}");
+
+        let err = run(&command(&["kelvin-does-not-exist"]), timeout).unwrap_err().to_string();
+        assert!(err.contains("not found in PATH"), "{err}");
+
+        assert!(run(&[], timeout).is_err());
+    }
+
+    #[test]
+    fn test_spawn() {
+        let mut child = spawn(&command(&["sh", "-c", "exit $CODE"]), &[("CODE", "3".to_string())]).unwrap();
+
+        let status = loop {
+            if let Some(status) = child.finished().unwrap() {
+                break status;
+            }
+
+            std::thread::sleep(WAIT_INTERVAL);
+        };
+
+        assert_eq!(status.code(), Some(3));
+        assert!(spawn(&command(&["kelvin-does-not-exist"]), &[]).is_err());
+    }
+}
@@ -0,0 +1,570 @@
+//! Control of fans by writing values of sensors to their PWM files each tick
+
+use crate::prelude::*;
+use crate::alarm::{ActiveAlarm, Severity};
+use crate::config::Output;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Value of `pwmN_enable` for manual control
+const MANUAL: &str = "1";
+
+/// Value of `pwmN_enable` for automatic control by the chip, for most drivers
+const AUTOMATIC: &str = "2";
+
+/// State of a PWM channel before kelvin took control of it
+#[derive(Debug, Clone)]
+struct SavedChannel {
+    path: PathBuf,
+    enable: String,
+    pwm: String,
+}
+
+/// Channels to restore on exit, global so the panic hook can reach them
+static SAVED: Mutex<Vec<SavedChannel>> = Mutex::new(vec![]);
+
+/// Path of the mode file of the channel, `pwm1_enable` for `pwm1`
+fn enable_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push("_enable");
+    path.with_file_name(name)
+}
+
+/// Switch the channel to manual mode, many drivers ignore writes otherwise
+///
+/// Original mode and value are saved for `restore`, files without a mode file are left alone
+pub fn take_control(path: &Path) -> Result<()> {
+    let enable = enable_path(path);
+    if !enable.exists() {
+        return Ok(());
+    }
+
+    let read = |x: &Path| std::fs::read_to_string(x)
+        .map(|x| x.trim().to_string())
+        .with_context(|| anyhow!("Unable to read {x:?}"));
+
+    let saved = SavedChannel { path: path.to_path_buf(), enable: read(&enable)?, pwm: read(path)? };
+
+    std::fs::write(&enable, MANUAL)
+        .with_context(|| anyhow!("Unable to switch {path:?} to manual mode"))?;
+
+    let mut channels = SAVED.lock().unwrap_or_else(|x| x.into_inner());
+    if !channels.iter().any(|x| x.path == saved.path) {
+        channels.push(saved);
+    }
+
+    Ok(())
+}
+
+/// Put all channels back the way they were before `take_control`, calling it again does nothing
+pub fn restore() -> Vec<anyhow::Error> {
+    // the lock may be held by the thread that panicked
+    let channels = match SAVED.try_lock() {
+        Ok(mut x) => std::mem::take(&mut *x),
+        Err(std::sync::TryLockError::Poisoned(x)) => std::mem::take(&mut *x.into_inner()),
+        Err(std::sync::TryLockError::WouldBlock) => return vec![anyhow!("Unable to restore PWM channels, they are in use")],
+    };
+
+    let mut errors = vec![];
+
+    for channel in channels {
+        // value is written while still in manual mode, automatic mode may reject it
+        let result = std::fs::write(&channel.path, &channel.pwm)
+            .and_then(|_| std::fs::write(enable_path(&channel.path), &channel.enable))
+            .with_context(|| anyhow!("Unable to restore PWM channel {:?}", channel.path));
+
+        match result {
+            Ok(()) => crate::log::info!("Restored PWM channel {:?}", channel.path),
+            Err(err) => errors.push(err),
+        }
+    }
+
+    errors
+}
+
+/// Switch the channel to automatic mode, for channels left in manual mode by a crashed run
+pub fn restore_automatic(path: &Path) -> Result<()> {
+    // the mode file itself works too
+    let enable = match path.to_string_lossy().ends_with("_enable") {
+        true => path.to_path_buf(),
+        false => enable_path(path),
+    };
+
+    if !enable.exists() {
+        bail!("PWM channel {path:?} has no mode file {enable:?}");
+    }
+
+    std::fs::write(&enable, AUTOMATIC)
+        .with_context(|| anyhow!("Unable to switch {path:?} to automatic mode"))
+}
+
+/// Decides the value of the output from the sensor value, with `hysteresis`, `min_start` and `stop_below`
+#[derive(Debug, Default)]
+pub struct Controller {
+    /// Sensor value the current duty was set at, and the duty
+    applied: Option<(f64, u8)>,
+
+    /// `min_start` was applied on the last tick, so the curve value follows
+    kicked: bool,
+}
+
+impl Controller {
+    /// Value for this tick, true if it should be written even if it is within `step`
+    pub fn next(&mut self, config: &Output, value: f64) -> (u8, bool) {
+        let target = match config.stop_below {
+            Some(x) if value < x => 0,
+            _ => config.target(value),
+        };
+
+        if self.kicked {
+            self.kicked = false;
+            self.applied = Some((value, target));
+            return (target, true);
+        }
+
+        if let Some((at, duty)) = self.applied && (value - at).abs() <= config.hysteresis {
+            return (duty, false);
+        }
+
+        // fan may be stopped when the previous duty is not known
+        let stopped = self.applied.is_none_or(|(_, duty)| duty == 0);
+
+        if let Some(min_start) = config.min_start && stopped && target > 0 && target < min_start {
+            self.kicked = true;
+            self.applied = Some((value, min_start));
+            return (min_start, true);
+        }
+
+        self.applied = Some((value, target));
+        (target, false)
+    }
+}
+
+/// Output with the value last written to it
+#[derive(Debug)]
+pub struct FanOutput {
+    pub config: Output,
+    control: Controller,
+
+    /// Nothing is written until the first successful write
+    last: Option<u8>,
+
+    /// Ticks in a row the source could not be read
+    failures: u32,
+
+    /// Forcing `failsafe.pwm` as the source could not be read
+    failsafe: bool,
+
+    /// Value being lowered towards the target after the failsafe, by `failsafe.ramp` each tick
+    ramp: Option<u8>,
+
+    /// Value computed on the last tick, written or not
+    target: Option<u8>,
+}
+
+/// State of an output for `kelvin status`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputStatus {
+    pub name: String,
+
+    /// Value last written, none if nothing was written yet
+    pub value: Option<u8>,
+
+    /// Value computed on the last tick, it is not written when within `step`
+    pub target: Option<u8>,
+
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub failsafe: bool,
+
+    /// Values are only logged, `value` is what would have been written
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+}
+
+impl FanOutput {
+    pub fn new(config: Output) -> Self {
+        Self { config, control: Controller::default(), last: None, failures: 0, failsafe: false, ramp: None, target: None }
+    }
+
+    fn write(&mut self, value: u8) -> Result<Option<u8>> {
+        if self.config.dry_run {
+            crate::log::info!("Output {:?} would write {value} to {:?}", self.config.name, self.config.path);
+        } else {
+            // sysfs wants the plain number without a newline
+            std::fs::write(&self.config.path, value.to_string())
+                .with_context(|| anyhow!("Unable to write {value} to output {:?} at {:?}", self.config.name, self.config.path))?;
+        }
+
+        self.last = Some(value);
+        Ok(Some(value))
+    }
+
+    /// Write the target for the value if it moved more than `step`, returns the value if it was written
+    ///
+    /// Value is none when the source failed, after `failsafe.after_failures` of those the failsafe value is forced
+    pub fn update(&mut self, value: Option<f64>) -> Result<Option<u8>> {
+        let Some(value) = value else {
+            self.failures += 1;
+
+            if self.failures < self.config.failsafe.after_failures {
+                return Ok(None);
+            }
+
+            self.failsafe = true;
+            self.ramp = None;
+            self.target = Some(self.config.failsafe.pwm);
+
+            return match self.last == Some(self.config.failsafe.pwm) {
+                true => Ok(None),
+                false => self.write(self.config.failsafe.pwm),
+            };
+        };
+
+        self.failures = 0;
+        if std::mem::take(&mut self.failsafe) {
+            self.ramp = self.last;
+        }
+
+        let (target, force) = self.control.next(&self.config, value);
+        self.target = Some(target);
+
+        // going up is not limited, the fan should not be slower than the curve
+        if let Some(current) = self.ramp {
+            let next = current.saturating_sub(self.config.failsafe.ramp).max(target);
+            self.ramp = (next > target).then_some(next);
+            self.target = Some(next);
+
+            return match self.last == Some(next) {
+                true => Ok(None),
+                false => self.write(next),
+            };
+        }
+
+        if !force && self.last.is_some_and(|x| x.abs_diff(target) <= self.config.step) {
+            return Ok(None);
+        }
+
+        self.write(target)
+    }
+
+    /// Critical alarm while the failsafe value is forced
+    pub fn alarm(&self) -> Option<ActiveAlarm> {
+        self.failsafe.then(|| ActiveAlarm {
+            severity: Severity::Critical,
+            message: format!(
+                "Output {:?} is forced to {} as {} failed {} times in a row",
+                self.config.name, self.config.failsafe.pwm, describe_sources(&self.config), self.failures,
+            ),
+        })
+    }
+
+    pub fn status(&self) -> OutputStatus {
+        OutputStatus {
+            name: self.config.name.clone(),
+            value: self.last,
+            target: self.target,
+            failsafe: self.failsafe,
+            dry_run: self.config.dry_run,
+        }
+    }
+}
+
+/// Sources of the output for messages, like `sensors "cpu", "gpu"`
+fn describe_sources(config: &Output) -> String {
+    let names = config.sources().iter().map(|x| format!("{x:?}")).collect::<Vec<_>>().join(", ");
+
+    match config.sources().len() {
+        1 => format!("sensor {names}"),
+        _ => format!("sensors {names}"),
+    }
+}
+
+/// Create outputs of the config, taking control of their PWM channels unless it is a dry run
+pub fn outputs(outputs: &[Output], dry_run: bool) -> Result<Vec<FanOutput>> {
+    let mut created = vec![];
+
+    for output in outputs {
+        let mut output = output.clone();
+        output.dry_run |= dry_run;
+
+        if !output.dry_run {
+            take_control(&output.path)
+                .with_context(|| anyhow!("Unable to take control of output {:?}", output.name))?;
+        }
+
+        created.push(FanOutput::new(output));
+    }
+
+    Ok(created)
+}
+
+/// Update all outputs from the values of sensors read this tick, `notify` sends notifications for the failsafe
+pub fn update(outputs: &mut [FanOutput], values: &HashMap<String, f64>, notify: bool) -> Vec<anyhow::Error> {
+    let mut errors = vec![];
+
+    for output in outputs {
+        let was_failsafe = output.failsafe;
+
+        match output.update(output.config.value(values)) {
+            Ok(Some(x)) => crate::log::debug!("Output {:?} set to {x}", output.config.name),
+            Ok(None) => {},
+            Err(err) => errors.push(err),
+        }
+
+        let message = match (was_failsafe, output.alarm()) {
+            (false, Some(alarm)) => alarm.message,
+            (true, None) => format!("Output {:?} can read {} again", output.config.name, describe_sources(&output.config)),
+            _ => continue,
+        };
+
+        match output.failsafe {
+            true => crate::log::error!("{message}"),
+            false => crate::log::info!("{message}"),
+        }
+
+        if notify {
+            let result = match output.failsafe {
+                true => crate::notify::send("Kelvin fan failsafe", &message, crate::notify::Urgency::Critical),
+                false => crate::notify::send("Kelvin fan failsafe cleared", &message, crate::notify::Urgency::Normal),
+            };
+
+            if let Err(err) = result {
+                errors.push(err);
+            }
+        }
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::config::SensorMap;
+
+    #[test]
+    fn test_update() {
+        let dir = TempDir::new("fan");
+
+        let path = dir.join("pwm1");
+        let mut output = FanOutput::new(Output {
+            name: "case".into(),
+            source: "cpu".into(),
+            sources: vec![],
+            combine: None,
+            path: path.clone(),
+            map: Some(SensorMap { input: Some((40.0, 80.0)), output: (0.0, 255.0) }),
+            curve: None,
+            step: 5,
+            hysteresis: 0.0,
+            min_start: None,
+            stop_below: None,
+            failsafe: Default::default(),
+            dry_run: false,
+        });
+
+        let read = || std::fs::read_to_string(&path).unwrap();
+
+        assert_eq!(output.update(Some(60.0)).unwrap(), Some(128));
+        assert_eq!(read(), "128");
+
+        // changes within the step are not written
+        assert_eq!(output.update(Some(60.5)).unwrap(), None);
+        assert_eq!(output.update(Some(61.0)).unwrap(), Some(134));
+
+        // clamped to the range
+        assert_eq!(output.update(Some(100.0)).unwrap(), Some(255));
+        assert_eq!(output.update(Some(0.0)).unwrap(), Some(0));
+        assert_eq!(read(), "0");
+
+        let values = HashMap::from([("cpu".to_string(), 80.0)]);
+        assert!(update(std::slice::from_mut(&mut output), &values, false).is_empty());
+        assert_eq!(read(), "255");
+
+        // sensor failed so nothing is written until the failsafe
+        assert!(update(std::slice::from_mut(&mut output), &HashMap::new(), false).is_empty());
+        assert_eq!(output.last, Some(255));
+
+        output.config.path = dir.join("missing/pwm1");
+        let errors = update(std::slice::from_mut(&mut output), &HashMap::from([("cpu".to_string(), 40.0)]), false);
+        assert!(format!("{:#}", errors[0]).contains("Unable to write 0 to output \"case\""));
+    }
+
+    /// Values the controller gives for the sequence of temperatures
+    fn run(config: &Output, values: &[f64]) -> Vec<u8> {
+        let mut control = Controller::default();
+        values.iter().map(|x| control.next(config, *x).0).collect()
+    }
+
+    fn output(hysteresis: f64, min_start: Option<u8>, stop_below: Option<f64>) -> Output {
+        Output {
+            name: "case".into(),
+            source: "cpu".into(),
+            sources: vec![],
+            combine: None,
+            path: PathBuf::new(),
+            map: None,
+            curve: Some(vec![(30.0, 0.0), (80.0, 250.0)].try_into().unwrap()),
+            step: 0,
+            hysteresis,
+            min_start,
+            stop_below,
+            failsafe: Default::default(),
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let config = output(2.0, None, None);
+
+        // small changes around the plateau keep the duty
+        assert_eq!(run(&config, &[50.0, 51.0, 49.0, 52.0, 52.5, 50.5, 49.9]), [100, 100, 100, 100, 113, 113, 100]);
+    }
+
+    #[test]
+    fn test_min_start() {
+        let config = output(0.0, Some(80), None);
+
+        // kicked when starting and settling on the next tick, also after stopping again
+        assert_eq!(run(&config, &[32.0, 32.0, 34.0, 30.0, 33.0, 35.0]), [80, 10, 20, 0, 80, 25]);
+
+        // high enough to start on its own
+        assert_eq!(run(&config, &[50.0, 30.0, 50.0]), [100, 0, 100]);
+
+        let mut control = Controller::default();
+        assert_eq!(control.next(&config, 32.0), (80, true));
+        assert_eq!(control.next(&config, 32.0), (10, true));
+        assert_eq!(control.next(&config, 32.0), (10, false));
+    }
+
+    #[test]
+    fn test_stop_below() {
+        let config = output(1.0, Some(80), Some(40.0));
+
+        // stops below the limit, starts again only past the hysteresis
+        assert_eq!(run(&config, &[45.0, 44.5, 39.5, 40.2, 41.0, 42.0]), [80, 73, 0, 0, 80, 60]);
+    }
+
+    #[test]
+    fn test_failsafe() {
+        let dir = TempDir::new("fan-failsafe");
+
+        let mut config = output(0.0, None, None);
+        config.path = dir.join("pwm1");
+        config.failsafe = crate::config::Failsafe { after_failures: 2, pwm: 250, ramp: 50 };
+
+        let mut output = FanOutput::new(config);
+        let mut tick = |value: Option<f64>| {
+            output.update(value).unwrap();
+            (std::fs::read_to_string(dir.join("pwm1")).unwrap(), output.alarm().is_some())
+        };
+
+        assert_eq!(tick(Some(40.0)), ("50".into(), false));
+        assert_eq!(tick(None), ("50".into(), false));
+        assert_eq!(tick(None), ("250".into(), true));
+        assert_eq!(tick(None), ("250".into(), true));
+
+        // ramps down to the curve instead of dropping right away
+        assert_eq!(tick(Some(40.0)), ("200".into(), false));
+        assert_eq!(tick(Some(40.0)), ("150".into(), false));
+
+        // hotter than the ramp ends it
+        assert_eq!(tick(Some(60.0)), ("150".into(), false));
+        assert_eq!(tick(Some(40.0)), ("50".into(), false));
+
+        assert_eq!(output.status(), OutputStatus { name: "case".into(), value: Some(50), target: Some(50), failsafe: false, dry_run: false });
+    }
+
+    #[test]
+    fn test_sources() {
+        let dir = TempDir::new("fan-sources");
+
+        let mut config = output(0.0, None, None);
+        config.path = dir.join("pwm1");
+        config.source = String::new();
+        config.sources = vec!["cpu".into(), "gpu".into()];
+        config.failsafe = crate::config::Failsafe { after_failures: 1, pwm: 250, ramp: 255 };
+
+        let mut outputs = vec![FanOutput::new(config)];
+        let mut tick = |values: &[(&str, f64)]| {
+            let values = values.iter().map(|(name, x)| (name.to_string(), *x)).collect();
+            assert!(update(&mut outputs, &values, false).is_empty());
+            (std::fs::read_to_string(dir.join("pwm1")).unwrap(), outputs[0].alarm().map(|x| x.message))
+        };
+
+        // follows the hotter one
+        assert_eq!(tick(&[("cpu", 40.0), ("gpu", 60.0)]), ("150".into(), None));
+
+        // falls back to the ones that can still be read
+        assert_eq!(tick(&[("cpu", 40.0)]), ("50".into(), None));
+
+        let (pwm, alarm) = tick(&[]);
+        assert_eq!(pwm, "250");
+        assert_eq!(alarm.unwrap(), "Output \"case\" is forced to 250 as sensors \"cpu\", \"gpu\" failed 1 times in a row");
+
+        assert_eq!(tick(&[("gpu", 50.0)]), ("100".into(), None));
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let dir = TempDir::new("fan-dry");
+
+        let pwm = dir.join("pwm1");
+        std::fs::write(&pwm, "90").unwrap();
+        std::fs::write(dir.join("pwm1_enable"), "2").unwrap();
+
+        let mut config = output(0.0, None, None);
+        config.path = pwm.clone();
+        config.step = 10;
+
+        let mut outputs = outputs(&[config], true).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("pwm1_enable")).unwrap(), "2");
+
+        let values = |x: f64| HashMap::from([("cpu".to_string(), x)]);
+        assert!(update(&mut outputs, &values(54.0), false).is_empty());
+        assert!(update(&mut outputs, &values(55.0), false).is_empty());
+
+        assert_eq!(std::fs::read_to_string(&pwm).unwrap(), "90");
+        assert_eq!(outputs[0].status(), OutputStatus { name: "case".into(), value: Some(120), target: Some(125), failsafe: false, dry_run: true });
+    }
+
+    #[test]
+    fn test_restore() {
+        let dir = TempDir::new("fan-restore");
+
+        let pwm = dir.join("pwm1");
+        let enable = dir.join("pwm1_enable");
+        std::fs::write(&pwm, "90\n").unwrap();
+        std::fs::write(&enable, "2\n").unwrap();
+
+        let read = |x: &Path| std::fs::read_to_string(x).unwrap();
+
+        take_control(&pwm).unwrap();
+        assert_eq!(read(&enable), "1");
+
+        // the original state is kept when taking control again
+        std::fs::write(&pwm, "200").unwrap();
+        take_control(&pwm).unwrap();
+
+        assert!(restore().is_empty());
+        assert_eq!((read(&pwm).as_str(), read(&enable).as_str()), ("90", "2"));
+
+        // nothing left to restore
+        std::fs::write(&enable, "1").unwrap();
+        assert!(restore().is_empty());
+        assert_eq!(read(&enable), "1");
+
+        restore_automatic(&pwm).unwrap();
+        assert_eq!(read(&enable), "2");
+
+        // files without a mode file are not touched
+        let plain = dir.join("value");
+        std::fs::write(&plain, "5").unwrap();
+        take_control(&plain).unwrap();
+        assert!(restore_automatic(&plain).is_err());
+    }
+}
@@ -0,0 +1,319 @@
+//! Import of `/etc/fancontrol` written by `pwmconfig` from lm_sensors
+//!
+//! Paths in the file are relative to `/sys/class/hwmon` like `hwmon1/pwm1`, each directive holds
+//! space separated `pwm=value` pairs
+
+use crate::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+pub const DEFAULT_PATH: &str = "/etc/fancontrol";
+
+const HWMON_DIR: &str = "/sys/class/hwmon";
+
+/// Settings of a single pwm channel
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Channel {
+    /// Path of the pwm file like `hwmon1/pwm1`
+    pub pwm: String,
+
+    /// Temperature inputs, fancontrol uses the highest of them
+    pub temps: Vec<String>,
+    pub min_temp: Option<f64>,
+    pub max_temp: Option<f64>,
+    pub min_start: Option<u8>,
+    pub min_stop: Option<u8>,
+    pub min_pwm: Option<u8>,
+    pub max_pwm: Option<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fancontrol {
+    /// Seconds between updates
+    pub interval: Option<u64>,
+
+    /// Chip names by hwmon directory like `hwmon1 = it8728`
+    pub devnames: BTreeMap<String, String>,
+    pub channels: Vec<Channel>,
+
+    /// Directives and values that have no equivalent in kelvin
+    pub warnings: Vec<String>,
+}
+
+impl Fancontrol {
+    fn channel(&mut self, pwm: &str) -> &mut Channel {
+        let index = match self.channels.iter().position(|x| x.pwm == pwm) {
+            Some(x) => x,
+            None => {
+                self.channels.push(Channel { pwm: pwm.to_string(), ..Default::default() });
+                self.channels.len() - 1
+            },
+        };
+
+        &mut self.channels[index]
+    }
+}
+
+pub fn parse(text: &str) -> Result<Fancontrol> {
+    let mut fancontrol = Fancontrol::default();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("Line {}: expected DIRECTIVE=value, found {line:?}", i + 1);
+        };
+
+        let (key, value) = (key.trim(), value.trim());
+
+        if key == "INTERVAL" {
+            let interval = value.parse().with_context(|| anyhow!("Line {}: invalid INTERVAL {value:?}", i + 1))?;
+            fancontrol.interval = Some(interval);
+            continue;
+        }
+
+        let pairs = value.split_whitespace()
+            .map(|x| x.split_once('=').ok_or_else(|| anyhow!("Line {}: expected path=value in {key}, found {x:?}", i + 1)))
+            .collect::<Result<Vec<_>>>()?;
+
+        for (name, value) in pairs {
+            let temp = || -> Result<f64> {
+                value.parse().with_context(|| anyhow!("Line {}: invalid {key} {value:?} for {name}", i + 1))
+            };
+
+            let pwm = || -> Result<u8> {
+                value.parse().with_context(|| anyhow!("Line {}: {key} {value:?} for {name} is not a number in 0-255", i + 1))
+            };
+
+            match key {
+                "DEVNAME" => {
+                    fancontrol.devnames.insert(name.to_string(), value.to_string());
+                },
+                "FCTEMPS" => fancontrol.channel(name).temps = value.split('+').map(str::to_string).collect(),
+                "MINTEMP" => fancontrol.channel(name).min_temp = Some(temp()?),
+                "MAXTEMP" => fancontrol.channel(name).max_temp = Some(temp()?),
+                "MINSTART" => fancontrol.channel(name).min_start = Some(pwm()?),
+                "MINSTOP" => fancontrol.channel(name).min_stop = Some(pwm()?),
+                "MINPWM" => fancontrol.channel(name).min_pwm = Some(pwm()?),
+                "MAXPWM" => fancontrol.channel(name).max_pwm = Some(pwm()?),
+                "DEVPATH" => fancontrol.warnings.push(format!("DEVPATH {name}={value} is not checked, output paths depend on hwmon numbering")),
+                "FCFANS" => fancontrol.warnings.push(format!("FCFANS {name}={value} is ignored, fan speed is not monitored")),
+                _ => fancontrol.warnings.push(format!("{key} {name}={value} is not supported")),
+            }
+        }
+    }
+
+    Ok(fancontrol)
+}
+
+/// Path as an absolute path in sysfs
+fn sysfs_path(path: &str) -> String {
+    match path.starts_with('/') {
+        true => path.to_string(),
+        false => format!("{HWMON_DIR}/{path}"),
+    }
+}
+
+/// Name usable in format, like `it8728_temp1` or `hwmon1_temp1` without DEVNAME
+fn name(fancontrol: &Fancontrol, path: &str) -> String {
+    let (dir, file) = path.rsplit_once('/').unwrap_or(("", path));
+    let dir = dir.rsplit('/').next().unwrap_or(dir);
+    let chip = fancontrol.devnames.get(dir).map(String::as_str).unwrap_or(dir);
+    let file = file.strip_suffix("_input").unwrap_or(file);
+
+    format!("{chip}_{file}")
+        .to_lowercase()
+        .chars()
+        .map(|x| if x.is_ascii_alphanumeric() { x } else { '_' })
+        .collect()
+}
+
+/// TOML sensor entry for a temperature input, by chip name when DEVNAME is known
+fn sensor(fancontrol: &Fancontrol, path: &str) -> String {
+    let mut out = format!("[[sensors]]\nname = {:?}\n", name(fancontrol, path));
+
+    let chip = path.split_once('/').and_then(|(dir, _)| fancontrol.devnames.get(dir).map(|x| (x, dir)));
+    match chip {
+        Some((chip, dir)) if !path.starts_with('/') => {
+            let file = &path[dir.len() + 1..];
+            writeln!(out, "source = \"hwmon\"\npath = {:?}", format!("{chip}/{file}")).unwrap();
+        },
+        _ => writeln!(out, "source = \"file\"\npath = {:?}", sysfs_path(path)).unwrap(),
+    }
+
+    out.push_str("divisor = 1000\ntemperature = true\n");
+    out
+}
+
+/// Equivalent kelvin TOML, everything that could not be converted is listed as comments at the top
+pub fn to_toml(fancontrol: &Fancontrol) -> String {
+    let mut warnings = fancontrol.warnings.clone();
+    let mut sensors: Vec<(String, String)> = vec![];
+    let mut outputs = vec![];
+
+    for channel in &fancontrol.channels {
+        let (Some(min_temp), Some(max_temp)) = (channel.min_temp, channel.max_temp) else {
+            warnings.push(format!("{} is skipped, it needs both MINTEMP and MAXTEMP", channel.pwm));
+            continue;
+        };
+
+        if channel.temps.is_empty() {
+            warnings.push(format!("{} is skipped, it has no FCTEMPS", channel.pwm));
+            continue;
+        }
+
+        if min_temp >= max_temp {
+            warnings.push(format!("{} is skipped, MINTEMP {min_temp} is not below MAXTEMP {max_temp}", channel.pwm));
+            continue;
+        }
+
+        for temp in &channel.temps {
+            if !sensors.iter().any(|(x, _)| x == temp) {
+                sensors.push((temp.clone(), sensor(fancontrol, temp)));
+            }
+        }
+
+        let min_pwm = channel.min_pwm.unwrap_or(0);
+        let max_pwm = channel.max_pwm.unwrap_or(255);
+
+        // between the temperatures fancontrol starts from MINSTOP, MINPWM is only used below MINTEMP
+        let low = channel.min_stop.map_or(min_pwm, |x| x.max(min_pwm));
+
+        let mut out = format!("[[outputs]]\nname = {:?}\n", name(fancontrol, &channel.pwm));
+
+        // fancontrol follows the hottest input
+        let names = channel.temps.iter().map(|x| name(fancontrol, x)).collect::<Vec<_>>();
+        match names.as_slice() {
+            [single] => writeln!(out, "source = {single:?}").unwrap(),
+            _ => writeln!(out, "sources = {names:?}\ncombine = \"max\"").unwrap(),
+        }
+
+        writeln!(out, "path = {:?}", sysfs_path(&channel.pwm)).unwrap();
+        writeln!(out, "curve = [[{min_temp}, {low}], [{max_temp}, {max_pwm}]]").unwrap();
+
+        if let Some(min_start) = channel.min_start {
+            writeln!(out, "min_start = {min_start}").unwrap();
+        }
+
+        if min_pwm < low {
+            writeln!(out, "stop_below = {min_temp}").unwrap();
+
+            if min_pwm > 0 {
+                warnings.push(format!("{} MINPWM {min_pwm} is written as 0 below MINTEMP", channel.pwm));
+            }
+        }
+
+        outputs.push(out);
+    }
+
+    let mut lines = vec!["# Config imported from fancontrol by `kelvin config import-fancontrol`".to_string()];
+
+    if !warnings.is_empty() {
+        lines.push("#".to_string());
+        lines.extend(warnings.iter().map(|x| format!("# warning: {x}")));
+    }
+
+    lines.push(String::new());
+
+    if let Some(interval) = fancontrol.interval {
+        lines.push(format!("poll_rate = {:?}\n", crate::duration::format(std::time::Duration::from_secs(interval))));
+    }
+
+    lines.extend(sensors.into_iter().map(|(_, x)| x));
+    lines.extend(outputs);
+
+    lines.join("\n")
+}
+
+/// Read fancontrol config and convert it to kelvin TOML
+pub fn import(path: &Path) -> Result<String> {
+    let text = std::fs::read_to_string(path).with_context(|| anyhow!("Unable to read {path:?}"))?;
+    let fancontrol = parse(&text).with_context(|| anyhow!("Unable to parse {path:?}"))?;
+
+    Ok(to_toml(&fancontrol))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    const EXAMPLE: &str = "\
+# Configuration file generated by pwmconfig, changes will be lost
+INTERVAL=10
+DEVPATH=hwmon1=devices/platform/it87.656
+DEVNAME=hwmon1=it8728
+FCTEMPS=hwmon1/pwm1=hwmon1/temp1_input hwmon1/pwm2=hwmon1/temp1_input+hwmon1/temp2_input
+FCFANS=hwmon1/pwm1=hwmon1/fan1_input
+MINTEMP=hwmon1/pwm1=35 hwmon1/pwm2=40
+MAXTEMP=hwmon1/pwm1=60 hwmon1/pwm2=70
+MINSTART=hwmon1/pwm1=150
+MINSTOP=hwmon1/pwm1=100
+MINPWM=hwmon1/pwm2=60
+AVERAGE=hwmon1/pwm1=3
+";
+
+    #[test]
+    fn test_parse() {
+        let fancontrol = parse(EXAMPLE).unwrap();
+
+        assert_eq!(fancontrol.interval, Some(10));
+        assert_eq!(fancontrol.devnames.get("hwmon1").map(String::as_str), Some("it8728"));
+        assert_eq!(fancontrol.channels[0], Channel {
+            pwm: "hwmon1/pwm1".into(),
+            temps: vec!["hwmon1/temp1_input".into()],
+            min_temp: Some(35.0),
+            max_temp: Some(60.0),
+            min_start: Some(150),
+            min_stop: Some(100),
+            min_pwm: None,
+            max_pwm: None,
+        });
+        assert_eq!(fancontrol.channels[1].temps, ["hwmon1/temp1_input", "hwmon1/temp2_input"]);
+        assert_eq!(fancontrol.warnings.len(), 3, "{:?}", fancontrol.warnings);
+
+        assert!(parse("MINTEMP=hwmon1/pwm1").is_err());
+        assert!(parse("MAXPWM=hwmon1/pwm1=300").is_err());
+        assert!(parse("INTERVAL").is_err());
+    }
+
+    #[test]
+    fn test_to_toml() {
+        let text = to_toml(&parse(EXAMPLE).unwrap());
+
+        assert!(text.contains("# warning: AVERAGE hwmon1/pwm1=3 is not supported\n"), "{text}");
+        assert!(text.contains("# warning: FCFANS hwmon1/pwm1=hwmon1/fan1_input is ignored"), "{text}");
+
+        let mut config: Config = toml::from_str(&text).unwrap();
+        config.resolve().unwrap();
+
+        assert_eq!(config.poll_rate, std::time::Duration::from_secs(10));
+        assert_eq!(config.sensors.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(), ["it8728_temp1", "it8728_temp2"]);
+        assert_eq!(config.sensors[0].path, Path::new("it8728/temp1_input"));
+        assert_eq!(config.sensors[0].process(45000.0), 45.0);
+
+        let first = &config.outputs[0];
+        assert_eq!(first.path, Path::new("/sys/class/hwmon/hwmon1/pwm1"));
+        assert_eq!((first.target(30.0), first.target(35.0), first.target(60.0)), (100, 100, 255));
+        assert_eq!((first.min_start, first.stop_below), (Some(150), Some(35.0)));
+
+        // highest of the inputs, MINPWM kept below MINTEMP
+        let second = &config.outputs[1];
+        assert_eq!(second.sources, ["it8728_temp1", "it8728_temp2"]);
+        assert_eq!(second.combine, Some(crate::config::VirtualOp::Max));
+        assert_eq!((second.target(20.0), second.target(70.0)), (60, 255));
+        assert_eq!(second.stop_below, None);
+    }
+
+    #[test]
+    fn test_skipped_channel() {
+        let text = to_toml(&parse("FCTEMPS=hwmon0/pwm1=hwmon0/temp1_input\nMINTEMP=hwmon0/pwm1=40").unwrap());
+
+        assert!(text.contains("# warning: hwmon0/pwm1 is skipped, it needs both MINTEMP and MAXTEMP"), "{text}");
+        assert!(!text.contains("[[outputs]]"), "{text}");
+    }
+}
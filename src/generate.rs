@@ -0,0 +1,182 @@
+use crate::prelude::*;
+use crate::config::{ConfigFormat, Sensor, SensorLabel, SensorSource};
+use serde_json::Value as JsonValue;
+use std::path::Path;
+
+/// Temperature used as max when the chip does not report one
+const DEFAULT_MAX_TEMPERATURE: f64 = 100.0;
+
+/// Turn chip and feature name into a sensor name usable in format, like `k10temp_tctl`
+fn sensor_name(chip: &str, feature: &str) -> String {
+    // drop the bus address, it does not help the user
+    let chip = chip.split('-').next().unwrap_or(chip);
+
+    format!("{chip}_{feature}")
+        .to_lowercase()
+        .chars()
+        .map(|x| if x.is_ascii_alphanumeric() { x } else { '_' })
+        .collect()
+}
+
+/// Find all temperature inputs in lm_sensors output
+pub fn detect_sensors(sensors: &JsonValue) -> Vec<Sensor> {
+    let mut detected: Vec<Sensor> = vec![];
+
+    let Some(chips) = sensors.as_object() else {
+        return detected;
+    };
+
+    for (chip, features) in chips {
+        let Some(features) = features.as_object() else {
+            continue;
+        };
+
+        for (feature, inputs) in features {
+            let Some(inputs) = inputs.as_object() else {
+                // adapter name and such
+                continue;
+            };
+
+            for key in inputs.keys() {
+                let Some(index) = key.strip_prefix("temp").and_then(|x| x.strip_suffix("_input")) else {
+                    continue;
+                };
+
+                // use critical or max temperature reported by the chip
+                let max = [format!("temp{index}_crit"), format!("temp{index}_max")]
+                    .iter()
+                    .find_map(|x| inputs.get(x).and_then(|x| x.as_f64()))
+                    .unwrap_or(DEFAULT_MAX_TEMPERATURE);
+
+                let mut name = sensor_name(chip, feature);
+
+                // names must be unique to be used in format
+                if detected.iter().any(|x| x.name == name) {
+                    name = format!("{name}_{}", detected.len());
+                }
+
+                detected.push(Sensor {
+                    name,
+                    label: Some(SensorLabel {
+                        name: feature.clone(),
+                        unit: None,
+                    }),
+                    min: Some(0.0),
+                    max: Some(max),
+                    round: Some(1),
+                    temperature: true,
+                    source: SensorSource::Sensors,
+                    path: Path::new(chip).join(feature).join(key),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    detected
+}
+
+/// Generate commented starter config, json has no comments so only the settings are written
+pub fn starter_config(sensors: &[Sensor], format: ConfigFormat) -> Result<String> {
+    let poll_rate = crate::duration::format(crate::MINIMAL_POLL_RATE);
+
+    if format == ConfigFormat::Json {
+        let config = serde_json::json!({ "poll_rate": poll_rate, "sensors": sensors });
+        return Ok(serde_json::to_string_pretty(&config)?);
+    }
+
+    let assign = if format == ConfigFormat::Yaml { ":" } else { " =" };
+
+    let mut lines = vec![
+        "# Starter config generated by `kelvin config init`".to_string(),
+        "#".to_string(),
+        "# Custom format, placeholders are names of the sensors, `time` or `cpu_usage`".to_string(),
+    ];
+
+    match sensors.first() {
+        Some(sensor) => lines.push(format!("# format{assign} \"{} {{{}}}\"", sensor.prefix().trim(), sensor.name)),
+        None => lines.push(format!("# format{assign} \"{{time}}\"")),
+    }
+
+    lines.push(String::new());
+    lines.push(format!("# How often to check the sensors\npoll_rate{assign} \"{poll_rate}\""));
+    lines.push(String::new());
+
+    if sensors.is_empty() {
+        lines.push(format!("# No temperature sensors were detected, add them manually\nsensors{assign} []"));
+    } else if format == ConfigFormat::Yaml {
+        lines.push("sensors:".to_string());
+    }
+
+    for sensor in sensors {
+        #[derive(serde::Serialize)]
+        struct Entry<'a> {
+            sensors: [&'a Sensor; 1],
+        }
+
+        let entry = match format {
+            ConfigFormat::Yaml => crate::yaml::to_string(&[sensor]),
+            _ => Ok(toml::to_string(&Entry { sensors: [sensor] })?),
+        };
+
+        let entry = entry.with_context(|| anyhow!("Unable to serialize sensor {:?}", sensor.name))?;
+
+        lines.push(format!("# {}", sensor.path.display()));
+        lines.push(entry);
+    }
+
+    Ok(lines.join("\n"))
+}
+
+/// Write starter config to the path, refusing to overwrite unless forced
+pub fn init(path: &Path, force: bool, format: ConfigFormat) -> Result<()> {
+    if path.exists() && !force {
+        bail!("Config {path:?} already exists, use --force to overwrite it");
+    }
+
+    let sensors = detect_sensors(&crate::get_temps()?);
+    let config = starter_config(&sensors, format)?;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| anyhow!("Unable to create config directory {parent:?}"))?;
+    }
+
+    std::fs::write(path, config)
+        .with_context(|| anyhow!("Unable to write config to {path:?}"))?;
+
+    println!("Written config with {} sensors to {path:?}", sensors.len());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_starter_config() {
+        let sensors: JsonValue = serde_json::from_str(include_str!("../tests/fixtures/sensors.json")).unwrap();
+        let detected = detect_sensors(&sensors);
+
+        let names = detected.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["amdgpu_edge", "k10temp_tccd1", "k10temp_tctl", "nvme_composite"]);
+
+        // uses critical temperature from the chip
+        assert_eq!(detected[0].max, Some(100.0));
+
+        // generated config must be valid in every format
+        for format in [ConfigFormat::Toml, ConfigFormat::Yaml, ConfigFormat::Json] {
+            let text = starter_config(&detected, format).unwrap();
+            let config: Config = format.parse(&text).unwrap_or_else(|err| panic!("{err:#}\n{text}"));
+            assert_eq!(config.sensors.len(), 4);
+            assert_eq!(config.sensors[2].path, Path::new("k10temp-pci-00c3/Tctl/temp1_input"));
+            assert!(config.sensors[0].temperature);
+            assert_eq!(config.poll_rate, crate::MINIMAL_POLL_RATE);
+        }
+
+        let config: Config = ConfigFormat::Yaml.parse(&starter_config(&[], ConfigFormat::Yaml).unwrap()).unwrap();
+        assert!(config.sensors.is_empty());
+    }
+}
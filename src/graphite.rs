@@ -0,0 +1,302 @@
+//! Sends readings to carbon with the Graphite plaintext protocol
+//!
+//! Lines are sent from their own thread, so a slow or missing server never delays the poll loop
+
+use crate::prelude::*;
+use crate::config::{GraphiteConfig, GraphiteProtocol};
+use crate::net::Url;
+use crate::output::Reading;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Ticks waiting for the sender thread, more are dropped
+const QUEUE_SIZE: usize = 64;
+
+/// Lines kept while the connection is down, the oldest are dropped first
+const MAX_PENDING: usize = 10000;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Datagrams are kept below the usual MTU so they are not fragmented
+const MAX_DATAGRAM: usize = 1400;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Split lines into datagrams of whole lines, a longer line gets its own datagram
+fn datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams = vec![];
+    let mut current = String::new();
+
+    for line in lines {
+        if !current.is_empty() && current.len() + line.len() + 1 > MAX_DATAGRAM {
+            datagrams.push(std::mem::take(&mut current));
+        }
+
+        current.push_str(line);
+        current.push('\n');
+    }
+
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+
+    datagrams
+}
+
+/// Sender thread, lines are sent until the sender is dropped
+struct Worker {
+    url: Url,
+    receiver: Receiver<Vec<String>>,
+
+    /// Lines not yet sent over TCP, oldest first
+    pending: VecDeque<String>,
+}
+
+impl Worker {
+    fn queue(&mut self, lines: Vec<String>) {
+        self.pending.extend(lines);
+
+        let excess = self.pending.len().saturating_sub(MAX_PENDING);
+        self.pending.drain(..excess);
+    }
+
+    /// Send lines until the sender is dropped, only returns successfully then
+    fn session(&mut self, stream: &mut dyn Write) -> Result<()> {
+        loop {
+            // kept until written, so they are sent again after reconnecting
+            if !self.pending.is_empty() {
+                let text = self.pending.iter().map(|x| format!("{x}\n")).collect::<String>();
+                stream.write_all(text.as_bytes()).with_context(|| anyhow!("Unable to send to {}", self.url.address()))?;
+                self.pending.clear();
+            }
+
+            match self.receiver.recv() {
+                Ok(lines) => self.queue(lines),
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+
+    fn run_tcp(mut self) {
+        let mut backoff = MIN_BACKOFF;
+
+        loop {
+            let error = match crate::net::connect(&self.url, TIMEOUT, None) {
+                Ok(mut stream) => {
+                    crate::log::info!("Connected to Graphite {}", self.url.address());
+                    backoff = MIN_BACKOFF;
+
+                    match self.session(&mut stream) {
+                        Ok(()) => return,
+                        Err(err) => err.context("Lost connection to Graphite"),
+                    }
+                },
+                Err(err) => err.context("Unable to connect to Graphite"),
+            };
+
+            crate::log::warning!("{error:#}, retrying in {}", crate::duration::format(backoff));
+
+            // lines are kept while waiting, up to a limit
+            let deadline = Instant::now() + backoff;
+            while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+                match self.receiver.recv_timeout(left) {
+                    Ok(lines) => self.queue(lines),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    fn run_udp(self) {
+        let mut socket = None;
+        let mut failing = false;
+
+        while let Ok(lines) = self.receiver.recv() {
+            // resolved again after failures, the address may have changed
+            if socket.is_none() {
+                match udp_socket(&self.url) {
+                    Ok(x) => {
+                        socket = Some(x);
+                        failing = false;
+                    },
+
+                    // warned once until it works again, the log would fill up otherwise
+                    Err(err) if !failing => {
+                        crate::log::warning!("Unable to send to Graphite: {err:#}");
+                        failing = true;
+                    },
+                    Err(_) => {},
+                }
+            }
+
+            let Some(x) = &socket else {
+                continue;
+            };
+
+            for datagram in datagrams(&lines) {
+                if let Err(err) = x.send(datagram.as_bytes()) {
+                    crate::log::debug!("Unable to send to Graphite {}: {err}", self.url.address());
+                    socket = None;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn udp_socket(url: &Url) -> Result<UdpSocket> {
+    let address = (url.host.as_str(), url.port).to_socket_addrs()
+        .with_context(|| anyhow!("Unable to resolve {:?}", url.host))?
+        .next()
+        .with_context(|| anyhow!("No address found for {:?}", url.host))?;
+
+    let local = match address.is_ipv4() {
+        true => "0.0.0.0:0",
+        false => "[::]:0",
+    };
+
+    let socket = UdpSocket::bind(local).with_context(|| anyhow!("Unable to create UDP socket"))?;
+    socket.connect(address).with_context(|| anyhow!("Unable to connect to {}", url.address()))?;
+    Ok(socket)
+}
+
+/// Queues readings for the sender thread
+pub struct Sender {
+    config: GraphiteConfig,
+    hostname: String,
+    sender: Option<SyncSender<Vec<String>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Sender {
+    pub fn start(config: &GraphiteConfig, hostname: &str) -> Result<Self> {
+        let url = Url {
+            scheme: "tcp".into(),
+            host: config.host.clone(),
+            port: config.port,
+            path: "/".into(),
+            username: None,
+            password: None,
+        };
+
+        let (sender, receiver) = std::sync::mpsc::sync_channel(QUEUE_SIZE);
+        let worker = Worker { url, receiver, pending: VecDeque::new() };
+        let protocol = config.protocol;
+
+        let thread = std::thread::Builder::new()
+            .name("graphite".into())
+            .spawn(move || match protocol {
+                GraphiteProtocol::Tcp => worker.run_tcp(),
+                GraphiteProtocol::Udp => worker.run_udp(),
+            })
+            .with_context(|| anyhow!("Unable to start Graphite thread"))?;
+
+        Ok(Self {
+            config: config.clone(),
+            hostname: hostname.to_string(),
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn config(&self) -> &GraphiteConfig {
+        &self.config
+    }
+
+    /// Queue readings of a tick, they are dropped if the sender thread is falling behind
+    pub fn send(&mut self, readings: &[&Reading]) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+
+        let text = crate::output::graphite(readings, &self.config.prefix, &self.hostname, chrono::Utc::now().timestamp());
+        if text.is_empty() {
+            return;
+        }
+
+        match sender.try_send(text.lines().map(String::from).collect()) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {},
+            Err(TrySendError::Full(_)) => crate::log::debug!("Graphite queue is full, dropped {} lines", readings.len()),
+        }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        // closing the channel stops the thread once queued lines are sent
+        self.sender = None;
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::AlarmState;
+    use crate::config::Sensor;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn readings() -> Vec<Reading> {
+        let sensor = Sensor { name: "cpu temp".into(), ..Default::default() };
+        vec![Reading::new(&sensor, 62.5, 62.5, AlarmState::Normal)]
+    }
+
+    #[test]
+    fn test_datagrams() {
+        let lines = ["a".repeat(1000), "b".repeat(300), "c".repeat(200), "d".repeat(2000)];
+        let sizes = datagrams(&lines).iter().map(|x| x.len()).collect::<Vec<_>>();
+        assert_eq!(sizes, [1302, 201, 2001]);
+
+        assert!(datagrams(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut text = String::new();
+            stream.read_to_string(&mut text).unwrap();
+            text
+        });
+
+        let config: GraphiteConfig = toml::from_str(&format!("host = \"127.0.0.1\"\nport = {port}")).unwrap();
+        let mut sender = Sender::start(&config, "my.host").unwrap();
+        sender.send(&readings().iter().collect::<Vec<_>>());
+        drop(sender);
+
+        let text = server.join().unwrap();
+        assert!(text.starts_with("kelvin.my_host.cpu_temp 62.5 "), "{text}");
+        assert!(text.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_udp() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let port = socket.local_addr().unwrap().port();
+
+        let config: GraphiteConfig = toml::from_str(&format!("host = \"127.0.0.1\"\nport = {port}\nprefix = \"x\"\nprotocol = \"udp\"")).unwrap();
+        let mut sender = Sender::start(&config, "host").unwrap();
+        sender.send(&readings().iter().collect::<Vec<_>>());
+
+        let mut buffer = [0u8; MAX_DATAGRAM];
+        let length = socket.recv(&mut buffer).unwrap();
+        let text = std::str::from_utf8(&buffer[..length]).unwrap();
+        assert!(text.starts_with("x.host.cpu_temp 62.5 "), "{text}");
+    }
+}
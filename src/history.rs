@@ -0,0 +1,207 @@
+//! History of readings stored in a SQLite database
+
+use crate::prelude::*;
+use crate::alarm::AlarmState;
+use crate::config::HistoryConfig;
+use crate::sqlite::{Connection, Value};
+use crate::Widgets;
+use chrono::{DateTime, Local, TimeZone};
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS readings (
+    timestamp INTEGER NOT NULL,
+    sensor TEXT NOT NULL,
+    value REAL NOT NULL,
+    alarm TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS readings_sensor_timestamp ON readings (sensor, timestamp);
+";
+
+/// How often old readings are removed
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub struct History {
+    connection: Connection,
+    retention: Option<Duration>,
+    last_prune: Option<Instant>,
+}
+
+/// Single stored reading
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Entry {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Local>,
+    pub value: f64,
+    pub alarm: String,
+}
+
+fn serialize_timestamp<S: serde::Serializer>(value: &DateTime<Local>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&value.to_rfc3339_opts(chrono::SecondsFormat::Millis, false))
+}
+
+impl History {
+    pub fn open(config: &HistoryConfig) -> Result<Self> {
+        if let Some(dir) = config.path.parent() && !dir.as_os_str().is_empty() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| anyhow!("Unable to create directory {dir:?}"))?;
+        }
+
+        let connection = Connection::open(&config.path, false)?;
+
+        // sqlite only runs one statement at a time
+        for statement in SCHEMA.split(';').filter(|x| !x.trim().is_empty()) {
+            connection.execute(statement, &[])
+                .with_context(|| anyhow!("Unable to create schema in {:?}", config.path))?;
+        }
+
+        Ok(Self {
+            connection,
+            retention: config.retention,
+            last_prune: None,
+        })
+    }
+
+    /// Insert readings of the tick, failed sensors are skipped
+    pub fn insert(&mut self, time: DateTime<Local>, widgets: &Widgets) -> Result<()> {
+        let timestamp = Value::Integer(time.timestamp_millis());
+
+        // single transaction per tick, so a crash cannot leave half a tick
+        self.connection.execute("BEGIN", &[])?;
+
+        let mut stmt = self.connection.prepare("INSERT INTO readings (timestamp, sensor, value, alarm) VALUES (?, ?, ?, ?)")?;
+        for (_, widget) in widgets.iter() {
+            let (Some(sensor), Some(reading)) = (widget.sensor(), widget.reading()) else {
+                continue;
+            };
+
+            let alarm = widget.alarm_tracker().map_or(AlarmState::Normal, |x| x.state);
+
+            stmt.bind(&[timestamp, Value::Text(&sensor.name), Value::Real(reading.value), Value::Text(alarm.as_str())])?;
+            stmt.step()?;
+        }
+        drop(stmt);
+
+        self.connection.execute("COMMIT", &[])?;
+
+        if self.last_prune.is_none_or(|x| x.elapsed() >= PRUNE_INTERVAL) {
+            self.last_prune = Some(Instant::now());
+            self.prune(time)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove readings older than the retention
+    fn prune(&self, now: DateTime<Local>) -> Result<()> {
+        let Some(retention) = self.retention else {
+            return Ok(());
+        };
+
+        let cutoff = now.timestamp_millis() - retention.as_millis() as i64;
+        self.connection.execute("DELETE FROM readings WHERE timestamp < ?", &[Value::Integer(cutoff)])
+    }
+}
+
+/// Readings of the sensor since the time, oldest first
+pub fn query(path: &Path, sensor: &str, since: DateTime<Local>) -> Result<Vec<Entry>> {
+    if !path.exists() {
+        bail!("History database {path:?} does not exist, is history enabled in the daemon config?");
+    }
+
+    let connection = Connection::open(path, true)?;
+    let mut stmt = connection.prepare("SELECT timestamp, value, alarm FROM readings WHERE sensor = ? AND timestamp >= ? ORDER BY timestamp")?;
+    stmt.bind(&[Value::Text(sensor), Value::Integer(since.timestamp_millis())])?;
+
+    let mut entries = vec![];
+    while stmt.step()? {
+        let Some(timestamp) = Local.timestamp_millis_opt(stmt.integer(0)).single() else {
+            continue;
+        };
+
+        entries.push(Entry {
+            timestamp,
+            value: stmt.real(1),
+            alarm: stmt.text(2),
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::config::Sensor;
+    use crate::output::Reading;
+
+    struct TestWidget(Sensor, Option<Reading>);
+
+    impl crate::Widget for TestWidget {
+        fn value(&mut self, _ctx: &crate::Context) -> Result<String> {
+            bail!("unreachable")
+        }
+
+        fn sensor(&self) -> Option<&Sensor> {
+            Some(&self.0)
+        }
+
+        fn reading(&self) -> Option<&Reading> {
+            self.1.as_ref()
+        }
+    }
+
+    fn widgets() -> Widgets {
+        let widget = |name: &str, value: Option<f64>| -> (String, Box<dyn crate::Widget>) {
+            let sensor = Sensor { name: name.into(), ..Default::default() };
+            let reading = value.map(|x| Reading::new(&sensor, x, x, Default::default()));
+            (format!("{{{name}}}"), Box::new(TestWidget(sensor, reading)))
+        };
+
+        vec![widget("cpu", Some(40.0)), widget("gpu", None)]
+    }
+
+    #[test]
+    fn test_history() {
+        let dir = TempDir::new("history");
+        let path = dir.join("history.db");
+
+        let config = HistoryConfig {
+            backend: Default::default(),
+            path: path.clone(),
+            retention: Some(Duration::from_secs(60 * 60)),
+        };
+
+        let now = Local::now();
+        let hours = |x| now - chrono::Duration::hours(x);
+
+        let mut history = History::open(&config).unwrap();
+        history.insert(hours(2), &widgets()).unwrap();
+        assert_eq!(query(&path, "cpu", hours(3)).unwrap().len(), 1);
+
+        history.prune(now).unwrap();
+        assert!(query(&path, "cpu", hours(3)).unwrap().is_empty());
+
+        // not pruned again within the hour
+        history.insert(hours(2), &widgets()).unwrap();
+        history.insert(now, &widgets()).unwrap();
+        assert_eq!(query(&path, "cpu", hours(3)).unwrap().len(), 2);
+
+        let entries = query(&path, "cpu", hours(1)).unwrap();
+        assert_eq!(entries, vec![Entry {
+            timestamp: Local.timestamp_millis_opt(now.timestamp_millis()).unwrap(),
+            value: 40.0,
+            alarm: "normal".to_string(),
+        }]);
+
+        // failed sensors are skipped
+        assert!(query(&path, "gpu", hours(3)).unwrap().is_empty());
+
+        drop(history);
+        drop(dir);
+        assert!(query(&path, "cpu", now).is_err());
+    }
+}
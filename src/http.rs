@@ -0,0 +1,144 @@
+//! Minimal HTTP/1.1 client for pushing data, one connection per request
+
+use crate::prelude::*;
+use crate::net::Url;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Decode a body sent with `Transfer-Encoding: chunked`
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = vec![];
+
+    loop {
+        let end = data.windows(2).position(|x| x == b"\r\n").with_context(|| anyhow!("Truncated chunked body"))?;
+        let size = std::str::from_utf8(&data[..end]).ok()
+            .map(|x| x.split(';').next().unwrap_or_default().trim())
+            .and_then(|x| usize::from_str_radix(x, 16).ok())
+            .with_context(|| anyhow!("Invalid chunk size"))?;
+
+        data = &data[end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+
+        let chunk = data.get(..size).with_context(|| anyhow!("Truncated chunked body"))?;
+        body.extend_from_slice(chunk);
+        data = data.get(size + 2..).unwrap_or_default();
+    }
+}
+
+/// Parse a whole response, read until the server closed the connection
+fn parse_response(data: &[u8]) -> Result<Response> {
+    let end = data.windows(4).position(|x| x == b"\r\n\r\n").with_context(|| anyhow!("Truncated response"))?;
+    let head = String::from_utf8_lossy(&data[..end]);
+    let mut lines = head.lines();
+
+    let status = lines.next()
+        .and_then(|x| x.split_whitespace().nth(1))
+        .and_then(|x| x.parse().ok())
+        .with_context(|| anyhow!("Invalid response"))?;
+
+    let chunked = lines
+        .filter_map(|x| x.split_once(':'))
+        .any(|(name, value)| name.trim().eq_ignore_ascii_case("transfer-encoding") && value.trim().eq_ignore_ascii_case("chunked"));
+
+    let body = &data[end + 4..];
+    let body = match chunked {
+        true => decode_chunked(body)?,
+        false => body.to_vec(),
+    };
+
+    Ok(Response { status, body: String::from_utf8_lossy(&body).into_owned() })
+}
+
+/// Send the request and wait for the whole response, `timeout` applies to connecting and each read and write
+pub fn request(method: &str, url: &Url, headers: &[(&str, &str)], body: &[u8], timeout: Duration, ca_file: Option<&Path>) -> Result<Response> {
+    let mut stream = crate::net::connect(url, timeout, ca_file)?;
+
+    let mut head = format!("{method} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: kelvin/{}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        url.path, url.address(), env!("CARGO_PKG_VERSION"), body.len());
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(body))
+        .with_context(|| anyhow!("Unable to send request to {}", url.address()))?;
+
+    let mut data = vec![];
+    match stream.read_to_end(&mut data) {
+        Ok(_) => {},
+
+        // servers may close TLS connections without notifying
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof && !data.is_empty() => {},
+        Err(err) => return Err(err).with_context(|| anyhow!("Unable to read response from {}", url.address())),
+    }
+
+    parse_response(&data).with_context(|| anyhow!("Invalid response from {}", url.address()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_response() {
+        let response = parse_response(b"HTTP/1.1 204 No Content\r\nX-Test: 1\r\n\r\n").unwrap();
+        assert_eq!(response, Response { status: 204, body: String::new() });
+        assert!(response.is_success());
+
+        let response = parse_response(b"HTTP/1.1 400 Bad Request\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nbad \r\n4;x=1\r\nline\r\n0\r\n\r\n").unwrap();
+        assert_eq!(response, Response { status: 400, body: "bad line".into() });
+        assert!(!response.is_success());
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+        assert!(parse_response(b"garbage\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut data = vec![];
+            let mut buffer = [0u8; 1024];
+
+            // request ends with the body of known length
+            while !data.ends_with(b"hello") {
+                let read = stream.read(&mut buffer).unwrap();
+                data.extend_from_slice(&buffer[..read]);
+            }
+
+            stream.write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok").unwrap();
+            String::from_utf8(data).unwrap()
+        });
+
+        let url = Url::parse(&format!("http://127.0.0.1:{port}/write?x=1")).unwrap();
+        let response = request("POST", &url, &[("Authorization", "Token abc")], b"hello", Duration::from_secs(5), None).unwrap();
+        assert_eq!(response, Response { status: 201, body: "ok".into() });
+
+        let sent = server.join().unwrap();
+        assert!(sent.starts_with("POST /write?x=1 HTTP/1.1\r\n"));
+        assert!(sent.contains(&format!("\r\nHost: 127.0.0.1:{port}\r\n")));
+        assert!(sent.contains("\r\nContent-Length: 5\r\n"));
+        assert!(sent.ends_with("\r\nAuthorization: Token abc\r\n\r\nhello"));
+    }
+}
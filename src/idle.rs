@@ -0,0 +1,154 @@
+use crate::prelude::*;
+use crate::config::{IdleConfig, IdleStrategy, Sensor};
+use std::collections::{HashMap, VecDeque};
+
+/// Read 1 minute load average from `/proc/loadavg`
+fn load_average() -> Result<f64> {
+    let contents = std::fs::read_to_string("/proc/loadavg")
+        .with_context(|| anyhow!("Could not read /proc/loadavg"))?;
+
+    let first = contents.split_whitespace().next().unwrap_or_default();
+
+    first.parse()
+        .with_context(|| anyhow!("Unable to parse {first:?} in /proc/loadavg"))
+}
+
+/// Decides when the polling loop can slow down to idle poll rate
+#[derive(Debug)]
+pub struct IdleDetector {
+    config: IdleConfig,
+
+    /// Recent values of each sensor, at most `config.ticks` long
+    history: HashMap<String, VecDeque<f64>>,
+
+    idle: bool,
+}
+
+impl IdleDetector {
+    pub fn new(config: IdleConfig) -> Self {
+        Self {
+            config,
+            history: HashMap::new(),
+            idle: false,
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Feed readings of the current tick, returns true if idle state changed
+    pub fn update(&mut self, readings: &[(&Sensor, f64)]) -> bool {
+        self.update_with(readings, || load_average().ok())
+    }
+
+    fn update_with(&mut self, readings: &[(&Sensor, f64)], load_average: impl FnOnce() -> Option<f64>) -> bool {
+        let mut active = false;
+
+        for (sensor, value) in readings {
+            let history = self.history.entry(sensor.name.clone()).or_default();
+
+            // sudden jump means something is happening
+            if history.back().is_some_and(|last| (value - last).abs() > self.config.delta) {
+                active = true;
+            }
+
+            let margin = self.config.alarm_margin;
+            if sensor.alarm_high.is_some_and(|x| *value >= x - margin)
+                || sensor.alarm_low.is_some_and(|x| *value <= x + margin) {
+                active = true;
+            }
+
+            history.push_back(*value);
+            if history.len() > self.config.ticks {
+                history.pop_front();
+            }
+        }
+
+        let idle = !active && match self.config.strategy {
+            IdleStrategy::LoadAverage => load_average().is_some_and(|x| x < self.config.load_average),
+            IdleStrategy::Stable => self.is_stable(),
+        };
+
+        let changed = idle != self.idle;
+        self.idle = idle;
+
+        changed
+    }
+
+    /// All sensors have full history and no value moved more than `delta`
+    fn is_stable(&self) -> bool {
+        !self.history.is_empty() && self.history.values().all(|history| {
+            let min = history.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = history.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+            history.len() >= self.config.ticks && max - min <= self.config.delta
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(strategy: IdleStrategy) -> IdleConfig {
+        IdleConfig {
+            strategy,
+            load_average: 0.5,
+            delta: 2.0,
+            ticks: 3,
+            alarm_margin: 5.0,
+        }
+    }
+
+    fn sensor() -> Sensor {
+        Sensor {
+            name: "cpu".into(),
+            alarm_high: Some(90.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_stable() {
+        let sensor = sensor();
+        let mut detector = IdleDetector::new(config(IdleStrategy::Stable));
+
+        // needs full history before going idle
+        assert!(!detector.update(&[(&sensor, 40.0)]));
+        assert!(!detector.update(&[(&sensor, 41.0)]));
+        assert!(detector.update(&[(&sensor, 40.5)]));
+        assert!(detector.is_idle());
+
+        // jump snaps back to active
+        assert!(detector.update(&[(&sensor, 50.0)]));
+        assert!(!detector.is_idle());
+
+        // window still contains the old value
+        assert!(!detector.update(&[(&sensor, 51.5)]));
+        assert!(detector.update(&[(&sensor, 51.0)]));
+    }
+
+    #[test]
+    fn test_alarm_margin() {
+        let sensor = sensor();
+        let mut detector = IdleDetector::new(config(IdleStrategy::LoadAverage));
+
+        assert!(detector.update_with(&[(&sensor, 80.0)], || Some(0.1)));
+        assert!(detector.is_idle());
+
+        // close to alarm_high is never idle
+        assert!(detector.update_with(&[(&sensor, 85.0)], || Some(0.1)));
+        assert!(!detector.is_idle());
+    }
+
+    #[test]
+    fn test_load_average() {
+        let sensor = sensor();
+        let mut detector = IdleDetector::new(config(IdleStrategy::LoadAverage));
+
+        assert!(detector.update_with(&[(&sensor, 40.0)], || Some(0.1)));
+        assert!(detector.update_with(&[(&sensor, 40.0)], || Some(2.0)));
+        assert!(!detector.update_with(&[(&sensor, 40.0)], || None));
+    }
+}
@@ -0,0 +1,312 @@
+//! Writes readings to the InfluxDB v2 write API
+//!
+//! Points are sent from their own thread and buffered while the server is unreachable, so polling never waits on it
+
+use crate::prelude::*;
+use crate::config::InfluxConfig;
+use crate::net::Url;
+use crate::output::Reading;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Ticks waiting for the writer thread, more are dropped
+const QUEUE_SIZE: usize = 64;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most points sent in a single request
+const BATCH_SIZE: usize = 5000;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Url of the write endpoint for the bucket, the path of the server url is kept as a prefix
+fn write_url(config: &InfluxConfig) -> Result<Url> {
+    let mut url = Url::parse(&config.url)?;
+    let base = url.path.split('?').next().unwrap_or_default().trim_end_matches('/');
+
+    url.path = format!(
+        "{base}/api/v2/write?org={}&bucket={}&precision=ns",
+        crate::net::percent_encode(&config.org),
+        crate::net::percent_encode(&config.bucket),
+    );
+
+    Ok(url)
+}
+
+/// Writer thread, points are sent until the sender is dropped
+struct Worker {
+    url: Url,
+    token: Option<String>,
+    ca_file: Option<PathBuf>,
+    max_buffer: usize,
+    receiver: Receiver<Vec<String>>,
+
+    /// Points not yet written, oldest first
+    buffer: VecDeque<String>,
+
+    /// Points dropped as the buffer was full, reported once writing works again
+    dropped: usize,
+}
+
+impl Worker {
+    fn add(&mut self, points: Vec<String>) {
+        self.buffer.extend(points);
+
+        while self.buffer.len() > self.max_buffer {
+            self.buffer.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    /// Write all buffered points, points rejected by the server are dropped as sending them again would fail too
+    fn flush(&mut self) -> Result<()> {
+        while !self.buffer.is_empty() {
+            let count = self.buffer.len().min(BATCH_SIZE);
+            let body = self.buffer.iter().take(count).map(String::as_str).collect::<Vec<_>>().join("\n");
+
+            let authorization = self.token.as_ref().map(|x| format!("Token {x}"));
+            let mut headers = vec![("Content-Type", "text/plain; charset=utf-8")];
+            if let Some(x) = &authorization {
+                headers.push(("Authorization", x));
+            }
+
+            let response = crate::http::request("POST", &self.url, &headers, body.as_bytes(), TIMEOUT, self.ca_file.as_deref())?;
+            match response.status {
+                _ if response.is_success() => {},
+                400 | 413 | 422 => {
+                    crate::log::error!("InfluxDB rejected {count} points with status {}: {}", response.status, response.body.trim());
+                },
+                x => bail!("InfluxDB answered with status {x}: {}", response.body.trim()),
+            }
+
+            self.buffer.drain(..count);
+        }
+
+        if self.dropped > 0 {
+            crate::log::warning!("Dropped {} points while InfluxDB was unreachable", self.dropped);
+            self.dropped = 0;
+        }
+
+        Ok(())
+    }
+
+    fn run(mut self) {
+        let mut backoff = MIN_BACKOFF;
+        let mut retry_at = None;
+        let mut failing = false;
+
+        loop {
+            let message = match retry_at.and_then(|x: Instant| x.checked_duration_since(Instant::now())) {
+                Some(left) => self.receiver.recv_timeout(left),
+                None if retry_at.is_some() => Err(RecvTimeoutError::Timeout),
+                None => self.receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            let closed = match message {
+                Ok(points) => {
+                    self.add(points);
+                    while let Ok(x) = self.receiver.try_recv() {
+                        self.add(x);
+                    }
+
+                    false
+                },
+                Err(RecvTimeoutError::Timeout) => false,
+                Err(RecvTimeoutError::Disconnected) => true,
+            };
+
+            // points that arrive while waiting to retry are only buffered
+            if retry_at.is_some_and(|x| Instant::now() < x) && !closed {
+                continue;
+            }
+
+            match self.flush() {
+                Ok(()) => {
+                    if failing {
+                        crate::log::info!("Writing to InfluxDB {} again", self.url.address());
+                    }
+
+                    failing = false;
+                    backoff = MIN_BACKOFF;
+                    retry_at = None;
+                },
+                Err(err) => {
+                    // warned once per outage, the log would fill up otherwise
+                    if !failing && !closed {
+                        crate::log::warning!("Unable to write to InfluxDB, buffering up to {} points: {err:#}", self.max_buffer);
+                    }
+
+                    failing = true;
+                    retry_at = Some(Instant::now() + backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                },
+            }
+
+            if closed {
+                if !self.buffer.is_empty() {
+                    crate::log::warning!("Discarding {} points not written to InfluxDB", self.buffer.len());
+                }
+
+                return;
+            }
+        }
+    }
+}
+
+/// Queues readings for the writer thread, remaining points get a last chance to be written when dropped
+pub struct Writer {
+    config: InfluxConfig,
+    hostname: String,
+    sender: Option<SyncSender<Vec<String>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Writer {
+    pub fn start(config: &InfluxConfig, hostname: &str) -> Result<Self> {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(QUEUE_SIZE);
+        let worker = Worker {
+            url: write_url(config)?,
+            token: config.token.clone(),
+            ca_file: config.ca_file.clone(),
+            max_buffer: config.max_buffer,
+            receiver,
+            buffer: VecDeque::new(),
+            dropped: 0,
+        };
+
+        let thread = std::thread::Builder::new()
+            .name("influx".into())
+            .spawn(move || worker.run())
+            .with_context(|| anyhow!("Unable to start InfluxDB thread"))?;
+
+        Ok(Self {
+            config: config.clone(),
+            hostname: hostname.to_string(),
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn config(&self) -> &InfluxConfig {
+        &self.config
+    }
+
+    /// Queue readings of a tick, they are dropped if the writer thread is falling behind
+    pub fn write(&mut self, readings: &[&Reading]) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+
+        let lines = crate::output::influx(readings, &self.hostname, crate::output::influx_timestamp());
+        if lines.is_empty() {
+            return;
+        }
+
+        match sender.try_send(lines.lines().map(String::from).collect()) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {},
+            Err(TrySendError::Full(_)) => crate::log::debug!("InfluxDB queue is full, dropped {} points", readings.len()),
+        }
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.sender = None;
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::AlarmState;
+    use crate::config::Sensor;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn config(url: &str) -> InfluxConfig {
+        InfluxConfig {
+            url: url.into(),
+            org: "my org".into(),
+            bucket: "kelvin".into(),
+            token: Some("secret".into()),
+            ca_file: None,
+            max_buffer: 3,
+        }
+    }
+
+    #[test]
+    fn test_write_url() {
+        let url = write_url(&config("http://localhost:8086")).unwrap();
+        assert_eq!(url.path, "/api/v2/write?org=my%20org&bucket=kelvin&precision=ns");
+
+        let url = write_url(&config("https://example.com/influx/?x=1")).unwrap();
+        assert_eq!((url.port, url.path.as_str()), (443, "/influx/api/v2/write?org=my%20org&bucket=kelvin&precision=ns"));
+    }
+
+    #[test]
+    fn test_buffer() {
+        let (_sender, receiver) = std::sync::mpsc::sync_channel(1);
+        let mut worker = Worker {
+            url: write_url(&config("http://127.0.0.1:1")).unwrap(),
+            token: None,
+            ca_file: None,
+            max_buffer: 3,
+            receiver,
+            buffer: VecDeque::new(),
+            dropped: 0,
+        };
+
+        worker.add(vec!["a".into(), "b".into()]);
+        worker.add(vec!["c".into(), "d".into()]);
+        assert_eq!(worker.buffer, ["b", "c", "d"]);
+        assert_eq!(worker.dropped, 1);
+
+        // nothing listens on the port, points are kept for the next try
+        assert!(worker.flush().is_err());
+        assert_eq!(worker.buffer.len(), 3);
+    }
+
+    #[test]
+    fn test_writer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            let mut buffer = [0u8; 1024];
+
+            // headers, then the body of the announced length
+            while !request.split_once("\r\n\r\n").is_some_and(|(head, body)| {
+                head.lines().find_map(|x| x.strip_prefix("Content-Length: ")).is_some_and(|x| x.parse() == Ok(body.len()))
+            }) {
+                let read = stream.read(&mut buffer).unwrap();
+                request.push_str(std::str::from_utf8(&buffer[..read]).unwrap());
+            }
+
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            request
+        });
+
+        let sensor = Sensor { name: "cpu".into(), ..Default::default() };
+        let reading = Reading::new(&sensor, 95.0, 95.0, AlarmState::High);
+
+        let mut writer = Writer::start(&config(&format!("http://127.0.0.1:{port}")), "host").unwrap();
+        writer.write(&[&reading]);
+
+        let request = server.join().unwrap();
+        drop(writer);
+
+        assert!(request.starts_with("POST /api/v2/write?org=my%20org&bucket=kelvin&precision=ns HTTP/1.1\r\n"), "{request}");
+        assert!(request.contains("\r\nAuthorization: Token secret\r\n"), "{request}");
+        assert!(request.contains("\r\n\r\nkelvin,host=host,sensor=cpu value=95.0,alarm=2i "), "{request}");
+    }
+}
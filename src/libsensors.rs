@@ -0,0 +1,214 @@
+//! Read lm_sensors data directly through libsensors, loaded at runtime so kelvin still works without it
+//!
+//! The data has the same shape as `sensors -j` output so paths stay the same
+
+use crate::prelude::*;
+use serde_json::{Map, Value as JsonValue};
+use std::ffi::{CStr, c_char, c_double, c_int, c_short, c_uint, c_void};
+use std::sync::{Mutex, OnceLock};
+
+const LIBRARY: &CStr = c"libsensors.so.5";
+const RTLD_NOW: c_int = 2;
+
+/// Subfeature can be read
+const SENSORS_MODE_R: c_uint = 1;
+
+#[repr(C)]
+struct BusId {
+    kind: c_short,
+    nr: c_short,
+}
+
+#[repr(C)]
+struct ChipName {
+    prefix: *mut c_char,
+    bus: BusId,
+    addr: c_int,
+    path: *mut c_char,
+}
+
+#[repr(C)]
+struct Feature {
+    name: *mut c_char,
+    number: c_int,
+    kind: c_int,
+    first_subfeature: c_int,
+    padding: c_int,
+}
+
+#[repr(C)]
+struct Subfeature {
+    name: *mut c_char,
+    number: c_int,
+    kind: c_int,
+    mapping: c_int,
+    flags: c_uint,
+}
+
+unsafe extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn fopen(path: *const c_char, mode: *const c_char) -> *mut c_void;
+    fn fclose(file: *mut c_void) -> c_int;
+    fn free(ptr: *mut c_void);
+}
+
+type InitFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+type GetDetectedChipsFn = unsafe extern "C" fn(*const ChipName, *mut c_int) -> *const ChipName;
+type SnprintfChipNameFn = unsafe extern "C" fn(*mut c_char, usize, *const ChipName) -> c_int;
+type GetAdapterNameFn = unsafe extern "C" fn(*const BusId) -> *const c_char;
+type GetFeaturesFn = unsafe extern "C" fn(*const ChipName, *mut c_int) -> *const Feature;
+type GetLabelFn = unsafe extern "C" fn(*const ChipName, *const Feature) -> *mut c_char;
+type GetAllSubfeaturesFn = unsafe extern "C" fn(*const ChipName, *const Feature, *mut c_int) -> *const Subfeature;
+type GetValueFn = unsafe extern "C" fn(*const ChipName, c_int, *mut c_double) -> c_int;
+
+/// Functions loaded from the library
+struct Library {
+    get_detected_chips: GetDetectedChipsFn,
+    snprintf_chip_name: SnprintfChipNameFn,
+    get_adapter_name: GetAdapterNameFn,
+    get_features: GetFeaturesFn,
+    get_label: GetLabelFn,
+    get_all_subfeatures: GetAllSubfeaturesFn,
+    get_value: GetValueFn,
+}
+
+// SAFETY: libsensors is only used while holding the lock
+unsafe impl Send for Library {}
+
+static LIBRARY_INSTANCE: OnceLock<Option<Mutex<Library>>> = OnceLock::new();
+
+impl Library {
+    /// Load and initialize libsensors, returns `None` if it is not installed or fails to initialize
+    fn load() -> Option<Self> {
+        // SAFETY: symbols are cast to their signatures from sensors.h
+        unsafe {
+            let handle = dlopen(LIBRARY.as_ptr(), RTLD_NOW);
+            if handle.is_null() {
+                return None;
+            }
+
+            macro_rules! symbol {
+                ($name:literal, $type:ty) => {{
+                    let ptr = dlsym(handle, $name.as_ptr());
+                    if ptr.is_null() {
+                        return None;
+                    }
+                    std::mem::transmute::<*mut c_void, $type>(ptr)
+                }};
+            }
+
+            let init = symbol!(c"sensors_init", InitFn);
+
+            // same as `sensors --config /dev/null`, raw values without any user configuration
+            let config = fopen(c"/dev/null".as_ptr(), c"r".as_ptr());
+            if config.is_null() {
+                return None;
+            }
+
+            let result = init(config);
+            fclose(config);
+
+            if result != 0 {
+                return None;
+            }
+
+            Some(Self {
+                get_detected_chips: symbol!(c"sensors_get_detected_chips", GetDetectedChipsFn),
+                snprintf_chip_name: symbol!(c"sensors_snprintf_chip_name", SnprintfChipNameFn),
+                get_adapter_name: symbol!(c"sensors_get_adapter_name", GetAdapterNameFn),
+                get_features: symbol!(c"sensors_get_features", GetFeaturesFn),
+                get_label: symbol!(c"sensors_get_label", GetLabelFn),
+                get_all_subfeatures: symbol!(c"sensors_get_all_subfeatures", GetAllSubfeaturesFn),
+                get_value: symbol!(c"sensors_get_value", GetValueFn),
+            })
+        }
+    }
+
+    /// Read all chips in the same shape as `sensors -j`
+    fn read(&self) -> Result<JsonValue> {
+        let mut chips = Map::new();
+
+        // SAFETY: pointers come from libsensors and stay valid until `sensors_cleanup`, which is never called
+        unsafe {
+            let mut chip_nr = 0;
+            loop {
+                let chip = (self.get_detected_chips)(std::ptr::null(), &mut chip_nr);
+                if chip.is_null() {
+                    break;
+                }
+
+                let mut buffer = [0 as c_char; 256];
+                if (self.snprintf_chip_name)(buffer.as_mut_ptr(), buffer.len(), chip) < 0 {
+                    bail!("Unable to get name of a chip from libsensors");
+                }
+                let chip_name = CStr::from_ptr(buffer.as_ptr()).to_string_lossy().into_owned();
+
+                let mut features = Map::new();
+
+                let adapter = (self.get_adapter_name)(&(*chip).bus);
+                if !adapter.is_null() {
+                    features.insert("Adapter".into(), CStr::from_ptr(adapter).to_string_lossy().into());
+                }
+
+                let mut feature_nr = 0;
+                loop {
+                    let feature = (self.get_features)(chip, &mut feature_nr);
+                    if feature.is_null() {
+                        break;
+                    }
+
+                    let label_ptr = (self.get_label)(chip, feature);
+                    let label = if label_ptr.is_null() {
+                        CStr::from_ptr((*feature).name).to_string_lossy().into_owned()
+                    } else {
+                        let label = CStr::from_ptr(label_ptr).to_string_lossy().into_owned();
+                        free(label_ptr.cast());
+                        label
+                    };
+
+                    let mut values = Map::new();
+
+                    let mut subfeature_nr = 0;
+                    loop {
+                        let subfeature = (self.get_all_subfeatures)(chip, feature, &mut subfeature_nr);
+                        if subfeature.is_null() {
+                            break;
+                        }
+
+                        if (*subfeature).flags & SENSORS_MODE_R == 0 {
+                            continue;
+                        }
+
+                        let mut value: c_double = 0.0;
+                        if (self.get_value)(chip, (*subfeature).number, &mut value) != 0 {
+                            // powered down devices and such, sensors skips them too
+                            continue;
+                        }
+
+                        let name = CStr::from_ptr((*subfeature).name).to_string_lossy().into_owned();
+                        values.insert(name, value.into());
+                    }
+
+                    features.insert(label, values.into());
+                }
+
+                chips.insert(chip_name, features.into());
+            }
+        }
+
+        Ok(chips.into())
+    }
+}
+
+/// Read sensors data through libsensors, returns `None` if the library is not available
+pub fn read() -> Option<Result<JsonValue>> {
+    let library = LIBRARY_INSTANCE.get_or_init(|| Library::load().map(Mutex::new)).as_ref()?;
+
+    let library = match library.lock() {
+        Ok(x) => x,
+        Err(_) => return Some(Err(anyhow!("libsensors lock is poisoned"))),
+    };
+
+    Some(library.read())
+}
@@ -0,0 +1,205 @@
+use crate::prelude::*;
+use serde_json::Value as JsonValue;
+use std::path::{Path, PathBuf};
+
+/// Collect all numeric leaves of lm_sensors output as paths usable in `path`
+pub fn flatten_sensors(value: &JsonValue, prefix: &Path, out: &mut Vec<(PathBuf, f64)>) {
+    match value {
+        JsonValue::Object(map) => {
+            for (key, value) in map {
+                flatten_sensors(value, &prefix.join(key), out);
+            }
+        },
+        JsonValue::Number(x) => {
+            if let Some(x) = x.as_f64() {
+                out.push((prefix.to_path_buf(), x));
+            }
+        },
+        // some drivers put numbers in strings
+        JsonValue::String(x) => {
+            if let Ok(x) = x.trim().parse() {
+                out.push((prefix.to_path_buf(), x));
+            }
+        },
+        _ => {},
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HwmonEntry {
+    /// Contents of the `name` file of the chip
+    pub chip: String,
+
+    pub path: PathBuf,
+    pub value: String,
+}
+
+/// Find all `*_input` files of every hwmon chip
+pub fn hwmon_entries(root: &Path) -> Result<Vec<HwmonEntry>> {
+    let mut entries = vec![];
+
+    let mut chips = std::fs::read_dir(root)
+        .with_context(|| anyhow!("Unable to read {root:?}"))?
+        .filter_map(|x| x.ok().map(|x| x.path()))
+        .collect::<Vec<_>>();
+    chips.sort();
+
+    for chip_dir in chips {
+        let chip = std::fs::read_to_string(chip_dir.join("name"))
+            .map(|x| x.trim().to_string())
+            .unwrap_or_default();
+
+        let Ok(files) = std::fs::read_dir(&chip_dir) else {
+            continue;
+        };
+
+        let mut files = files
+            .filter_map(|x| x.ok().map(|x| x.path()))
+            .filter(|x| x.file_name().is_some_and(|x| x.to_string_lossy().ends_with("_input")))
+            .collect::<Vec<_>>();
+        files.sort();
+
+        for path in files {
+            // unreadable files are usually from powered down devices
+            let Ok(value) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+
+            entries.push(HwmonEntry {
+                chip: chip.clone(),
+                path,
+                value: value.trim().to_string(),
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThermalEntry {
+    /// Contents of the `type` file of the zone, used as `path` of thermal source
+    pub zone: String,
+
+    pub dir: PathBuf,
+
+    /// Temperature in degrees, not millidegrees like in the file
+    pub value: f64,
+}
+
+/// Find all thermal zones with a readable temperature
+pub fn thermal_entries(root: &Path) -> Result<Vec<ThermalEntry>> {
+    let mut zones = std::fs::read_dir(root)
+        .with_context(|| anyhow!("Unable to read {root:?}"))?
+        .filter_map(|x| x.ok().map(|x| x.path()))
+        .filter(|x| x.file_name().is_some_and(|x| x.to_string_lossy().starts_with("thermal_zone")))
+        .collect::<Vec<_>>();
+    zones.sort();
+
+    Ok(zones.into_iter()
+        .filter_map(|dir| {
+            let zone = std::fs::read_to_string(dir.join("type")).ok()?.trim().to_string();
+
+            // disabled zones fail to read
+            let value = std::fs::read_to_string(dir.join("temp")).ok()?.trim().parse::<f64>().ok()? / 1000.0;
+
+            Some(ThermalEntry { zone, dir, value })
+        })
+        .collect())
+}
+
+/// Print all available sensor paths
+pub fn list(filter: Option<&str>, hwmon: bool, thermal: bool) -> Result<()> {
+    let matches = |x: &Path| filter.is_none_or(|filter| x.to_string_lossy().contains(filter));
+
+    let mut sensors = vec![];
+    flatten_sensors(&crate::get_temps()?, Path::new(""), &mut sensors);
+
+    println!("# source = \"sensors\"");
+    for (path, value) in sensors.iter().filter(|(x, _)| matches(x)) {
+        println!("{}  {value}", path.display());
+    }
+
+    if hwmon {
+        println!("\n# source = \"file\"");
+        for entry in hwmon_entries(Path::new(crate::sysfs::HWMON_ROOT))?.iter().filter(|x| matches(&x.path)) {
+            println!("{}  {}  ({})", entry.path.display(), entry.value, entry.chip);
+        }
+    }
+
+    // only shown when the driver is installed, most machines do not have it
+    if let Ok(table) = crate::nvidia::query(std::time::Duration::from_millis(crate::config::DEFAULT_SENSORS_TIMEOUT)) {
+        println!("\n# source = \"nvidia\"");
+        for gpu in &table.0 {
+            for (metric, value) in crate::nvidia::METRICS.iter().zip(&gpu.values) {
+                let path = format!("{}/{}", gpu.index, metric.name);
+                if matches(Path::new(&path)) {
+                    println!("{path}  {value} {}  ({})", metric.unit, gpu.name);
+                }
+            }
+        }
+    }
+
+    if thermal {
+        println!("\n# source = \"thermal\"");
+        for entry in thermal_entries(Path::new(crate::sysfs::THERMAL_ROOT))?.iter().filter(|x| matches(Path::new(&x.zone))) {
+            println!("{}  {}  ({})", entry.zone, entry.value, entry.dir.display());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_flatten_sensors() {
+        let sensors: JsonValue = serde_json::from_str(include_str!("../tests/fixtures/sensors.json")).unwrap();
+
+        let mut out = vec![];
+        flatten_sensors(&sensors, Path::new(""), &mut out);
+
+        assert!(out.contains(&(PathBuf::from("k10temp-pci-00c3/Tctl/temp1_input"), 61.25)));
+        assert!(out.contains(&(PathBuf::from("nvme-pci-0100/Composite/temp1_input"), 38.85)));
+
+        // adapter names are not numbers
+        assert!(!out.iter().any(|(x, _)| x.ends_with("Adapter")));
+    }
+
+    #[test]
+    fn test_hwmon_entries() {
+        let root = TempDir::new("hwmon");
+        let chip = root.join("hwmon0");
+        std::fs::create_dir_all(&chip).unwrap();
+        std::fs::write(chip.join("name"), "k10temp\n").unwrap();
+        std::fs::write(chip.join("temp1_input"), "42000\n").unwrap();
+        std::fs::write(chip.join("temp1_label"), "Tctl\n").unwrap();
+
+        assert_eq!(hwmon_entries(&root).unwrap(), [HwmonEntry {
+            chip: "k10temp".into(),
+            path: chip.join("temp1_input"),
+            value: "42000".into(),
+        }]);
+    }
+
+    #[test]
+    fn test_thermal_entries() {
+        let root = TempDir::new("thermal-list");
+        for (dir, zone, temp) in [("thermal_zone0", "acpitz", Some("27800\n")), ("thermal_zone1", "iwlwifi_1", None), ("cooling_device0", "Processor", None)] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(root.join(dir).join("type"), format!("{zone}\n")).unwrap();
+            if let Some(temp) = temp {
+                std::fs::write(root.join(dir).join("temp"), temp).unwrap();
+            }
+        }
+
+        assert_eq!(thermal_entries(&root).unwrap(), [ThermalEntry {
+            zone: "acpitz".into(),
+            dir: root.join("thermal_zone0"),
+            value: 27.8,
+        }]);
+    }
+}
@@ -0,0 +1,89 @@
+//! Leveled logging to stderr, stdout is reserved for the output
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warning",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+
+    /// Level from number of `-v` flags, starting from `default`
+    pub fn from_verbosity(default: Self, verbose: u8, quiet: bool) -> Self {
+        if quiet {
+            return Self::Error;
+        }
+
+        match default as u8 + verbose {
+            0 => Self::Error,
+            1 => Self::Warn,
+            2 => Self::Info,
+            3 => Self::Debug,
+            _ => Self::Trace,
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Warn as u8);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Use the macros instead
+pub fn write(level: Level, args: std::fmt::Arguments) {
+    if enabled(level) {
+        eprintln!("[{}] {}: {args}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"), level.as_str());
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Error, format_args!($($arg)*)) };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Warn, format_args!($($arg)*)) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Info, format_args!($($arg)*)) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
+
+pub(crate) use {debug, error, info, warning};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_verbosity() {
+        assert_eq!(Level::from_verbosity(Level::Warn, 0, false), Level::Warn);
+        assert_eq!(Level::from_verbosity(Level::Warn, 1, false), Level::Info);
+        assert_eq!(Level::from_verbosity(Level::Warn, 2, false), Level::Debug);
+        assert_eq!(Level::from_verbosity(Level::Warn, 10, false), Level::Trace);
+        assert_eq!(Level::from_verbosity(Level::Info, 0, false), Level::Info);
+        assert_eq!(Level::from_verbosity(Level::Info, 3, true), Level::Error);
+    }
+}
@@ -1,5 +1,58 @@
+mod alarm;
+mod bar;
 mod cli;
+mod color;
+mod completions;
 mod config;
+mod crash;
+mod csv;
+mod daemon;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod duration;
+mod endpoint;
+mod env;
+mod exec;
+mod fan;
+mod fancontrol;
+mod generate;
+mod graphite;
+mod history;
+mod http;
+mod idle;
+mod influx;
+#[cfg(feature = "libsensors")]
+mod libsensors;
+mod list;
+mod log;
+mod man;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod net;
+mod notify;
+mod nvidia;
+mod output;
+mod rate;
+mod regex;
+mod replay;
+mod schedule;
+mod sdnotify;
+mod signal;
+mod smoothing;
+mod sort;
+mod sound;
+mod sqlite;
+mod stats;
+mod status;
+mod sysfs;
+mod table;
+#[cfg(test)]
+mod testing;
+mod tls;
+mod validate;
+mod watch;
+mod webhook;
+mod yaml;
 
 pub mod prelude {
     pub use anyhow::{Context as AnyhowContext, Result, anyhow, bail};
@@ -8,21 +61,87 @@ pub mod prelude {
 use clap::Parser;
 use prelude::*;
 use serde_json::Value as JsonValue;
-use crate::config::{Config, Sensor};
-use std::{cell::OnceCell, collections::HashMap, io::{BufRead, BufReader}};
+use crate::alarm::{ActiveAlarm, AlarmCommands, AlarmState, AlarmTracker};
+use crate::cli::OutputFormat;
+use crate::config::{Config, Sensor, SensorSource};
+use crate::idle::IdleDetector;
+use crate::output::Reading;
+use crate::stats::SensorStats;
+use std::{cell::OnceCell, io::{BufRead, BufReader, Read, Write}};
 
-fn get_temps() -> Result<JsonValue> {
-    let output = std::process::Command::new("sensors")
-        .args(["-j", "--config", "/dev/null"])
-        .output()
-        .with_context(|| anyhow!("Unable to run sensors command"))?;
+/// Sensors data given with `--sensors-json`, used instead of running `sensors`
+#[derive(Debug)]
+enum SensorsJson {
+    /// Read again every time, so it can be rewritten while running
+    File(std::path::PathBuf),
+
+    /// Stdin can only be read once
+    Stdin(JsonValue),
+}
+
+static SENSORS_JSON: std::sync::OnceLock<SensorsJson> = std::sync::OnceLock::new();
+
+impl SensorsJson {
+    fn new(path: &std::path::Path) -> Result<Self> {
+        if path != std::path::Path::new("-") {
+            return Ok(Self::File(path.to_path_buf()));
+        }
+
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text).with_context(|| anyhow!("Unable to read sensors json from stdin"))?;
+
+        serde_json::from_str(&text)
+            .with_context(|| anyhow!("Unable to parse sensors json from stdin"))
+            .map(Self::Stdin)
+    }
+
+    fn read(&self) -> Result<JsonValue> {
+        match self {
+            Self::File(path) => {
+                let text = std::fs::read_to_string(path).with_context(|| anyhow!("Unable to read sensors json from {path:?}"))?;
+                serde_json::from_str(&text).with_context(|| anyhow!("Unable to parse sensors json from {path:?}"))
+            },
+            Self::Stdin(x) => Ok(x.clone()),
+        }
+    }
+}
+
+/// Run `sensors` and parse its output, killing it after `timeout`
+///
+/// With `libsensors` feature the library is used instead when it is installed, with `--sensors-json` neither is
+fn get_temps_timeout(timeout: std::time::Duration) -> Result<JsonValue> {
+    if let Some(x) = SENSORS_JSON.get() {
+        return x.read();
+    }
 
-    let stdout = String::from_utf8(output.stdout)?;
+    #[cfg(feature = "libsensors")]
+    if let Some(result) = libsensors::read() {
+        return result;
+    }
+
+    let command = ["sensors", "-j", "--config", "/dev/null"].map(String::from);
+    let stdout = exec::run(&command, timeout)?;
 
     serde_json::from_str(&stdout)
         .with_context(|| anyhow!("Unable to parse json from sensors"))
 }
 
+/// Get sensors data with the default timeout
+fn get_temps() -> Result<JsonValue> {
+    get_temps_timeout(std::time::Duration::from_millis(config::DEFAULT_SENSORS_TIMEOUT))
+}
+
+/// Get sensors data with timeout and retry from the config
+fn get_config_temps(config: &Config) -> Result<JsonValue> {
+    let timeout = std::time::Duration::from_millis(config.sensors_timeout);
+
+    match get_temps_timeout(timeout) {
+        Err(err) if config.sensors_retry => get_temps_timeout(timeout)
+            .with_context(|| anyhow!("Retry failed, first attempt failed with: {err:#}")),
+        result => result,
+    }
+}
+
 
 const CLEAR_SEQ: &str = "\x1b[H\x1b[2J";
 
@@ -30,7 +149,169 @@ const CLEAR_SEQ: &str = "\x1b[H\x1b[2J";
 struct Context {
     args: cli::Cli,
     config: Config,
-    sensors_data: JsonValue,
+    /// Output of lm_sensors, only present if any sensor uses it
+    sensors_data: Option<JsonValue>,
+
+    /// Set by widgets when an alarm fired, sounds of all sensors are played once per tick
+    sound_requested: std::cell::Cell<bool>,
+
+    /// Values of sensors read this tick, for virtual sensors
+    values: std::cell::RefCell<std::collections::HashMap<String, f64>>,
+
+    /// For relative timestamps, kept when the config is reloaded
+    started: std::time::Instant,
+
+    /// Output of `nvidia-smi` for this tick, run for the first sensor that needs it
+    nvidia: std::cell::RefCell<Option<Result<nvidia::Table, String>>>,
+
+    /// Inputs of this tick, being recorded with `--record` or replayed with `--replay`
+    tick: std::cell::RefCell<Option<replay::Tick>>,
+
+    /// lm_sensors data read again this tick by retries, by attempt
+    refetched: std::cell::RefCell<Vec<Result<JsonValue, String>>>,
+
+    /// Time spent waiting between retries this tick
+    retry_waited: std::cell::Cell<std::time::Duration>,
+
+    /// Signals of the poll loop, so waiting between retries does not delay shutdown
+    waiter: Option<std::rc::Rc<signal::Waiter>>,
+}
+
+impl Context {
+    fn new(args: cli::Cli, config: Config) -> Self {
+        Self {
+            args,
+            config,
+            sensors_data: None,
+            sound_requested: Default::default(),
+            values: Default::default(),
+            started: std::time::Instant::now(),
+            nvidia: Default::default(),
+            tick: Default::default(),
+            refetched: Default::default(),
+            retry_waited: Default::default(),
+            waiter: None,
+        }
+    }
+
+    /// Alarm is always enabled in daemon mode
+    fn alarms_enabled(&self) -> bool {
+        self.args.alarm || self.args.daemon
+    }
+
+    /// Colors for values, only used for text output going to a terminal
+    fn value_colors(&self) -> Option<&config::Colors> {
+        use std::io::IsTerminal;
+
+        let text = matches!(self.args.output_format(), OutputFormat::Text | OutputFormat::Line | OutputFormat::Table);
+        let terminal = std::io::stdout().is_terminal() && self.args.textfile.is_none() && !self.args.daemon;
+
+        (text && color::enabled(self.args.color, terminal)).then_some(&self.config.colors)
+    }
+
+    /// Timestamp of output at `time` as configured
+    fn timestamp(&self, time: chrono::DateTime<chrono::Local>) -> Option<output::Timestamp> {
+        output::timestamp(self.config.timestamp, self.config.timestamp_utc, time, self.now() - self.started)
+    }
+
+    /// Time of the tick being replayed, otherwise the current time
+    fn now(&self) -> std::time::Instant {
+        match self.replayed() {
+            Some(x) => self.started + std::time::Duration::from_millis(x.elapsed),
+            None => std::time::Instant::now(),
+        }
+    }
+
+    /// Local time of the tick being replayed, otherwise the current time
+    fn local_time(&self) -> chrono::DateTime<chrono::Local> {
+        match self.replayed() {
+            Some(x) => x.local_time(),
+            None => chrono::Local::now(),
+        }
+    }
+
+    fn replayed(&self) -> Option<std::cell::Ref<'_, replay::Tick>> {
+        self.args.replay.as_ref()?;
+        std::cell::Ref::filter_map(self.tick.borrow(), Option::as_ref).ok()
+    }
+
+    /// Start a new tick, from the recording when replaying
+    fn start_tick(&mut self, tick: Option<replay::Tick>) {
+        if let Some(tick) = tick {
+            self.sensors_data = tick.sensors.clone();
+            *self.tick.get_mut() = Some(tick);
+        } else if self.args.record.is_some() {
+            *self.tick.get_mut() = Some(replay::Tick::new(self.started, self.sensors_data.clone()));
+        }
+    }
+
+    /// Keep what reading the sensor gave for the recording, lm_sensors data is recorded as a whole
+    fn record(&self, sensor: &Sensor, raw: &Result<f64>, trace: &config::ReadTrace) {
+        if self.args.record.is_none() || matches!(sensor.source, SensorSource::Sensors) {
+            return;
+        }
+
+        let input = match raw {
+            Ok(_) => replay::Input::Text(trace.text.clone().unwrap_or_default()),
+            Err(err) => replay::Input::Error(format!("{err:#}")),
+        };
+
+        if let Some(tick) = self.tick.borrow_mut().as_mut() {
+            tick.inputs.insert(sensor.name.clone(), input);
+        }
+    }
+
+    /// Read NVIDIA GPU metric, failing to run `nvidia-smi` only fails the sensors using it
+    fn read_nvidia(&self, path: &std::path::Path, trace: &mut config::ReadTrace) -> Result<f64> {
+        let timeout = std::time::Duration::from_millis(self.config.sensors_timeout);
+
+        let mut table = self.nvidia.borrow_mut();
+        match table.get_or_insert_with(|| nvidia::query(timeout).map_err(|x| format!("{x:#}"))) {
+            Ok(x) => x.read(path, trace),
+            Err(err) => Err(anyhow!("{err}")),
+        }
+    }
+
+    /// All retries of a tick wait at most half of the poll rate together, so it is not delayed into the next one
+    fn retry_budget(&self) -> std::time::Duration {
+        self.config.poll_rate / 2
+    }
+
+    /// Colors for alarms printed to stderr
+    fn alarm_colors(&self) -> Option<&config::Colors> {
+        use std::io::IsTerminal;
+
+        color::enabled(self.args.color, std::io::stderr().is_terminal()).then_some(&self.config.colors)
+    }
+}
+
+impl config::Retry for Context {
+    fn refetch(&self, attempt: u32) -> Result<JsonValue> {
+        let mut refetched = self.refetched.borrow_mut();
+
+        // sensors failing on the same attempt use the same data, `sensors` is not run for each of them
+        while refetched.len() < attempt as usize {
+            refetched.push(get_config_temps(&self.config).map_err(|x| format!("{x:#}")));
+        }
+
+        refetched[attempt as usize - 1].clone().map_err(|x| anyhow!("{x}"))
+    }
+
+    fn wait(&self, delay: std::time::Duration) -> bool {
+        let left = self.retry_budget().saturating_sub(self.retry_waited.get());
+        if delay > left {
+            return false;
+        }
+
+        self.retry_waited.set(self.retry_waited.get() + delay);
+        match &self.waiter {
+            Some(x) => x.pause(delay),
+            None => {
+                std::thread::sleep(delay);
+                true
+            },
+        }
+    }
 }
 
 trait Widget {
@@ -40,8 +321,55 @@ trait Widget {
         // update is not required for all widgets
         Ok(())
     }
+
+    /// Currently active alarm if any
+    fn alarm(&self) -> Option<ActiveAlarm> {
+        None
+    }
+
+    /// Sensor the widget is showing
+    fn sensor(&self) -> Option<&Sensor> {
+        None
+    }
+
+    /// Placeholders derived from the value like `{cpu_rate}` with their values, if they could be computed
+    fn derived(&self) -> Vec<(String, Option<String>)> {
+        vec![]
+    }
+
+    /// Last successful reading of the sensor
+    fn reading(&self) -> Option<&Reading> {
+        None
+    }
+
+    /// Error from the last reading of the sensor
+    fn error(&self) -> Option<&str> {
+        None
+    }
+
+    /// Steps of the last reading, only kept with `--verbose-json`
+    fn trace(&self) -> Option<&config::ReadTrace> {
+        None
+    }
+
+    /// Alarm state of the sensor, carried over when the config is reloaded
+    fn alarm_tracker(&self) -> Option<&AlarmTracker> {
+        None
+    }
+
+    fn set_alarm_tracker(&mut self, _tracker: AlarmTracker) {}
+
+    /// Statistics of the session, carried over when the config is reloaded
+    fn stats(&self) -> Option<&SensorStats> {
+        None
+    }
+
+    fn set_stats(&mut self, _stats: SensorStats) {}
 }
 
+/// Widgets with their format variable, kept in config order
+type Widgets = Vec<(String, Box<dyn Widget>)>;
+
 #[derive(Debug)]
 struct CPUUsageWidget {
     last_idle: u64,
@@ -122,7 +450,7 @@ impl CPUUsageWidget {
         self.last_sum = sum;
 
         // cpu count wont change so initialize it once
-        let cpu_count = *self.cpu_count.get_or_init(|| Self::get_cpu_count());
+        let cpu_count = *self.cpu_count.get_or_init(Self::get_cpu_count);
 
         // clamp to 0-100
         Ok(((usage as f64 / cpu_count as f64) * 0.01).clamp(0.0, 100.0))
@@ -140,14 +468,322 @@ impl Widget for CPUUsageWidget {
     }
 }
 
+/// Compute value of a virtual sensor from values read this tick, inputs are evaluated before it
+fn virtual_value(sensor: &Sensor, values: &std::collections::HashMap<String, f64>) -> Result<f64> {
+    let inputs = sensor.inputs.iter()
+        .map(|x| values.get(x).copied().with_context(|| anyhow!("Input {x:?} could not be read")))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(sensor.op.unwrap().apply(&inputs))
+}
+
+/// Alarm of a sensor with everything needed to act on its transitions
+#[derive(Debug, Default)]
+struct SensorAlarm {
+    tracker: AlarmTracker,
+    commands: AlarmCommands,
+    active: Option<ActiveAlarm>,
+}
+
+impl SensorAlarm {
+    /// Evaluate the value, notifying and running commands when the state changes
+    fn update(&mut self, ctx: &Context, sensor: &Sensor, value: f64, now: std::time::Instant) -> alarm::AlarmState {
+        let mut previous = self.tracker.state;
+        let state = self.tracker.update(sensor, value, now);
+
+        // repeating the alarm is the same as it firing again
+        if self.tracker.repeat_due(sensor, now) {
+            previous = alarm::AlarmState::Normal;
+        }
+
+        if ctx.alarms_enabled() {
+            self.active = alarm::alarm_message(sensor, state, value);
+
+            if ctx.args.daemon {
+                alarm::notify_transition(sensor, previous, state, value);
+
+                // sound is reserved for critical alarms
+                if previous != state && state.severity() == alarm::Severity::Critical {
+                    ctx.sound_requested.set(true);
+                }
+            }
+
+            self.commands.reap(sensor);
+            if let Err(err) = self.commands.run_transition(sensor, previous, state, value) {
+                log::error!("{err:#}");
+            }
+
+            if ctx.args.daemon && previous != state && let Some(webhook) = &sensor.alarm_webhook {
+                webhook::send(webhook, &webhook::Event::new(sensor, previous, state, value), &ctx.values.borrow());
+            }
+        }
+
+        state
+    }
+}
+
+/// Rate of change of a sensor, alarmed on as its own sensor
+#[derive(Debug)]
+struct SensorRateState {
+    var: String,
+    sensor: Sensor,
+    tracker: rate::RateTracker,
+    alarm: SensorAlarm,
+    value: Option<f64>,
+}
+
+impl SensorRateState {
+    fn new(sensor: &Sensor) -> Option<Self> {
+        let sensor = rate::rate_sensor(sensor)?;
+
+        Some(Self {
+            var: format_var(&sensor.name),
+            sensor,
+            tracker: Default::default(),
+            alarm: Default::default(),
+            value: None,
+        })
+    }
+
+    /// Update with the value read at `now`, returns the alarm state of the rate if there is one
+    fn update(&mut self, ctx: &Context, value: f64, now: std::time::Instant) -> alarm::AlarmState {
+        self.value = self.tracker.update(value, now);
+
+        match self.value {
+            Some(rate) => self.alarm.update(ctx, &self.sensor, rate, now),
+            // there is nothing to alarm on until the second reading
+            None => alarm::AlarmState::Normal,
+        }
+    }
+
+    /// Rate cannot be computed across a failed read
+    fn fail(&mut self) {
+        self.tracker.reset();
+        self.value = None;
+        self.alarm.active = None;
+    }
+}
+
 #[derive(Debug)]
 struct SensorWidget {
     sensor: Sensor,
+    alarm: SensorAlarm,
+    rate: Option<SensorRateState>,
+    reading: Option<Reading>,
+    error: Option<String>,
+    trace: Option<config::ReadTrace>,
+    stats: SensorStats,
+    smoother: smoothing::Smoother,
+    schedule: schedule::Schedule,
+
+    /// Output of the last reading, shown again until the sensor is due
+    output: Option<String>,
+
+    /// Last successful reading with its output and when it was read, only kept with `stale_for`
+    fresh: Option<(std::time::Instant, Reading, String)>,
+}
+
+impl SensorWidget {
+    fn new(sensor: Sensor) -> Self {
+        Self {
+            rate: SensorRateState::new(&sensor),
+            sensor,
+            alarm: SensorAlarm::default(),
+            reading: None,
+            error: None,
+            trace: None,
+            stats: SensorStats::default(),
+            smoother: smoothing::Smoother::default(),
+            schedule: schedule::Schedule::default(),
+            output: None,
+            fresh: None,
+        }
+    }
 }
 
 impl Widget for SensorWidget {
     fn value(&mut self, ctx: &Context) -> Result<String> {
-        Ok(self.sensor.format_value(self.sensor.get_value(&ctx.sensors_data)?))
+        let now = ctx.now();
+
+        // slow sensors keep the last reading until their own poll rate elapses
+        if let (Some(reading), Some(output)) = (&mut self.reading, &self.output)
+            && !self.schedule.due(self.sensor.poll_rate, now) {
+            reading.cached = true;
+            ctx.values.borrow_mut().insert(self.sensor.name.clone(), reading.value);
+            return Ok(output.clone());
+        }
+
+        self.reading = None;
+        self.error = None;
+        self.output = None;
+
+        // only traced for `--verbose-json` and `--record`, so polling does not keep copies of everything it read
+        let mut trace = (ctx.args.verbose_json || ctx.args.record.is_some()).then(config::ReadTrace::default);
+        let raw = match (&self.sensor.source, &mut trace) {
+            (SensorSource::Virtual, _) => virtual_value(&self.sensor, &ctx.values.borrow()),
+
+            // recorded data is read once, retrying would give the same result
+            (SensorSource::Sensors, Some(trace)) if ctx.args.replay.is_some() => self.sensor.read_traced(ctx.sensors_data.as_ref(), trace),
+            (SensorSource::Sensors, None) if ctx.args.replay.is_some() => self.sensor.read_raw(ctx.sensors_data.as_ref()),
+            (_, trace) if let Some(tick) = ctx.replayed() => tick.read(&self.sensor.name, trace.as_mut().unwrap_or(&mut config::ReadTrace::default())),
+            (SensorSource::Nvidia, trace) => ctx.read_nvidia(&self.sensor.path, trace.as_mut().unwrap_or(&mut config::ReadTrace::default())),
+            (_, trace) => self.sensor.read_retrying(ctx.sensors_data.as_ref(), trace.as_mut().unwrap_or(&mut config::ReadTrace::default()), ctx),
+        };
+
+        if let Some(trace) = &trace {
+            ctx.record(&self.sensor, &raw, trace);
+        }
+        self.trace = trace.filter(|_| ctx.args.verbose_json);
+
+        let raw = match raw {
+            Ok(x) => x,
+
+            // alarm and rate are kept as they were, the value did not change as far as anyone knows
+            Err(err) if let Some(stale_for) = self.sensor.stale_for
+                && let Some((read_at, reading, output)) = &self.fresh
+                && now.duration_since(*read_at) <= stale_for => {
+                log::debug!("Showing last value of sensor {:?} as reading failed: {err:#}", self.sensor.name);
+
+                let output = color::stale(output, ctx.value_colors().is_some());
+                ctx.values.borrow_mut().insert(self.sensor.name.clone(), reading.value);
+
+                self.reading = Some(Reading { stale: true, ..reading.clone() });
+                self.output = Some(output.clone());
+
+                return Ok(output);
+            },
+            Err(err) => {
+                self.error = Some(format!("{err:#}"));
+
+                // alarm cannot be evaluated without a value
+                self.alarm.active = None;
+
+                if let Some(rate) = &mut self.rate {
+                    rate.fail();
+                }
+
+                return Err(err).with_context(|| anyhow!("Unable to read sensor {:?}", self.sensor.name));
+            },
+        };
+
+        let processed = match &mut self.trace {
+            Some(trace) => self.sensor.process_traced(raw, trace),
+            None => self.sensor.process(raw),
+        };
+
+        let unsmoothed = self.sensor.convert_unit(processed);
+        if let Some(trace) = &mut self.trace {
+            trace.converted = Some(unsmoothed);
+        }
+        let value = match &self.sensor.smoothing {
+            Some(x) => self.smoother.smooth(x, unsmoothed),
+            None => unsmoothed,
+        };
+
+        // compare the mapped value, the same one user sees unless smoothing would hide a spike
+        let alarm_value = if self.sensor.alarm_on_raw.unwrap_or(true) { unsmoothed } else { value };
+
+        self.schedule.read(now);
+        let state = self.alarm.update(ctx, &self.sensor, alarm_value, now);
+
+        self.stats.update(value);
+        ctx.values.borrow_mut().insert(self.sensor.name.clone(), value);
+
+        let mut reading = Reading::new(&self.sensor, raw, value, state);
+        reading.stats = Some(self.stats);
+
+        if let Some(rate) = &mut self.rate {
+            let rate_state = rate.update(ctx, alarm_value, now);
+            reading.rate = rate.value;
+            reading.severity = reading.severity.max(rate_state.severity());
+        }
+
+        let colored = match ctx.value_colors().and_then(|x| color::value_color(x, &self.sensor, value, reading.severity)) {
+            Some(code) => color::paint(&reading.formatted, code),
+            None => reading.formatted.clone(),
+        };
+
+        let formatted = if ctx.config.show_stats {
+            format!(
+                "{colored} (min {} / max {})",
+                self.sensor.format_value(self.stats.min),
+                self.sensor.format_value(self.stats.max),
+            )
+        } else {
+            colored
+        };
+
+        if self.sensor.stale_for.is_some() {
+            self.fresh = Some((now, reading.clone(), formatted.clone()));
+        }
+
+        self.reading = Some(reading);
+        self.output = Some(formatted.clone());
+
+        Ok(formatted)
+    }
+
+    fn alarm(&self) -> Option<ActiveAlarm> {
+        let rate = self.rate.as_ref().and_then(|x| x.alarm.active.as_ref());
+
+        // the more severe one is shown
+        match (&self.alarm.active, rate) {
+            (Some(value), Some(rate)) if rate.severity > value.severity => Some(rate.clone()),
+            (None, Some(rate)) => Some(rate.clone()),
+            (value, _) => value.clone(),
+        }
+    }
+
+    fn derived(&self) -> Vec<(String, Option<String>)> {
+        let mut derived = vec![];
+
+        if let Some(rate) = &self.rate {
+            derived.push((rate.var.clone(), rate.value.map(|x| rate.sensor.format_value(x))));
+        }
+
+        if let Some(bar) = &self.sensor.bar && let Some(var) = self.sensor.bar_name() {
+            // reading is already mapped, so it is measured against the output range
+            let value = match (self.sensor.value_range(), &self.reading) {
+                ((Some(min), Some(max)), Some(reading)) => Some(bar::render(bar, min, max, reading.value)),
+                _ => None,
+            };
+
+            derived.push((format_var(&var), value));
+        }
+
+        derived
+    }
+
+    fn sensor(&self) -> Option<&Sensor> {
+        Some(&self.sensor)
+    }
+
+    fn reading(&self) -> Option<&Reading> {
+        self.reading.as_ref()
+    }
+
+    fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    fn trace(&self) -> Option<&config::ReadTrace> {
+        self.trace.as_ref()
+    }
+
+    fn alarm_tracker(&self) -> Option<&AlarmTracker> {
+        Some(&self.alarm.tracker)
+    }
+
+    fn set_alarm_tracker(&mut self, tracker: AlarmTracker) {
+        self.alarm.tracker = tracker;
+    }
+
+    fn stats(&self) -> Option<&SensorStats> {
+        Some(&self.stats)
+    }
+
+    fn set_stats(&mut self, stats: SensorStats) {
+        self.stats = stats;
     }
 }
 
@@ -155,11 +791,14 @@ impl Widget for SensorWidget {
 struct TimeWidget;
 
 impl Widget for TimeWidget {
-    fn value(&mut self, _ctx: &Context) -> Result<String> {
-        Ok(chrono::Local::now().format("%H:%M:%S").to_string())
+    fn value(&mut self, ctx: &Context) -> Result<String> {
+        Ok(ctx.local_time().format("%H:%M:%S").to_string())
     }
 }
 
+/// Names of widgets that are not sensors
+const BUILTIN_VARS: &[&str] = &["time", "cpu_usage"];
+
 fn format_var(var: &str) -> String {
     // very simple "{var}" formatter
     format!("{{{var}}}")
@@ -174,118 +813,1559 @@ impl Widget for DummyWidget {
     }
 }
 
-const MINIMAL_POLL_RATE: u16 = 1000;
+const MINIMAL_POLL_RATE: std::time::Duration = std::time::Duration::from_secs(1);
 
-// TODO warn user of any panic or crash!
-fn main() -> Result<()> {
-    let args = cli::Cli::parse();
+/// Indices of widgets in the order they have to be evaluated, virtual sensors after their inputs
+fn evaluation_order(widgets: &Widgets) -> Vec<usize> {
+    let mut order = vec![];
+    let mut evaluated = vec![];
+    let mut pending = (0..widgets.len()).collect::<Vec<_>>();
 
-    let config = if let Some(path) = &args.config {
-        Config::read_from_file(&path)?
-    } else {
-        Config::read_config()?
-    };
+    while !pending.is_empty() {
+        let (mut ready, waiting): (Vec<_>, Vec<_>) = pending.into_iter()
+            .partition(|i| widgets[*i].1.sensor().is_none_or(|x| x.inputs.iter().all(|x| evaluated.contains(&x))));
 
-    if config.poll_rate < MINIMAL_POLL_RATE {
-        bail!("Poll rate must be at least {}ms", MINIMAL_POLL_RATE);
-    }
+        // config rejects cycles, a missing input is reported when the value is computed
+        if ready.is_empty() {
+            ready = waiting;
+            pending = vec![];
+        } else {
+            pending = waiting;
+        }
 
-    if args.kill {
-        todo!();
+        evaluated.extend(ready.iter().filter_map(|i| widgets[*i].1.sensor()).map(|x| &x.name));
+        order.extend(ready);
     }
 
-    // TODO daemon mode
-    if args.daemon {
-        todo!();
-    }
+    order
+}
 
-    // struct to hold all the data that widgets have access to
-    let mut ctx = Context {
-        args,
-        config,
-        sensors_data: get_temps()?,
-    };
+/// Replace all placeholders, failed widgets are replaced by `unavailable` text and their errors returned
+///
+/// Widgets are sorted by `sort` afterwards, as sorting by value needs the values of this tick
+///
+/// Fails if a required sensor or every sensor failed
+fn update_format(ctx: &Context, format: &mut String, widgets: &mut Widgets) -> Result<Vec<anyhow::Error>> {
+    let mut errors = vec![];
+    let mut sensors = 0;
+    let mut failed_sensors = 0;
 
-    // TODO if no format just list all in order
-    if ctx.args.no_format || ctx.config.format.is_none() {
-        todo!();
-    }
+    ctx.values.borrow_mut().clear();
+    ctx.nvidia.borrow_mut().take();
+    ctx.refetched.borrow_mut().clear();
+    ctx.retry_waited.set(std::time::Duration::ZERO);
 
-    // TODO alarms
+    let mut values = vec![String::new(); widgets.len()];
+    for i in evaluation_order(widgets) {
+        let widget = &mut widgets[i].1;
+        let is_sensor = widget.sensor().is_some();
+        sensors += usize::from(is_sensor);
 
-    let mut widgets: HashMap<String, Box<dyn Widget>> = HashMap::new();
+        values[i] = match widget.value(ctx) {
+            Ok(x) => x,
+            Err(err) => {
+                if widget.sensor().is_some_and(|x| x.required) {
+                    return Err(err);
+                }
 
-    // only create widgets that are actually used
-    {
-        let format = ctx.config.format.as_ref().unwrap();
+                failed_sensors += usize::from(is_sensor);
+                errors.push(err);
+                ctx.config.unavailable.clone()
+            },
+        };
+    }
 
-        // filtering out sensors that are not used
-        //
-        // NOTE: i am taking the sensors vector to simplify the ownership
-        for sensor in std::mem::take(&mut ctx.config.sensors) {
-            let var = format_var(&sensor.name);
-            if format.contains(&var) {
-                widgets.insert(var, Box::new(SensorWidget { sensor: sensor }));
-            }
-        }
+    if sensors > 0 && failed_sensors == sensors {
+        let err = errors.into_iter().next().unwrap();
+        return Err(err.context("All sensors failed"));
+    }
 
-        let var = format_var("time");
-        if format.contains(&var) {
-            widgets.insert(var, Box::new(TimeWidget));
-        }
+    let mut items = std::mem::take(widgets).into_iter().zip(values).collect::<Vec<_>>();
+    sort::sort(&mut items, ctx.config.sort, |((_, x), _)| Some((x.sensor()?, x.reading().map(|x| x.value))));
 
-        let var = format_var("cpu_usage");
-        if format.contains(&var) {
-            if ctx.args.once {
-                // cpu usage cannot be calculated quickly
-                widgets.insert(var, Box::new(DummyWidget("??".to_string())));
-            } else {
-                widgets.insert(var, Box::new(CPUUsageWidget::new()));
+    // each line of the verbose format has a single sensor, so the lines are sorted with them
+    if ctx.config.verbose {
+        let mut lines = format.lines().map(Some).collect::<Vec<_>>();
+        let mut sorted = vec![];
+
+        for ((var, _), _) in &items {
+            if let Some(line) = lines.iter_mut().find(|x| x.is_some_and(|x| x.contains(var.as_str()))) {
+                sorted.extend(line.take());
             }
         }
+
+        sorted.extend(lines.into_iter().flatten());
+        *format = sorted.join("\n");
     }
 
-    fn update_format(ctx: &Context, format: &mut String, widgets: &mut HashMap<String, Box<dyn Widget>>) -> Result<()> {
-        // replace all instances
-        for (var, widget) in widgets.iter_mut() {
-            *format = format.replace(var, &widget.value(&ctx)?);
+    // replace all instances
+    for ((var, widget), value) in items {
+        *format = format.replace(var.as_str(), &value);
+
+        // computed along with the value
+        for (var, value) in widget.derived() {
+            *format = format.replace(&var, value.as_deref().unwrap_or(&ctx.config.unavailable));
         }
 
-        Ok(())
+        widgets.push((var, widget));
     }
 
-    let mut format = ctx.config.format.as_ref().unwrap().clone();
+    Ok(errors)
+}
 
-    if ctx.args.once {
-        update_format(&ctx, &mut format, &mut widgets)?;
+/// Render output of a tick in the requested format
+///
+/// Timestamp starts every line of text output and is added to json, other formats have their own or none
+fn render(ctx: &Context, text: &str, widgets: &Widgets, outputs: &[fan::OutputStatus], timestamp: Option<&output::Timestamp>) -> Result<String> {
+    let prefix = timestamp.map(|x| format!("{x} ")).unwrap_or_default();
 
-        println!("{}", format);
-    } else {
-        use std::thread::sleep;
-        use std::time::Duration;
+    match ctx.args.output_format() {
+        OutputFormat::Text => Ok(text.lines().map(|x| format!("{prefix}{x}")).collect::<Vec<_>>().join("\n")),
+        OutputFormat::Line => Ok(format!("{prefix}{}", text.replace('\n', " "))),
+        OutputFormat::Table => Ok(table::table(widgets, &ctx.config.unavailable, ctx.value_colors())),
+        OutputFormat::Json => output::json(widgets, outputs, timestamp),
+        OutputFormat::Waybar => output::waybar(text, widgets, &ctx.config.unavailable),
+        OutputFormat::Prometheus => Ok(output::prometheus(&output::readings(widgets))),
+        OutputFormat::Nagios => Ok(output::nagios(widgets, &ctx.config.unavailable)),
+        OutputFormat::Influx => Ok(output::influx(&output::readings(widgets), &config::get_hostname()?, ctx.local_time().timestamp_nanos_opt().unwrap_or_default())),
+        OutputFormat::Graphite => {
+            let prefix = ctx.config.graphite.as_ref().map(|x| x.prefix.clone()).unwrap_or_else(config::GraphiteConfig::default_prefix);
+            Ok(output::graphite(&output::readings(widgets), &prefix, &config::get_hostname()?, ctx.local_time().timestamp()))
+        },
+    }
+}
 
-        loop {
-            update_format(&ctx, &mut format, &mut widgets)?;
-            println!("{CLEAR_SEQ}{format}");
+/// Print rendered output or write it to the textfile
+fn emit(ctx: &Context, text: &str, widgets: &Widgets, outputs: &[fan::OutputStatus], timestamp: Option<&output::Timestamp>) -> Result<()> {
+    let output = render(ctx, text, widgets, outputs, timestamp)?;
 
-            if ctx.config.poll_rate > MINIMAL_POLL_RATE {
-                sleep(Duration::from_millis((ctx.config.poll_rate - MINIMAL_POLL_RATE).into()));
-            }
+    match &ctx.args.textfile {
+        Some(path) => output::write_atomic(path, &format!("{output}\n")),
+        None => {
+            // consumers like polybar read it as soon as it is written
+            let mut stdout = std::io::stdout().lock();
+            writeln!(stdout, "{output}").and_then(|_| stdout.flush())
+                .with_context(|| anyhow!("Unable to write output"))
+        },
+    }
+}
 
-            // update all widgets
-            for (_, widget) in widgets.iter_mut() {
-                widget.update(&ctx)?;
-            }
+/// Output of a tick in loop mode, the daemon logs it and writes only the textfile
+fn show_tick(ctx: &Context, text: &str, widgets: &Widgets, outputs: &[fan::OutputStatus], timestamp: Option<&output::Timestamp>) -> Result<()> {
+    if ctx.args.daemon {
+        log::info!("{text}");
 
-            sleep(Duration::from_millis(MINIMAL_POLL_RATE.into()));
+        // node_exporter reads it from the daemon
+        return match ctx.args.textfile {
+            Some(_) => emit(ctx, text, widgets, outputs, timestamp),
+            None => Ok(()),
+        };
+    }
 
-            // reset format
-            format = ctx.config.format.as_ref().unwrap().clone();
+    if matches!(ctx.args.output_format(), OutputFormat::Text | OutputFormat::Table) && ctx.args.textfile.is_none() {
+        println!("{CLEAR_SEQ}{}", render(ctx, text, widgets, outputs, timestamp)?);
+        return Ok(());
+    }
 
-            // get fresh sensor data
-            ctx.sensors_data = get_temps()?;
+    emit(ctx, text, widgets, outputs, timestamp)
+}
+
+/// Report all active alarms, returns true if there were any
+fn report_alarms(ctx: &Context, widgets: &Widgets) -> bool {
+    let mut any_alarm = false;
+
+    for (_, widget) in widgets {
+        if let Some(alarm) = widget.alarm() {
+            any_alarm = true;
+            report_alarm(ctx, &alarm);
         }
     }
 
-    Ok(())
+    any_alarm
+}
+
+/// Print the alarm, daemon logs it instead
+fn report_alarm(ctx: &Context, alarm: &ActiveAlarm) {
+    if ctx.args.daemon {
+        log::warning!("{alarm}");
+    } else {
+        eprintln!("{}", alarm::highlight(alarm, ctx.alarm_colors()));
+    }
+}
+
+/// Load config from the path in arguments or search for it, and check it can be used for polling
+fn load_config(args: &cli::Cli) -> Result<Config> {
+    // overrides are applied on every load, so they are kept when the config is reloaded
+    let mut config = if let Some(path) = &args.config {
+        Config::read_from_file(path, &args.set)?
+    } else {
+        Config::read_config(args.merge_config, &args.set)?
+    };
+
+    if let Some(interval) = args.interval {
+        config.poll_rate = interval;
+    }
+
+    if !args.sensor.is_empty() {
+        config.select_sensors(&args.sensor)?;
+    }
+
+    if let Some(x) = args.sort {
+        config.sort = x;
+    }
+
+    if let Some(x) = args.timestamp {
+        config.timestamp = x;
+    }
+
+    if config.poll_rate < MINIMAL_POLL_RATE {
+        bail!("Poll rate must be at least {}", duration::format(MINIMAL_POLL_RATE));
+    }
+
+    if config.idle.is_some() {
+        match config.idle_poll_rate {
+            None => bail!("idle_poll_rate is required when idle detection is enabled"),
+            Some(x) if x < MINIMAL_POLL_RATE => bail!("Idle poll rate must be at least {}", duration::format(MINIMAL_POLL_RATE)),
+            Some(_) => {},
+        }
+    }
+
+    // without format list all sensors in order, one per line
+    config.format = match config.format.take() {
+        // custom format could use sensors that were not selected
+        Some(format) if !args.no_format && args.sensor.is_empty() => {
+            config.check_format(&format, BUILTIN_VARS)?;
+
+            // output is often consumed by status bars, so it has to stay a single line
+            // stats are only shown in the verbose output
+            config.show_stats = false;
+
+            Some(format.trim_end_matches(['\r', '\n']).to_string())
+        },
+        _ => {
+            config.verbose = true;
+            Some(config.verbose_format(&args.sensor))
+        },
+    };
+
+    Ok(config)
+}
+
+/// Create widgets that are actually used in format
+///
+/// NOTE: sensors are taken out of the config to simplify the ownership
+fn create_widgets(args: &cli::Cli, config: &mut Config) -> Widgets {
+    let mut widgets: Widgets = vec![];
+    let format = config.format.as_ref().unwrap();
+
+    // machine readable output always contains all sensors
+    let mut used = config.sensors.iter()
+        .map(|x| {
+            let derived_used = x.derived_names().any(|x| format.contains(&format_var(&x)));
+            !matches!(args.output_format(), OutputFormat::Text | OutputFormat::Line) || format.contains(&format_var(&x.name)) || derived_used
+                || config.outputs.iter().any(|output| output.sources().contains(&x.name))
+        })
+        .collect::<Vec<_>>();
+
+    // inputs of virtual sensors are read even if not shown, virtual sensors come after the sensors they use
+    for (i, sensor) in config.sensors.iter().enumerate().rev() {
+        if used[i] {
+            for (j, input) in config.sensors.iter().enumerate() {
+                used[j] |= sensor.inputs.contains(&input.name);
+            }
+        }
+    }
+
+    // filtering out sensors that are not used
+    for (sensor, used) in std::mem::take(&mut config.sensors).into_iter().zip(used) {
+        if used {
+            widgets.push((format_var(&sensor.name), Box::new(SensorWidget::new(sensor))));
+        }
+    }
+
+    let var = format_var("time");
+    if format.contains(&var) {
+        widgets.push((var, Box::new(TimeWidget)));
+    }
+
+    let var = format_var("cpu_usage");
+    if format.contains(&var) {
+        if args.once {
+            // cpu usage cannot be calculated quickly
+            widgets.push((var, Box::new(DummyWidget("??".to_string()))));
+        } else {
+            widgets.push((var, Box::new(CPUUsageWidget::new())));
+        }
+    }
+
+    widgets
+}
+
+/// lm_sensors is not required unless it is actually used
+fn uses_sensors(widgets: &Widgets) -> bool {
+    widgets.iter().any(|(_, x)| x.sensor().is_some_and(|x| matches!(x.source, SensorSource::Sensors)))
+}
+
+/// Load the config again and replace widgets, keeping the old ones if the config is not valid
+///
+/// Alarm state is carried over for sensors with the same name so notifications do not fire again,
+/// and so are the statistics of the session
+fn reload(ctx: &mut Context, widgets: &mut Widgets) -> Result<()> {
+    if ctx.args.config.as_deref() == Some(std::path::Path::new("-")) {
+        bail!("Config was read from stdin, it cannot be read again");
+    }
+
+    let mut config = load_config(&ctx.args)?;
+    let mut new_widgets = create_widgets(&ctx.args, &mut config);
+
+    for (var, widget) in new_widgets.iter_mut() {
+        let Some((_, old)) = widgets.iter().find(|(x, _)| x == var) else {
+            continue;
+        };
+
+        if let Some(tracker) = old.alarm_tracker() {
+            widget.set_alarm_tracker(tracker.clone());
+        }
+
+        if let Some(stats) = old.stats() {
+            widget.set_stats(*stats);
+        }
+    }
+
+    ctx.config = config;
+    *widgets = new_widgets;
+
+    Ok(())
+}
+
+/// Why waiting for the next tick ended early
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Interrupt {
+    /// SIGTERM or SIGINT
+    Shutdown,
+
+    /// SIGUSR1, tick right away
+    Refresh,
+}
+
+/// Sleep for the duration keeping the watchdog fed, returns early on signals that need handling right away
+fn wait(waiter: &signal::Waiter, notifier: &mut Option<sdnotify::Notifier>, duration: std::time::Duration) -> Option<Interrupt> {
+    let deadline = std::time::Instant::now() + duration;
+
+    loop {
+        if let Some(x) = notifier && let Err(err) = x.watchdog() {
+            log::error!("{err:#}");
+        }
+
+        let now = std::time::Instant::now();
+        if now >= deadline {
+            return None;
+        }
+
+        let until = notifier.as_ref()
+            .and_then(|x| x.next_ping())
+            .map_or(deadline, |x| x.min(deadline));
+
+        // other signals are handled after the tick
+        match waiter.wait(until.saturating_duration_since(now)) {
+            Some(signal::SIGTERM | signal::SIGINT) => return Some(Interrupt::Shutdown),
+            Some(signal::SIGUSR1) => {
+                signal::take(signal::SIGUSR1);
+                return Some(Interrupt::Refresh);
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Only the daemon takes control of fans, a foreground instance would fight it over them
+///
+/// With `--dry-run` or `--replay` nothing is written, so they are computed in the foreground too
+fn start_outputs(ctx: &Context) -> Result<Vec<fan::FanOutput>> {
+    // fans are not following the machine that was recorded
+    let dry_run = ctx.args.dry_run || ctx.args.replay.is_some();
+
+    if !ctx.args.daemon && !dry_run {
+        if !ctx.config.outputs.is_empty() {
+            log::warning!("Ignoring outputs, only the daemon drives them, use --dry-run to compute them");
+        }
+
+        return Ok(vec![]);
+    }
+
+    fan::outputs(&ctx.config.outputs, dry_run)
+}
+
+/// Only the daemon records history, so there is a single writer
+fn open_history(ctx: &Context) -> Result<Option<history::History>> {
+    match &ctx.config.history {
+        Some(x) if ctx.args.daemon => Ok(Some(history::History::open(x)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Only the daemon publishes, a second client with the same id would take over its connection
+#[cfg(feature = "mqtt")]
+fn start_mqtt(ctx: &Context, widgets: &Widgets) -> Result<Option<mqtt::Publisher>> {
+    match &ctx.config.mqtt {
+        Some(x) if ctx.args.daemon => {
+            let mut publisher = mqtt::Publisher::start(x, &config::get_hostname()?)?;
+            let sensors = widgets.iter().filter_map(|(_, x)| x.sensor()).collect::<Vec<_>>();
+            if let Err(err) = publisher.announce(&sensors, &daemon::discovery_file_path()) {
+                log::error!("Unable to announce sensors to Home Assistant: {err:#}");
+            }
+
+            Ok(Some(publisher))
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Only the daemon writes to InfluxDB, so points are not duplicated
+fn start_influx(ctx: &Context) -> Result<Option<influx::Writer>> {
+    match &ctx.config.influx {
+        Some(x) if ctx.args.daemon => Ok(Some(influx::Writer::start(x, &config::get_hostname()?)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Only the daemon sends to Graphite, so points are not duplicated
+fn start_graphite(ctx: &Context) -> Result<Option<graphite::Sender>> {
+    match &ctx.config.graphite {
+        Some(x) if ctx.args.daemon => Ok(Some(graphite::Sender::start(x, &config::get_hostname()?)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Only the daemon serves readings, it holds the lock so the address is not taken by another instance
+fn start_http(ctx: &Context) -> Result<Option<endpoint::HttpServer>> {
+    match &ctx.config.http {
+        Some(x) if ctx.args.daemon => Ok(Some(endpoint::HttpServer::start(x)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Only the daemon owns the bus name, a second one would fail to get it anyway
+#[cfg(feature = "dbus")]
+fn start_dbus(ctx: &Context) -> Result<Option<dbus::Service>> {
+    match &ctx.config.dbus {
+        Some(x) if ctx.args.daemon => Ok(Some(dbus::Service::start(x)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Print readings of the daemon from D-Bus, for `status --dbus`
+#[cfg(feature = "dbus")]
+fn status_dbus(args: &cli::Cli, json: bool) -> Result<()> {
+    let config = load_config(args).ok().and_then(|x| x.dbus).unwrap_or_default();
+    let readings = dbus::query(&config)?;
+
+    if json {
+        println!("{}", serde_json::json!({ "readings": readings }));
+        return Ok(());
+    }
+
+    for reading in readings {
+        println!("{}: {} {} ({})", reading.name, reading.value, reading.unit, reading.alarm);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "dbus"))]
+fn status_dbus(_args: &cli::Cli, _json: bool) -> Result<()> {
+    bail!("kelvin was built without the dbus feature")
+}
+
+/// Open CSV log from arguments or the config if any
+fn open_csv(ctx: &Context, widgets: &Widgets) -> Result<Option<csv::CsvLogger>> {
+    match ctx.args.log_csv.as_ref().or(ctx.config.log_csv.as_ref()) {
+        Some(path) => Ok(Some(csv::CsvLogger::open(path, widgets)?)),
+        None => Ok(None),
+    }
+}
+
+/// Status for `--check`, failure of a required sensor takes precedence over alarms as the result is incomplete
+fn check_status(result: &Result<Vec<anyhow::Error>>, widgets: &Widgets) -> output::CheckStatus {
+    match result {
+        Ok(_) => output::CheckStatus::from_widgets(widgets),
+        Err(_) => output::CheckStatus::Unknown,
+    }
+}
+
+fn exit_check(result: &Result<Vec<anyhow::Error>>, widgets: &Widgets) -> ! {
+    if let Err(err) = result {
+        log::error!("{err:#}");
+    }
+
+    std::process::exit(check_status(result, widgets) as i32);
+}
+
+/// Nagios plugins report failures on stdout with UNKNOWN status
+fn exit_unknown(err: anyhow::Error) -> ! {
+    println!("{}", output::nagios_unknown(&err));
+    std::process::exit(output::CheckStatus::Unknown as i32);
+}
+
+/// Take the daemon lock, stopping the running daemon first with `--replace`
+fn take_over(args: &cli::Cli) -> Result<daemon::InstanceLock> {
+    if let Some(lock) = daemon::lock_instance()? {
+        return Ok(lock);
+    }
+
+    if !args.replace {
+        match daemon::running_daemon_pid() {
+            Some(pid) => bail!("Daemon is already running with pid {pid}, use --replace to restart it"),
+            None => bail!("Daemon is already running, use --replace to restart it"),
+        }
+    }
+
+    let pid = daemon::kill_daemon(std::time::Duration::from_secs(args.kill_timeout))?;
+    log::info!("Stopped daemon with pid {pid}");
+
+    daemon::lock_instance()?
+        .with_context(|| anyhow!("Daemon lock {:?} is still held after stopping the daemon", daemon::lock_file_path()))
+}
+
+fn run_command(args: &cli::Cli, command: &cli::Command) -> Result<()> {
+    use cli::{Command, ConfigCommand};
+
+    match command {
+        Command::Config(ConfigCommand::Init { force, format, path }) => {
+            let path = match (path, format) {
+                (Some(x), _) => x.clone(),
+                (None, Some(format)) => Config::user_config_path()?.with_extension(format.extension()),
+                (None, None) => Config::user_config_path()?,
+            };
+
+            let format = format.unwrap_or_else(|| config::ConfigFormat::from_path(&path));
+            generate::init(&path, *force, format)
+        },
+        Command::Config(ConfigCommand::Validate { offline, path }) => {
+            let config = match path {
+                Some(x) => Config::read_from_file(x, &[])?,
+                None => Config::read_config(args.merge_config, &[])?,
+            };
+
+            let sensors = if *offline || !config.uses_sensors() { None } else { Some(get_config_temps(&config)?) };
+            let problems = validate::validate(&config, sensors.as_ref(), BUILTIN_VARS);
+
+            for problem in &problems {
+                println!("{problem}");
+            }
+
+            let errors = problems.iter().filter(|x| x.severity == validate::Severity::Error).count();
+            if errors > 0 {
+                bail!("Found {errors} errors in the config");
+            }
+
+            println!("Config is valid");
+
+            Ok(())
+        },
+        Command::Config(ConfigCommand::Show { resolved, path }) => {
+            let config = match path.as_ref().or(args.config.as_ref()) {
+                Some(x) => Config::read_from_file(x, &[])?,
+                None => Config::read_config(args.merge_config, &[])?,
+            };
+
+            if *resolved {
+                print!("{}", toml::to_string(&config).with_context(|| anyhow!("Unable to serialize the config"))?);
+            } else if let Some(path) = &config.path {
+                print!("{}", std::fs::read_to_string(path).with_context(|| anyhow!("Unable to read config from file {path:?}"))?);
+            }
+
+            Ok(())
+        },
+        Command::Config(ConfigCommand::ImportFancontrol { path }) => {
+            print!("{}", fancontrol::import(path)?);
+
+            Ok(())
+        },
+        Command::List { names: true, .. } => {
+            let config = match &args.config {
+                Some(x) => Config::read_from_file(x, &[])?,
+                None => Config::read_config(args.merge_config, &[])?,
+            };
+
+            for sensor in &config.sensors {
+                println!("{}", sensor.name);
+            }
+
+            Ok(())
+        },
+        Command::List { filter, hwmon, thermal, .. } => list::list(filter.as_deref(), *hwmon, *thermal),
+        Command::Completions { shell } => {
+            let mut cmd = <cli::Cli as clap::CommandFactory>::command();
+            cmd.build();
+
+            print!("{}", completions::generate(*shell, &cmd));
+            Ok(())
+        },
+        Command::Man => {
+            print!("{}", man::man_page(&<cli::Cli as clap::CommandFactory>::command()));
+            Ok(())
+        },
+        Command::Status { json, reset_stats, dbus } => {
+            if *dbus {
+                return status_dbus(args, *json);
+            }
+
+            if *reset_stats {
+                status::reset_stats(&status::socket_path())?;
+                println!("Statistics reset");
+                return Ok(());
+            }
+
+            let reply = status::query(&status::socket_path())?;
+
+            if *json {
+                println!("{}", serde_json::json!({ "readings": reply.readings }));
+                return Ok(());
+            }
+
+            println!("{}", reply.text);
+
+            for reading in reply.readings.as_array().into_iter().flatten() {
+                if let (Some(name), Ok(stats)) = (reading["name"].as_str(), serde_json::from_value::<SensorStats>(reading["stats"].clone())) {
+                    println!("{name}: min {:.1} / max {:.1} / avg {:.1}", stats.min, stats.max, stats.mean);
+                }
+            }
+
+            for output in &reply.outputs {
+                let value = output.value.map_or("not written".to_string(), |x| x.to_string());
+                let target = output.target.map_or(String::new(), |x| format!(", target {x}"));
+                let failsafe = if output.failsafe { " (failsafe)" } else { "" };
+                let dry_run = if output.dry_run { " (dry run)" } else { "" };
+                println!("output {}: {value}{target}{failsafe}{dry_run}", output.name);
+            }
+
+            use std::io::IsTerminal;
+            // colors of the config if there is one
+            let colors = load_config(args).map(|x| x.colors).unwrap_or_default();
+            let colors = color::enabled(args.color, std::io::stderr().is_terminal()).then_some(&colors);
+
+            for alarm in &reply.alarms {
+                eprintln!("{}", alarm::highlight(alarm, colors));
+            }
+
+            // same as `--once`
+            if !reply.alarms.is_empty() {
+                std::process::exit(1);
+            }
+
+            Ok(())
+        },
+        Command::TestAlarm { sensor } => {
+            let config = load_config(args)?;
+            let sensor = config.sensors.iter().find(|x| x.name == *sensor)
+                .with_context(|| anyhow!("There is no sensor {sensor:?} in the config"))?;
+            let webhook = sensor.alarm_webhook.as_ref()
+                .with_context(|| anyhow!("Sensor {:?} has no alarm_webhook and there is none in the config", sensor.name))?;
+
+            // the most severe alarm the sensor has a threshold for, with the value right at it
+            let state = [AlarmState::High, AlarmState::Low, AlarmState::WarnHigh, AlarmState::WarnLow].into_iter()
+                .find(|x| x.threshold(sensor).is_some())
+                .unwrap_or(AlarmState::High);
+            let event = webhook::Event::new(sensor, AlarmState::Normal, state, state.threshold(sensor).unwrap_or_default());
+
+            let response = webhook::deliver(webhook, &event.body(webhook, &Default::default())?)?;
+            println!("Webhook of sensor {:?} answered with status {}", sensor.name, response.status);
+            Ok(())
+        },
+        Command::History { sensor, since, json } => {
+            let config = load_config(args)?;
+            let path = config.history.map(|x| x.path).unwrap_or_else(config::HistoryConfig::default_path);
+
+            let since = chrono::Local::now() - chrono::Duration::from_std(*since)?;
+            let entries = history::query(&path, sensor, since)?;
+
+            if *json {
+                println!("{}", serde_json::to_string(&entries)?);
+                return Ok(());
+            }
+
+            // use sensor formatting when it is still in the config
+            let sensor_config = config.sensors.iter().find(|x| x.name == *sensor);
+
+            for entry in &entries {
+                let value = match sensor_config {
+                    Some(x) => x.format_labeled(entry.value),
+                    None => entry.value.to_string(),
+                };
+
+                println!("{} {value} {}", entry.timestamp.format("%Y-%m-%d %H:%M:%S"), entry.alarm);
+            }
+
+            Ok(())
+        },
+    }
+}
+
+fn main() -> Result<()> {
+    let args = cli::Cli::parse();
+
+    // daemon output goes to the log file and dry run is only seen in the log, so show more by default
+    let default_level = if args.daemon || args.dry_run { log::Level::Info } else { log::Level::Warn };
+    log::set_level(log::Level::from_verbosity(default_level, args.verbose, args.quiet));
+    log::debug!("{args:?}");
+
+    // nobody is watching the terminal of a daemon running in the background
+    let background = args.daemon && (daemon::is_detached_child() || daemon::is_systemd_service());
+    crash::install_panic_hook(background);
+
+    let result = run(args);
+
+    // fans must not stay in manual mode after exiting, also after errors
+    for err in fan::restore() {
+        log::error!("{err:#}");
+    }
+
+    if background && let Err(err) = &result {
+        crash::report(&format!("{err:?}"), true);
+    }
+
+    result
+}
+
+fn run(args: cli::Cli) -> Result<()> {
+    let stdin = std::path::Path::new("-");
+    if args.daemon && args.config.as_deref() == Some(stdin) {
+        bail!("Config cannot be read from stdin in daemon mode, use a file instead");
+    }
+
+    if args.config.as_deref() == Some(stdin) && args.sensors_json.as_deref() == Some(stdin) {
+        bail!("Config and sensors json cannot both be read from stdin");
+    }
+
+    if let Some(path) = &args.sensors_json {
+        if args.daemon && path == stdin {
+            bail!("Sensors json cannot be read from stdin in daemon mode, use a file instead");
+        }
+
+        SENSORS_JSON.set(SensorsJson::new(path)?).unwrap();
+    }
+
+    if let Some(command) = &args.command {
+        return run_command(&args, command);
+    }
+
+    if let Some(path) = &args.restore {
+        fan::restore_automatic(path)?;
+        println!("PWM channel {path:?} is in automatic mode");
+        return Ok(());
+    }
+
+    if args.kill {
+        let pid = daemon::kill_daemon(std::time::Duration::from_secs(args.kill_timeout))?;
+        println!("Daemon with pid {pid} has exited");
+        return Ok(());
+    }
+
+    let mut config = load_config(&args)?;
+
+    // held until the daemon exits
+    let mut _lock = None;
+
+    if args.daemon {
+        // systemd handles the background part itself
+        if !daemon::is_detached_child() && !daemon::is_systemd_service() {
+            // check early so the error is shown in the terminal, the child takes the lock again
+            drop(take_over(&args)?);
+
+            let pid = daemon::detach()?;
+            println!("Daemon started with pid {pid}, logging to {:?}", daemon::log_file_path());
+            return Ok(());
+        }
+
+        _lock = Some(take_over(&args)?);
+        daemon::write_pid_file()?;
+        log::info!("Daemon started with pid {}", std::process::id());
+    }
+
+    // errors from a single tick are reported after the output, so they are not cleared
+    let mut errors = vec![];
+
+    let mut widgets = create_widgets(&args, &mut config);
+
+    // struct to hold all the data that widgets have access to
+    let mut ctx = Context::new(args, config);
+
+    let mut replay = ctx.args.replay.as_deref().map(replay::Replay::open).transpose()?;
+    let mut recorder = ctx.args.record.as_deref().map(replay::Recorder::create).transpose()?;
+
+    if replay.is_some() {
+        // always has at least one tick
+        ctx.start_tick(replay.as_mut().and_then(|x| x.next()));
+    } else if uses_sensors(&widgets) {
+        ctx.sensors_data = match get_config_temps(&ctx.config) {
+            Ok(x) => Some(x),
+            // there is nothing to show without the data
+            Err(err) if ctx.args.once && ctx.args.output_format() == OutputFormat::Nagios => exit_unknown(err),
+            Err(err) if ctx.args.check => exit_check(&Err(err), &widgets),
+            Err(err) if ctx.args.once => return Err(err),
+            Err(err) => {
+                errors.push(err);
+                None
+            },
+        };
+    }
+
+    ctx.start_tick(None);
+
+    let mut format = ctx.config.format.as_ref().unwrap().clone();
+    let mut csv = open_csv(&ctx, &widgets)?;
+    let mut history = open_history(&ctx)?;
+
+    if ctx.args.once {
+        let nagios = ctx.args.output_format() == OutputFormat::Nagios;
+
+        let errors = match update_format(&ctx, &mut format, &mut widgets) {
+            Ok(x) => x,
+            Err(err) if nagios => exit_unknown(err),
+            Err(err) if ctx.args.check => exit_check(&Err(err), &widgets),
+            Err(err) => return Err(err),
+        };
+
+        if let Some(x) = &mut recorder && let Some(tick) = ctx.tick.take() {
+            x.write(&tick)?;
+        }
+
+        let now = ctx.local_time();
+        let timestamp = ctx.timestamp(now);
+        emit(&ctx, &format, &widgets, &[], timestamp.as_ref())?;
+
+        if let Some(x) = &mut csv {
+            x.write(now, timestamp.as_ref(), &widgets)?;
+        }
+
+        for err in &errors {
+            log::error!("{err:#}");
+        }
+
+        if nagios {
+            std::process::exit(output::CheckStatus::from_widgets(&widgets) as i32);
+        }
+
+        let any_alarm = report_alarms(&ctx, &widgets);
+
+        if ctx.args.check {
+            exit_check(&Ok(errors), &widgets);
+        }
+
+        if any_alarm {
+            std::process::exit(1);
+        }
+    } else {
+        let mut idle = ctx.config.idle.clone().map(IdleDetector::new);
+
+        let mut outputs = start_outputs(&ctx)?;
+
+        let mut notifier = sdnotify::Notifier::from_env()?;
+        let mut player = sound::Player::default();
+
+        // only the daemon holds the lock, so it owns the socket
+        let status_server = if ctx.args.daemon {
+            Some(status::StatusServer::start(&status::socket_path())?)
+        } else {
+            None
+        };
+
+        #[cfg(feature = "mqtt")]
+        let mut mqtt = start_mqtt(&ctx, &widgets)?;
+
+        #[cfg(not(feature = "mqtt"))]
+        if ctx.args.daemon && ctx.config.mqtt.is_some() {
+            log::warning!("Ignoring mqtt, kelvin was built without the mqtt feature");
+        }
+
+        let mut influx = start_influx(&ctx)?;
+        let mut graphite = start_graphite(&ctx)?;
+        let mut http_server = start_http(&ctx)?;
+
+        #[cfg(feature = "dbus")]
+        let mut dbus = start_dbus(&ctx)?;
+
+        #[cfg(not(feature = "dbus"))]
+        if ctx.args.daemon && ctx.config.dbus.is_some() {
+            log::warning!("Ignoring dbus, kelvin was built without the dbus feature");
+        }
+
+        let waiter = std::rc::Rc::new(signal::Waiter::from_signals()?);
+        ctx.waiter = Some(waiter.clone());
+        signal::catch(signal::SIGHUP)?;
+        signal::catch(signal::SIGTERM)?;
+        signal::catch(signal::SIGINT)?;
+        signal::catch(signal::SIGUSR1)?;
+
+        let mut watcher = match &ctx.config.path {
+            Some(path) if ctx.args.watch_config || ctx.config.watch_config => Some(watch::ConfigWatcher::new(path)?),
+            _ => None,
+        };
+
+        loop {
+            if let Some(server) = &status_server && server.take_reset_stats() {
+                for (_, widget) in widgets.iter_mut() {
+                    widget.set_stats(SensorStats::default());
+                }
+
+                log::info!("Statistics reset");
+            }
+
+            let result = update_format(&ctx, &mut format, &mut widgets);
+
+            // written even if the tick failed, outputs of failed sensors are skipped
+            errors.extend(fan::update(&mut outputs, &ctx.values.borrow(), ctx.args.daemon));
+            let output_status = outputs.iter().map(|x| x.status()).collect::<Vec<_>>();
+
+            if let Some(x) = &mut recorder && let Some(tick) = ctx.tick.take() && let Err(err) = x.write(&tick) {
+                errors.push(err);
+            }
+
+            // failed sensors are left empty
+            let now = ctx.local_time();
+            let timestamp = ctx.timestamp(now);
+            if let Some(x) = &mut csv && let Err(err) = x.write(now, timestamp.as_ref(), &widgets) {
+                errors.push(err);
+            }
+
+            if let Some(x) = &mut history && let Err(err) = x.insert(now, &widgets) {
+                errors.push(err);
+            }
+
+            #[cfg(feature = "mqtt")]
+            if let Some(x) = &mut mqtt {
+                x.publish(&output::readings(&widgets));
+            }
+
+            if let Some(x) = &mut influx {
+                x.write(&output::readings(&widgets));
+            }
+
+            if let Some(x) = &mut graphite {
+                x.send(&output::readings(&widgets));
+            }
+
+            #[cfg(feature = "dbus")]
+            if let Some(x) = &mut dbus {
+                x.update(&widgets);
+            }
+
+            match result {
+                Ok(widget_errors) => {
+                    errors.extend(widget_errors);
+
+                    if let Err(err) = show_tick(&ctx, &format, &widgets, &output_status, timestamp.as_ref()) {
+                        errors.push(err);
+                    }
+
+                    report_alarms(&ctx, &widgets);
+
+                    // reported even without `--alarm`, the fan is no longer following the sensor
+                    for alarm in outputs.iter().filter_map(|x| x.alarm()) {
+                        report_alarm(&ctx, &alarm);
+                    }
+
+                    if ctx.sound_requested.take() && let Some(sound) = &ctx.config.alarm_sound
+                        && let Err(err) = player.play(sound) {
+                        errors.push(err);
+                    }
+
+                    // both serve the same status, so it is only serialized once
+                    if status_server.is_some() || http_server.is_some() {
+                        let alarms = widgets.iter().filter_map(|(_, x)| x.alarm())
+                            .chain(outputs.iter().filter_map(|x| x.alarm()))
+                            .collect::<Vec<_>>();
+                        match output::status(&format, &widgets, &alarms, &output_status) {
+                            Ok(json) => {
+                                if let Some(server) = &http_server {
+                                    server.update(endpoint::Snapshot { metrics: output::prometheus(&output::readings(&widgets)), json: json.clone() });
+                                }
+
+                                if let Some(server) = &status_server {
+                                    server.update(json);
+                                }
+                            },
+                            Err(err) => errors.push(err),
+                        }
+                    }
+
+                    // ready only after the first successful tick
+                    if let Some(x) = &mut notifier && let Err(err) = x.status(&output::summary(&widgets, &ctx.config.unavailable)) {
+                        errors.push(err);
+                    }
+                },
+                Err(err) => errors.push(err),
+            }
+
+            // a failed tick should not stop the loop
+            for err in errors.drain(..) {
+                log::error!("{err:#}");
+            }
+
+            let mut poll_rate = ctx.config.poll_rate;
+            if let Some(detector) = &mut idle {
+                let readings = widgets.iter()
+                    .filter_map(|(_, x)| Some((x.sensor()?, x.reading()?.value)))
+                    .collect::<Vec<_>>();
+                let changed = detector.update(&readings);
+
+                if detector.is_idle() {
+                    poll_rate = ctx.config.idle_poll_rate.unwrap();
+                }
+
+                if changed {
+                    let mode = if detector.is_idle() { "idle" } else { "active" };
+                    log::info!("Switching to {mode} poll rate of {}", duration::format(poll_rate));
+                }
+            }
+
+            // recorded ticks are replayed with their own timing
+            let mut minimal_poll_rate = MINIMAL_POLL_RATE;
+            if let Some(x) = &replay {
+                let Some(delay) = x.delay() else {
+                    log::info!("Replay finished");
+                    break;
+                };
+
+                poll_rate = delay.div_f64(ctx.args.speed);
+                minimal_poll_rate = minimal_poll_rate.min(poll_rate);
+            }
+
+            let mut interrupt = None;
+            if poll_rate > minimal_poll_rate {
+                interrupt = wait(&waiter, &mut notifier, poll_rate - minimal_poll_rate);
+            }
+
+            if interrupt == Some(Interrupt::Shutdown) {
+                break;
+            }
+
+            // update all widgets
+            for (_, widget) in widgets.iter_mut() {
+                if let Err(err) = widget.update(&ctx) {
+                    errors.push(err);
+                }
+            }
+
+            // refresh skips the rest of the wait
+            if interrupt.is_none() {
+                interrupt = wait(&waiter, &mut notifier, minimal_poll_rate);
+            }
+
+            if interrupt == Some(Interrupt::Shutdown) {
+                break;
+            }
+
+            let mut reload_requested = signal::take(signal::SIGHUP);
+
+            if let Some(x) = &mut watcher {
+                match x.poll() {
+                    Ok(Some(watch::WatchEvent::Changed)) => reload_requested = true,
+                    Ok(Some(watch::WatchEvent::Deleted)) =>
+                        log::warning!("Config {:?} was deleted, keeping the last config", x.path()),
+                    Ok(None) => {},
+                    Err(err) => errors.push(err),
+                }
+            }
+
+            if reload_requested {
+                match reload(&mut ctx, &mut widgets) {
+                    Ok(()) => {
+                        idle = ctx.config.idle.clone().map(IdleDetector::new);
+                        // channels no longer in the config go back to how they were
+                        errors.extend(fan::restore());
+                        match start_outputs(&ctx) {
+                            Ok(x) => outputs = x,
+                            Err(err) => {
+                                errors.push(err);
+                                outputs = vec![];
+                            },
+                        }
+
+                        // sensors may have changed
+                        match open_csv(&ctx, &widgets) {
+                            Ok(x) => csv = x,
+                            Err(err) => log::error!("Keeping the old CSV log: {err:#}"),
+                        }
+
+                        match open_history(&ctx) {
+                            Ok(x) => history = x,
+                            Err(err) => log::error!("Keeping the old history database: {err:#}"),
+                        }
+
+                        // reconnecting would flap the status topic, so only when the settings changed
+                        #[cfg(feature = "mqtt")]
+                        if mqtt.as_ref().map(|x| x.config()) != ctx.config.mqtt.as_ref().filter(|_| ctx.args.daemon) {
+                            mqtt = None;
+                            match start_mqtt(&ctx, &widgets) {
+                                Ok(x) => mqtt = x,
+                                Err(err) => log::error!("Unable to start MQTT: {err:#}"),
+                            }
+                        } else if let Some(x) = &mut mqtt {
+                            let sensors = widgets.iter().filter_map(|(_, x)| x.sensor()).collect::<Vec<_>>();
+                            if let Err(err) = x.announce(&sensors, &daemon::discovery_file_path()) {
+                                log::error!("Unable to announce sensors to Home Assistant: {err:#}");
+                            }
+                        }
+
+                        // buffered points are only lost when the settings changed
+                        if influx.as_ref().map(|x| x.config()) != ctx.config.influx.as_ref().filter(|_| ctx.args.daemon) {
+                            influx = None;
+                            match start_influx(&ctx) {
+                                Ok(x) => influx = x,
+                                Err(err) => log::error!("Unable to start InfluxDB writer: {err:#}"),
+                            }
+                        }
+
+                        if graphite.as_ref().map(|x| x.config()) != ctx.config.graphite.as_ref().filter(|_| ctx.args.daemon) {
+                            graphite = None;
+                            match start_graphite(&ctx) {
+                                Ok(x) => graphite = x,
+                                Err(err) => log::error!("Unable to start Graphite sender: {err:#}"),
+                            }
+                        }
+
+                        if http_server.as_ref().map(|x| x.config()) != ctx.config.http.as_ref().filter(|_| ctx.args.daemon) {
+                            http_server = None;
+                            match start_http(&ctx) {
+                                Ok(x) => http_server = x,
+                                Err(err) => log::error!("Unable to start HTTP endpoint: {err:#}"),
+                            }
+                        }
+
+                        #[cfg(feature = "dbus")]
+                        if dbus.as_ref().map(|x| x.config()) != ctx.config.dbus.as_ref().filter(|_| ctx.args.daemon) {
+                            dbus = None;
+                            match start_dbus(&ctx) {
+                                Ok(x) => dbus = x,
+                                Err(err) => log::error!("Unable to start D-Bus service: {err:#}"),
+                            }
+                        }
+
+                        log::info!("Config reloaded");
+                    },
+                    Err(err) => log::error!("Keeping the old config as reload failed: {err:#}"),
+                }
+            }
+
+            // reset format
+            format = ctx.config.format.as_ref().unwrap().clone();
+
+            // get fresh sensor data
+            if let Some(x) = &mut replay {
+                ctx.start_tick(x.next());
+                continue;
+            }
+
+            if uses_sensors(&widgets) {
+                ctx.sensors_data = match get_config_temps(&ctx.config) {
+                    Ok(x) => Some(x),
+                    Err(err) => {
+                        errors.push(err);
+                        None
+                    },
+                };
+            }
+
+            ctx.start_tick(None);
+        }
+
+        log::info!("Shutting down");
+
+        // removes the socket
+        drop(status_server);
+
+        if ctx.args.daemon {
+            daemon::remove_pid_file();
+        }
+
+        std::io::stdout().flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::TempDir;
+
+    #[test]
+    fn test_reload() {
+        let dir = TempDir::new("reload");
+
+        let value = dir.join("value");
+        std::fs::write(&value, "60").unwrap();
+
+        let path = dir.join("config.toml");
+        let contents = |format: &str| format!(
+            "format = \"{format}\"\n[[sensors]]\nname = \"cpu\"\nalarm_high = 50\nsource = \"file\"\npath = {value:?}\n"
+        );
+        std::fs::write(&path, contents("{cpu}")).unwrap();
+
+        let (mut ctx, mut widgets) = testing::context(&["--config", path.to_str().unwrap()]);
+
+        widgets[0].1.value(&ctx).unwrap();
+        assert_eq!(widgets[0].1.alarm_tracker().unwrap().state, alarm::AlarmState::High);
+
+        // alarm state is kept for the same sensor
+        std::fs::write(&path, contents("cpu {cpu}")).unwrap();
+        reload(&mut ctx, &mut widgets).unwrap();
+        assert_eq!(ctx.config.format.as_deref(), Some("cpu {cpu}"));
+        assert_eq!(widgets[0].1.alarm_tracker().unwrap().state, alarm::AlarmState::High);
+
+        // broken config keeps the old one
+        std::fs::write(&path, "format = ").unwrap();
+        assert!(reload(&mut ctx, &mut widgets).is_err());
+        assert_eq!(ctx.config.format.as_deref(), Some("cpu {cpu}"));
+        assert_eq!(widgets.len(), 1);
+    }
+
+    #[test]
+    fn test_start_outputs() {
+        let dir = TempDir::new("start-outputs");
+
+        let path = dir.join("config.toml");
+        std::fs::write(&path, format!(
+            "[[sensors]]\nname = \"cpu\"\nsource = \"file\"\npath = \"/dev/null\"\n[[outputs]]\nname = \"fan\"\nsource = \"cpu\"\npath = {:?}\n",
+            dir.join("pwm1"),
+        )).unwrap();
+
+        let outputs = |flags: &[&str]| start_outputs(&testing::context(&[&["--config", path.to_str().unwrap()], flags].concat()).0).unwrap();
+
+        // source is read even though it is not shown
+        let (_, widgets) = testing::context(&["--config", path.to_str().unwrap(), "--set", "format={time}"]);
+        assert_eq!(widgets.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["{cpu}", "{time}"]);
+
+        // the foreground never takes control, the daemon may be driving the fans
+        assert!(outputs(&[]).is_empty());
+        assert_eq!(outputs(&["--dry-run"]).len(), 1);
+        assert!(!dir.join("pwm1").exists());
+    }
+
+    #[test]
+    fn test_daemon_textfile() {
+        let dir = TempDir::new("daemon-textfile");
+
+        let value = dir.join("value");
+        std::fs::write(&value, "45").unwrap();
+
+        let path = dir.join("config.toml");
+        std::fs::write(&path, format!("[[sensors]]\nname = \"cpu\"\nsource = \"file\"\npath = {value:?}\n")).unwrap();
+
+        let textfile = dir.join("kelvin.prom");
+        let (ctx, mut widgets) = testing::context(&[
+            "--config", path.to_str().unwrap(), "--daemon", "--output", "prometheus", "--textfile", textfile.to_str().unwrap(),
+        ]);
+
+        let mut format = ctx.config.format.clone().unwrap();
+        update_format(&ctx, &mut format, &mut widgets).unwrap();
+        show_tick(&ctx, &format, &widgets, &[], None).unwrap();
+
+        let written = std::fs::read_to_string(&textfile).unwrap();
+        assert!(written.contains("kelvin_sensor_value{name=\"cpu\",unit=\"\",kind=\"\"} 45\n"), "{written}");
+    }
+
+    #[test]
+    fn test_set_overrides() {
+        let dir = TempDir::new("set-overrides");
+
+        // hwmon name, so it is read as millidegrees
+        let value = dir.join("temp1_input");
+        std::fs::write(&value, "45000").unwrap();
+
+        let path = dir.join("config.toml");
+        std::fs::write(&path, format!("format = \"{{cpu}}\"\n[[sensors]]\nname = \"cpu\"\nsource = \"file\"\npath = {value:?}\n")).unwrap();
+
+        let show = |flags: &[&str]| {
+            let (ctx, mut widgets) = testing::context(&[&["--config", path.to_str().unwrap()], flags].concat());
+            let mut format = ctx.config.format.clone().unwrap();
+            update_format(&ctx, &mut format, &mut widgets).unwrap();
+            format
+        };
+
+        // sensors are resolved with the overrides
+        assert_eq!(show(&[]), "45.0");
+        assert_eq!(show(&["--set", "temperature_unit=f"]), "113.0");
+        assert_eq!(show(&["--set", "auto_units=false"]), "45000");
+    }
+
+    #[test]
+    fn test_set_alarm_unit() {
+        let dir = TempDir::new("set-alarm-unit");
+
+        let value = dir.join("temp1_input");
+        std::fs::write(&value, "45000").unwrap();
+
+        let path = dir.join("config.toml");
+        std::fs::write(&path, format!("[[sensors]]\nname = \"cpu\"\nalarm_high = 100\nsource = \"file\"\npath = {value:?}\n")).unwrap();
+
+        let alarm = |flags: &[&str]| {
+            let (ctx, mut widgets) = testing::context(&[&["--config", path.to_str().unwrap(), "--alarm"], flags].concat());
+            let mut format = ctx.config.format.clone().unwrap();
+            update_format(&ctx, &mut format, &mut widgets).unwrap();
+            widgets[0].1.alarm().is_some()
+        };
+
+        // 100 °F is below the reading of 45 °C
+        assert!(!alarm(&[]));
+        assert!(alarm(&["--set", "alarm_unit=f"]));
+    }
+
+    #[test]
+    fn test_mapped_bar() {
+        let dir = TempDir::new("mapped-bar");
+
+        let value = dir.join("value");
+        std::fs::write(&value, "30").unwrap();
+
+        let path = dir.join("config.toml");
+        std::fs::write(&path, format!(
+            "format = \"{{cpu}} {{cpu_bar}}\"\n\
+            [[sensors]]\nname = \"cpu\"\nmin = 20\nmax = 80\nround = 0\nmap = {{ output = [0, 100] }}\nbar = {{}}\nsource = \"file\"\npath = {value:?}\n",
+        )).unwrap();
+
+        let (ctx, mut widgets) = testing::context(&["--config", path.to_str().unwrap()]);
+
+        // mapped value is below min, the bar follows the range of the map instead
+        let mut format = ctx.config.format.clone().unwrap();
+        update_format(&ctx, &mut format, &mut widgets).unwrap();
+        assert_eq!(format, "17 ▓▓░░░░░░░░");
+    }
+
+    #[test]
+    fn test_check_status() {
+        use output::CheckStatus;
+
+        let dir = TempDir::new("check");
+
+        let path = dir.join("config.toml");
+        let check = |required: bool, value: &str| {
+            std::fs::write(dir.join("value"), value).unwrap();
+            std::fs::write(&path, format!(
+                "format = \"{{cpu}} {{gpu}}\"\n\
+                [[sensors]]\nname = \"cpu\"\nwarn_high = 70\nalarm_high = 95\nsource = \"file\"\npath = {:?}\n\
+                [[sensors]]\nname = \"gpu\"\nrequired = {required}\nsource = \"file\"\npath = \"/kelvin/does/not/exist\"\n",
+                dir.join("value"),
+            )).unwrap();
+
+            let (ctx, mut widgets) = testing::context(&["--config", path.to_str().unwrap(), "--once", "--check"]);
+
+            let mut format = ctx.config.format.clone().unwrap();
+            let result = update_format(&ctx, &mut format, &mut widgets);
+            check_status(&result, &widgets)
+        };
+
+        // failure of optional sensor does not matter
+        assert_eq!(check(false, "50"), CheckStatus::Ok);
+        assert_eq!(check(false, "90"), CheckStatus::Warning);
+        assert_eq!(check(false, "99"), CheckStatus::Critical);
+
+        // failed required sensor wins over the alarm
+        assert_eq!(check(true, "50"), CheckStatus::Unknown);
+        assert_eq!(check(true, "99"), CheckStatus::Unknown);
+    }
+
+    #[test]
+    fn test_virtual_sensors() {
+        let dir = TempDir::new("virtual");
+
+        std::fs::write(dir.join("a"), "40").unwrap();
+        std::fs::write(dir.join("b"), "70").unwrap();
+
+        let path = dir.join("config.toml");
+        std::fs::write(&path, format!(
+            "format = \"{{spread}} {{hottest}}\"\n\
+            [[virtual_sensors]]\nname = \"spread\"\ninputs = [\"hottest\", \"a\"]\nop = \"diff\"\nalarm_high = 20\n\
+            [[virtual_sensors]]\nname = \"hottest\"\ninputs = [\"a\", \"b\"]\nop = \"max\"\nscale = 2\n\
+            [[sensors]]\nname = \"a\"\nsource = \"file\"\npath = {:?}\n\
+            [[sensors]]\nname = \"b\"\nsource = \"file\"\npath = {:?}\n",
+            dir.join("a"),
+            dir.join("b"),
+        )).unwrap();
+
+        let (ctx, mut widgets) = testing::context(&["--config", path.to_str().unwrap(), "--alarm"]);
+
+        // inputs are read even though they are not shown
+        let names = widgets.iter().map(|(x, _)| x.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["{a}", "{b}", "{hottest}", "{spread}"]);
+
+        // virtual sensors are processed like any other sensor
+        let mut format = ctx.config.format.clone().unwrap();
+        update_format(&ctx, &mut format, &mut widgets).unwrap();
+        assert_eq!(format, "100 140");
+        assert!(widgets[3].1.alarm().is_some());
+
+        // failed input fails the virtual sensor too
+        std::fs::remove_file(dir.join("b")).unwrap();
+        let mut format = ctx.config.format.clone().unwrap();
+        let errors = update_format(&ctx, &mut format, &mut widgets).unwrap();
+        assert_eq!(format, "N/A N/A");
+        assert_eq!(errors.len(), 3);
+    }
+
+    #[test]
+    fn test_sort() {
+        let dir = TempDir::new("sort");
+
+        std::fs::write(dir.join("a"), "40").unwrap();
+        std::fs::write(dir.join("b"), "60").unwrap();
+
+        let path = dir.join("config.toml");
+        std::fs::write(&path, format!(
+            "[[virtual_sensors]]\nname = \"lowest\"\ninputs = [\"a\", \"b\"]\nop = \"min\"\n\
+            [[sensors]]\nname = \"a\"\nsource = \"file\"\npath = {:?}\n\
+            [[sensors]]\nname = \"b\"\nsource = \"file\"\npath = {:?}\n\
+            [[sensors]]\nname = \"c\"\nsource = \"file\"\npath = {:?}\n",
+            dir.join("a"),
+            dir.join("b"),
+            dir.join("c"),
+        )).unwrap();
+
+        let (ctx, mut widgets) = testing::context(&["--config", path.to_str().unwrap(), "--sort", "value_desc"]);
+
+        // virtual sensor is computed even though it is sorted before its input, failed sensor is last
+        let mut format = ctx.config.format.clone().unwrap();
+        update_format(&ctx, &mut format, &mut widgets).unwrap();
+        assert_eq!(format, "b: 60\na: 40\nlowest: 40\nc: N/A");
+
+        // equal values keep their place on the next tick
+        let mut format = ctx.config.format.clone().unwrap();
+        update_format(&ctx, &mut format, &mut widgets).unwrap();
+        assert_eq!(format, "b: 60\na: 40\nlowest: 40\nc: N/A");
+
+        let names = widgets.iter().map(|(x, _)| x.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["{b}", "{a}", "{lowest}", "{c}"]);
+    }
+
+    #[test]
+    fn test_retry() {
+        use config::Retry;
+        use std::time::Duration;
+
+        let config: Config = toml::from_str("poll_rate = \"100ms\"\nsensors = []").unwrap();
+        let mut ctx = Context::new(cli::Cli::parse_from(["kelvin"]), config);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        ctx.waiter = Some(std::rc::Rc::new(signal::Waiter::new(rx)));
+
+        // sensors failing on the same attempt share the data
+        let first = ctx.refetch(1).map_err(|x| x.to_string());
+        assert_eq!(ctx.refetch(1).map_err(|x| x.to_string()), first);
+        assert_eq!(ctx.refetched.borrow().len(), 1);
+        ctx.refetch(2).ok();
+        assert_eq!(ctx.refetched.borrow().len(), 2);
+
+        // half of the poll rate for all retries of the tick
+        assert!(ctx.wait(Duration::from_millis(30)));
+        assert!(!ctx.wait(Duration::from_millis(30)));
+        assert!(ctx.wait(Duration::from_millis(20)));
+
+        // new tick starts over
+        let mut format = String::new();
+        update_format(&ctx, &mut format, &mut vec![]).ok();
+        assert!(ctx.refetched.borrow().is_empty());
+
+        // shutdown is not delayed
+        tx.send(signal::SIGTERM).unwrap();
+        let start = std::time::Instant::now();
+        assert!(!ctx.wait(Duration::from_millis(40)));
+        assert!(start.elapsed() < Duration::from_millis(40));
+        assert_eq!(wait(ctx.waiter.as_ref().unwrap(), &mut None, Duration::from_secs(10)), Some(Interrupt::Shutdown));
+
+        ctx.config.poll_rate = Duration::ZERO;
+        assert!(!ctx.wait(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_sensors_json() {
+        let dir = TempDir::new("sensors-json");
+        let path = dir.join("sensors.json");
+        let sensors = SensorsJson::new(&path).unwrap();
+
+        assert!(sensors.read().is_err());
+
+        std::fs::write(&path, include_str!("../tests/fixtures/sensors.json")).unwrap();
+        let first = sensors.read().unwrap();
+        let sensor = Sensor { path: "k10temp-pci-00c3/Tctl/temp1_input".into(), ..Default::default() };
+        assert_eq!(sensor.read_raw(Some(&first)).unwrap(), 61.25);
+
+        // file is read again, like a test rewriting it between ticks
+        std::fs::write(&path, r#"{"k10temp-pci-00c3": {"Tctl": {"temp1_input": 71.5}}}"#).unwrap();
+        assert_eq!(sensor.read_raw(Some(&sensors.read().unwrap())).unwrap(), 71.5);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(sensors.read().is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let dir = TempDir::new("replay");
+
+        // file does not exist, only the recording is read
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "[[sensors]]\nname = \"cpu\"\nsource = \"file\"\npath = \"/kelvin/does/not/exist\"\nalarm_high = 80\nalarm_for = \"2s\"\ndivisor = 1000\n").unwrap();
+
+        let ticks = [(0, "70000"), (1000, "85000"), (2000, "86000"), (3000, "85500")].map(|(elapsed, text)| {
+            let inputs = [("cpu".to_string(), replay::Input::Text(text.into()))].into();
+            serde_json::to_string(&replay::Tick { time: "2024-01-01T12:00:00+00:00".into(), elapsed, sensors: None, inputs }).unwrap()
+        });
+        std::fs::write(dir.join("recording.jsonl"), ticks.join("\n")).unwrap();
+
+        let (mut ctx, mut widgets) = testing::context(&["--config", path.to_str().unwrap(), "--alarm", "--replay", dir.join("recording.jsonl").to_str().unwrap()]);
+
+        // alarm fires once the value stayed high for two recorded seconds, however fast it is replayed
+        let mut states = vec![];
+        for tick in replay::Replay::open(&dir.join("recording.jsonl")).unwrap() {
+            ctx.start_tick(Some(tick));
+
+            let mut format = ctx.config.format.clone().unwrap();
+            update_format(&ctx, &mut format, &mut widgets).unwrap();
+            states.push((format, widgets[0].1.alarm().is_some()));
+        }
+
+        assert_eq!(states, [
+            ("cpu: 70".to_string(), false),
+            ("cpu: 85".to_string(), false),
+            ("cpu: 86".to_string(), false),
+            ("cpu: 85.5".to_string(), true),
+        ]);
+    }
+
+    #[test]
+    fn test_stale_for() {
+        let dir = TempDir::new("stale");
+
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "[[sensors]]\nname = \"gpu\"\nsource = \"file\"\npath = \"/kelvin/does/not/exist\"\nstale_for = \"10s\"\n").unwrap();
+        std::fs::write(dir.join("recording.jsonl"), "").unwrap();
+
+        let (mut ctx, mut widgets) = testing::context(&["--config", path.to_str().unwrap(), "--replay", dir.join("recording.jsonl").to_str().unwrap()]);
+
+        // window starts at the last success, not at the first failure
+        let mut outputs = vec![];
+        for (elapsed, input) in [(0, Some("50")), (1000, None), (10000, None), (10500, None), (11000, Some("55")), (12000, None)] {
+            let input = match input {
+                Some(x) => replay::Input::Text(x.into()),
+                None => replay::Input::Error("Failed to read path".into()),
+            };
+
+            ctx.start_tick(Some(replay::Tick { time: String::new(), elapsed, sensors: None, inputs: [("gpu".to_string(), input)].into() }));
+
+            // fails when all sensors failed
+            let mut format = ctx.config.format.clone().unwrap();
+            let result = update_format(&ctx, &mut format, &mut widgets);
+            outputs.push((result.is_ok().then_some(format), widgets[0].1.reading().is_some_and(|x| x.stale)));
+        }
+
+        assert_eq!(outputs, [
+            (Some("gpu: 50".to_string()), false),
+            (Some("gpu: 50*".to_string()), true),
+            (Some("gpu: 50*".to_string()), true),
+            (None, false),
+            (Some("gpu: 55".to_string()), false),
+            (Some("gpu: 55*".to_string()), true),
+        ]);
+    }
 }
@@ -0,0 +1,329 @@
+//! Man page generated from the command line definition and the config structs
+//!
+//! Config keys are described by doc comments of the fields, read from the source at build time
+
+use clap::{Arg, Command};
+use std::fmt::Write;
+
+const CONFIG_SOURCE: &str = include_str!("config.rs");
+
+/// Escape the text for roff, backticks switch to bold like code in the doc comments
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    let mut bold = false;
+
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\e"),
+            '-' => out.push_str("\\-"),
+            '`' => {
+                out.push_str(if bold { "\\fR" } else { "\\fB" });
+                bold = !bold;
+            },
+            _ => out.push(c),
+        }
+    }
+
+    if bold {
+        out.push_str("\\fR");
+    }
+
+    // control characters at the start would be read as requests
+    if out.starts_with(['.', '\'']) {
+        out.insert_str(0, "\\&");
+    }
+
+    out
+}
+
+/// Lines of the text as roff, indented lines are kept as they are like the config search order
+fn paragraphs(text: &str) -> String {
+    let mut out = String::new();
+    let mut verbatim = false;
+
+    for line in text.trim().lines() {
+        let indented = line.starts_with(char::is_whitespace) && !line.trim().is_empty();
+
+        if indented != verbatim {
+            out.push_str(if indented { ".nf\n" } else { ".fi\n" });
+            verbatim = indented;
+        }
+
+        if line.trim().is_empty() {
+            out.push_str(".sp\n");
+        } else if verbatim {
+            writeln!(out, "{}", escape(line)).unwrap();
+        } else {
+            writeln!(out, "{}\n.br", escape(line.trim())).unwrap();
+        }
+    }
+
+    if verbatim {
+        out.push_str(".fi\n");
+    }
+
+    out
+}
+
+fn option(arg: &Arg) -> String {
+    let mut names = arg.get_short().map(|x| format!("\\fB\\-{x}\\fR")).into_iter()
+        .chain(arg.get_long().map(|x| format!("\\fB\\-\\-{}\\fR", escape(x))))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    if arg.get_action().takes_values() {
+        let value = arg.get_value_names().and_then(|x| x.first()).map(|x| x.to_string())
+            .unwrap_or_else(|| arg.get_id().to_string().to_uppercase());
+        write!(names, " \\fI{}\\fR", escape(&value)).unwrap();
+    }
+
+    let mut out = format!(".TP\n{names}\n");
+
+    if let Some(help) = arg.get_long_help().or(arg.get_help()) {
+        out.push_str(&paragraphs(&help.to_string()));
+    }
+
+    let values = arg.get_possible_values().iter()
+        .filter(|x| !x.is_hide_set())
+        .map(|x| format!("\\fB{}\\fR", escape(x.get_name())))
+        .collect::<Vec<_>>();
+
+    if !values.is_empty() && arg.get_action().takes_values() {
+        writeln!(out, "Possible values: {}\n.br", values.join(", ")).unwrap();
+    }
+
+    let defaults = arg.get_default_values().iter().map(|x| x.to_string_lossy()).collect::<Vec<_>>();
+    if !defaults.is_empty() {
+        writeln!(out, "Default: {}\n.br", escape(&defaults.join(", "))).unwrap();
+    }
+
+    out
+}
+
+/// Field of a config struct as found in the source
+#[derive(Debug, Clone, PartialEq)]
+struct Field {
+    name: String,
+    ty: String,
+    doc: Vec<String>,
+}
+
+/// Fields of the struct with their doc comments, fields skipped by serde are left out
+fn struct_fields(source: &str, name: &str) -> Vec<Field> {
+    let header = format!("pub struct {name} {{");
+    let Some(start) = source.lines().position(|x| x.trim() == header) else {
+        return vec![];
+    };
+
+    let mut fields = vec![];
+    let mut doc = vec![];
+    let mut skip = false;
+
+    for line in source.lines().skip(start + 1).map(str::trim) {
+        if line == "}" {
+            break;
+        }
+
+        if let Some(text) = line.strip_prefix("///") {
+            doc.push(text.strip_prefix(' ').unwrap_or(text).to_string());
+        } else if let Some(attr) = line.strip_prefix("#[serde(") {
+            skip |= attr.split(['(', ')', ',']).any(|x| x.trim() == "skip");
+        } else if let Some((field, ty)) = line.strip_prefix("pub ").and_then(|x| x.split_once(':')) {
+            if !skip {
+                fields.push(Field {
+                    name: field.trim().to_string(),
+                    ty: ty.trim().trim_end_matches(',').to_string(),
+                    doc: std::mem::take(&mut doc),
+                });
+            }
+
+            doc.clear();
+            skip = false;
+        }
+    }
+
+    fields
+}
+
+/// Struct the field holds if it is one of the config structs, `Vec` of them is reported separately
+fn inner_struct(ty: &str) -> Option<(&str, bool)> {
+    let (ty, list) = match ty.strip_prefix("Vec<") {
+        Some(x) => (x.strip_suffix('>')?, true),
+        None => (ty.strip_prefix("Option<").and_then(|x| x.strip_suffix('>')).unwrap_or(ty), false),
+    };
+
+    CONFIG_SOURCE.contains(&format!("pub struct {ty} {{")).then_some((ty, list))
+}
+
+/// Keys of the struct, keys of nested tables as `table.key`
+fn config_keys(name: &str, prefix: &str, out: &mut String, lists: &mut Vec<String>) {
+    for field in struct_fields(CONFIG_SOURCE, name) {
+        let key = format!("{prefix}{}", field.name);
+
+        writeln!(out, ".TP\n\\fB{}\\fR", escape(&key)).unwrap();
+        out.push_str(&paragraphs(&field.doc.join("\n")));
+
+        match inner_struct(&field.ty) {
+            Some((ty, false)) => config_keys(ty, &format!("{key}."), out, lists),
+            Some((ty, true)) if !lists.iter().any(|x| x == ty) => lists.push(ty.to_string()),
+            _ => {},
+        }
+    }
+}
+
+/// Usage of the command like `kelvin config init [OPTIONS] [PATH]`
+fn usage(path: &str, cmd: &Command) -> String {
+    let mut usage = format!("\\fB{}\\fR", escape(path));
+
+    if cmd.get_arguments().any(|x| !x.is_positional() && !x.is_hide_set()) {
+        usage.push_str(" [\\fIOPTIONS\\fR]");
+    }
+
+    for arg in cmd.get_positionals() {
+        let name = arg.get_id().to_string().to_uppercase();
+        match arg.is_required_set() {
+            true => write!(usage, " \\fI{name}\\fR").unwrap(),
+            false => write!(usage, " [\\fI{name}\\fR]").unwrap(),
+        }
+    }
+
+    if cmd.get_subcommands().any(|x| !x.is_hide_set()) {
+        usage.push_str(" [\\fICOMMAND\\fR]");
+    }
+
+    usage
+}
+
+fn commands(path: &str, cmd: &Command, out: &mut String) {
+    for sub in cmd.get_subcommands().filter(|x| !x.is_hide_set()) {
+        let path = format!("{path} {}", sub.get_name());
+
+        writeln!(out, ".SS \"{}\"", escape(&path)).unwrap();
+        writeln!(out, "{}\n.br", usage(&path, sub)).unwrap();
+
+        if let Some(about) = sub.get_long_about().or(sub.get_about()) {
+            out.push_str(&paragraphs(&about.to_string()));
+        }
+
+        for arg in sub.get_arguments().filter(|x| !x.is_hide_set()) {
+            out.push_str(&option(arg));
+        }
+
+        commands(&path, sub, out);
+    }
+}
+
+/// Generate the man page, `cmd` should not be built so global options are only listed once
+pub fn man_page(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let mut out = String::new();
+
+    writeln!(out, ".TH {} 1 \"\" \"{name} {}\"", name.to_uppercase(), cmd.get_version().unwrap_or("")).unwrap();
+
+    writeln!(out, ".SH NAME").unwrap();
+    writeln!(out, "{name} \\- {}", escape(&cmd.get_about().map(|x| x.to_string()).unwrap_or_default())).unwrap();
+
+    writeln!(out, ".SH SYNOPSIS").unwrap();
+    writeln!(out, "{}", usage(name, cmd)).unwrap();
+
+    writeln!(out, ".SH OPTIONS").unwrap();
+    for arg in cmd.get_arguments().filter(|x| !x.is_positional() && !x.is_hide_set()) {
+        out.push_str(&option(arg));
+    }
+
+    writeln!(out, ".SH COMMANDS").unwrap();
+    commands(name, cmd, &mut out);
+
+    writeln!(out, ".SH FILES").unwrap();
+    writeln!(out, "Config is read from the first of these paths that exists, unless \\fB\\-\\-config\\fR is used").unwrap();
+    writeln!(out, ".nf").unwrap();
+    for path in crate::config::config_search_paths("<hostname>", None, Some("~".into())) {
+        writeln!(out, "{}", escape(&path.display().to_string())).unwrap();
+    }
+    writeln!(out, ".fi").unwrap();
+    writeln!(out, "The user directory is \\fB$XDG_CONFIG_HOME/kelvin\\fR when it is set").unwrap();
+
+    writeln!(out, ".SH CONFIGURATION").unwrap();
+    writeln!(out, "Keys at the top level of the config").unwrap();
+
+    let mut lists = vec![];
+    config_keys("Config", "", &mut out, &mut lists);
+
+    // each list is described once even if more keys hold it, like sensors and virtual sensors
+    let mut i = 0;
+    while let Some(ty) = lists.get(i).cloned() {
+        writeln!(out, ".SS \"{}\"", escape(&ty)).unwrap();
+        writeln!(out, "Keys of each entry in lists of \\fB{}\\fR", escape(&ty)).unwrap();
+        config_keys(&ty, "", &mut out, &mut lists);
+        i += 1;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use serde_json::Value as JsonValue;
+
+    #[test]
+    fn test_struct_fields() {
+        let source = r#"
+            pub struct Example {
+                /// Name of the thing
+                ///
+                /// Second paragraph
+                pub name: String,
+
+                #[serde(skip)]
+                pub path: Option<PathBuf>,
+
+                #[serde(default, skip_serializing_if = "Vec::is_empty")]
+                pub items: Vec<Item>,
+            }
+        "#;
+
+        assert_eq!(struct_fields(source, "Example"), [
+            Field { name: "name".into(), ty: "String".into(), doc: vec!["Name of the thing".into(), "".into(), "Second paragraph".into()] },
+            Field { name: "items".into(), ty: "Vec<Item>".into(), doc: vec![] },
+        ]);
+    }
+
+    #[test]
+    fn test_config_fields() {
+        // every key the config can have is found in the source
+        let keys = |name: &str| struct_fields(CONFIG_SOURCE, name).into_iter().map(|x| x.name).collect::<Vec<_>>();
+
+        let config: crate::config::Config = toml::from_str("sensors = []").unwrap();
+        let JsonValue::Object(map) = serde_json::to_value(&config).unwrap() else { panic!() };
+        let config_keys = keys("Config");
+        for key in map.keys() {
+            assert!(config_keys.contains(key), "{key} missing from {config_keys:?}");
+        }
+
+        let JsonValue::Object(map) = serde_json::to_value(crate::config::Sensor::default()).unwrap() else { panic!() };
+        let sensor_keys = keys("Sensor");
+        for key in map.keys() {
+            assert!(sensor_keys.contains(key), "{key} missing from {sensor_keys:?}");
+        }
+
+        assert!(!config_keys.iter().any(|x| x == "path"));
+    }
+
+    #[test]
+    fn test_man_page() {
+        let page = man_page(&crate::cli::Cli::command());
+
+        assert!(page.starts_with(".TH KELVIN 1"));
+        assert!(page.contains(".SS \"kelvin config init\"\n\\fBkelvin config init\\fR [\\fIOPTIONS\\fR] [\\fIPATH\\fR]"), "{page}");
+        assert!(page.contains("~/.config/kelvin/<hostname>.toml\n"), "{page}");
+        assert!(page.contains(".TP\n\\fBpoll_rate\\fR\nHow often to check the temperature, like \\fB5s\\fR or in millis\n"), "{page}");
+        assert!(page.contains(".TP\n\\fBhistory.path\\fR\n"), "{page}");
+        assert!(page.contains(".SS \"Sensor\"\n"), "{page}");
+        assert_eq!(page.matches(".SS \"Sensor\"").count(), 1);
+
+        // search order of `--config` help stays indented
+        assert!(page.contains(".nf\n  ~/.config/kelvin/<hostname>.toml\n"), "{page}");
+    }
+}
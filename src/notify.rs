@@ -0,0 +1,38 @@
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Urgency {
+    Normal,
+    Critical,
+}
+
+#[cfg(feature = "notify")]
+impl Urgency {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+/// Show a desktop notification using `notify-send`
+#[cfg(feature = "notify")]
+pub fn send(summary: &str, body: &str, urgency: Urgency) -> Result<()> {
+    let status = std::process::Command::new("notify-send")
+        .args(["--app-name", "kelvin", "--urgency", urgency.as_str(), summary, body])
+        .status()
+        .with_context(|| anyhow!("Unable to run notify-send"))?;
+
+    if !status.success() {
+        bail!("notify-send exited with {status}");
+    }
+
+    Ok(())
+}
+
+/// Notifications are disabled at compile time
+#[cfg(not(feature = "notify"))]
+pub fn send(_summary: &str, _body: &str, _urgency: Urgency) -> Result<()> {
+    Ok(())
+}
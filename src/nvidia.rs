@@ -0,0 +1,139 @@
+//! NVIDIA GPUs read from `nvidia-smi`, which is run at most once per tick for all sensors
+
+use crate::prelude::*;
+use crate::config::ReadTrace;
+use std::path::Path;
+use std::time::Duration;
+
+/// Metric usable in the path, with its `nvidia-smi` query field and unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metric {
+    pub name: &'static str,
+    pub field: &'static str,
+    pub unit: &'static str,
+}
+
+pub const METRICS: &[Metric] = &[
+    Metric { name: "temperature", field: "temperature.gpu", unit: "°C" },
+    Metric { name: "fan_speed", field: "fan.speed", unit: "%" },
+    Metric { name: "power_draw", field: "power.draw", unit: "W" },
+    Metric { name: "utilization", field: "utilization.gpu", unit: "%" },
+    Metric { name: "memory_utilization", field: "utilization.memory", unit: "%" },
+    Metric { name: "memory_used", field: "memory.used", unit: "MiB" },
+    Metric { name: "memory_total", field: "memory.total", unit: "MiB" },
+    Metric { name: "clock_graphics", field: "clocks.gr", unit: "MHz" },
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gpu {
+    pub index: u32,
+    pub name: String,
+
+    /// Values in the order of `METRICS` as printed, like `[N/A]` when the GPU does not support it
+    pub values: Vec<String>,
+}
+
+/// Parsed output of `nvidia-smi`, one GPU per line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table(pub Vec<Gpu>);
+
+/// Split path like `0/temperature` into GPU index and metric
+pub fn parse_path(path: &Path) -> Result<(u32, &'static Metric)> {
+    let text = path.to_string_lossy();
+    let Some((index, metric)) = text.split_once('/') else {
+        bail!("Nvidia path {path:?} must be <index>/<metric> like \"0/temperature\"");
+    };
+
+    let index = index.parse().with_context(|| anyhow!("GPU index {index:?} in {path:?} is not a number"))?;
+    let metric = METRICS.iter().find(|x| x.name == metric).with_context(|| {
+        let names = METRICS.iter().map(|x| x.name).collect::<Vec<_>>().join(", ");
+        anyhow!("Unknown metric {metric:?} in {path:?}, available ones are: {names}")
+    })?;
+
+    Ok((index, metric))
+}
+
+/// Parse csv output without header and units, name is the last column as it could contain commas
+fn parse(output: &str) -> Result<Table> {
+    output.lines()
+        .filter(|x| !x.trim().is_empty())
+        .map(|line| {
+            let mut fields = line.splitn(METRICS.len() + 2, ',').map(str::trim);
+            let index = fields.next().unwrap_or_default();
+            let index = index.parse().with_context(|| anyhow!("Invalid GPU index {index:?} in nvidia-smi output"))?;
+
+            let values = fields.by_ref().take(METRICS.len()).map(String::from).collect::<Vec<_>>();
+            let Some(name) = fields.next() else {
+                bail!("Expected {} columns in nvidia-smi output, found {line:?}", METRICS.len() + 2);
+            };
+
+            Ok(Gpu { index, name: name.to_string(), values })
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(Table)
+}
+
+/// Run `nvidia-smi` for all metrics of all GPUs
+pub fn query(timeout: Duration) -> Result<Table> {
+    let fields = METRICS.iter().map(|x| x.field).collect::<Vec<_>>().join(",");
+    let command = [
+        "nvidia-smi".to_string(),
+        format!("--query-gpu=index,{fields},name"),
+        "--format=csv,noheader,nounits".to_string(),
+    ];
+
+    parse(&crate::exec::run(&command, timeout)?)
+}
+
+impl Table {
+    /// Read the metric of the GPU in path like `0/temperature`
+    pub fn read(&self, path: &Path, trace: &mut ReadTrace) -> Result<f64> {
+        let (index, metric) = parse_path(path)?;
+
+        let gpu = self.0.iter().find(|x| x.index == index)
+            .with_context(|| anyhow!("No GPU with index {index}, nvidia-smi found {}", self.0.len()))?;
+
+        let position = METRICS.iter().position(|x| x == metric).unwrap();
+        let text = trace.text.insert(gpu.values[position].clone());
+
+        text.parse().with_context(|| anyhow!("GPU {index} ({}) has no {} value, found {text:?}", gpu.name, metric.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: &str = "0, 54, 30, 85.31, 12, 4, 1024, 10240, 1710, NVIDIA GeForce RTX 3080\n\
+                          1, 41, [N/A], 20.05, 0, 0, 5, 4096, 300, Tesla T4, rev 2\n";
+
+    #[test]
+    fn test_parse() {
+        let table = parse(OUTPUT).unwrap();
+        assert_eq!(table.0.len(), 2);
+        assert_eq!(table.0[0].name, "NVIDIA GeForce RTX 3080");
+        assert_eq!(table.0[1].name, "Tesla T4, rev 2");
+
+        assert!(parse("0, 54, 30\n").is_err());
+        assert_eq!(parse("").unwrap(), Table(vec![]));
+    }
+
+    #[test]
+    fn test_read() {
+        let table = parse(OUTPUT).unwrap();
+        let read = |path: &str| table.read(Path::new(path), &mut ReadTrace::default());
+
+        assert_eq!(read("0/temperature").unwrap(), 54.0);
+        assert_eq!(read("0/power_draw").unwrap(), 85.31);
+        assert_eq!(read("1/clock_graphics").unwrap(), 300.0);
+
+        // passive cooling has no fan
+        let err = format!("{:#}", read("1/fan_speed").unwrap_err());
+        assert!(err.contains("Tesla T4, rev 2") && err.contains("[N/A]"), "{err}");
+
+        assert!(read("2/temperature").is_err());
+        assert!(read("0/voltage").is_err());
+        assert!(read("temperature").is_err());
+        assert!(read("x/temperature").is_err());
+    }
+}
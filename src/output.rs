@@ -0,0 +1,808 @@
+use crate::prelude::*;
+use crate::alarm::{ActiveAlarm, AlarmState, Severity};
+use crate::config::{ReadTrace, Sensor, SensorKind, SensorSource, TimestampFormat};
+use crate::fan::OutputStatus;
+use crate::stats::SensorStats;
+use crate::Widgets;
+use serde::Serialize;
+use std::path::Path;
+
+/// Single reading of a sensor, used for machine readable output
+#[derive(Debug, Clone, Serialize)]
+pub struct Reading {
+    pub name: String,
+    pub label: Option<String>,
+
+    /// Value as read from the source
+    pub raw: f64,
+
+    /// Value after mapping and unit conversion
+    pub value: f64,
+
+    /// Value formatted with sensor options
+    pub formatted: String,
+
+    pub unit: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<SensorKind>,
+
+    pub alarm: AlarmState,
+    pub severity: Severity,
+
+    /// Statistics of the session including this reading
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<SensorStats>,
+
+    /// Rate of change per second, missing on the first reading
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate: Option<f64>,
+
+    /// Reading is from an earlier tick as the sensor has its own `poll_rate`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+
+    /// Reading failed and this is the last value, kept for `stale_for`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+}
+
+impl Reading {
+    pub fn new(sensor: &Sensor, raw: f64, value: f64, alarm: AlarmState) -> Self {
+        Self {
+            name: sensor.name.clone(),
+            label: sensor.label_name().map(String::from),
+            raw,
+            value,
+            formatted: sensor.format_value(value),
+            unit: sensor.unit().map(String::from),
+            kind: sensor.kind,
+            alarm,
+            severity: alarm.severity(),
+            stats: None,
+            rate: None,
+            cached: false,
+            stale: false,
+        }
+    }
+}
+
+/// Sensor that could not be read, value is always null
+#[derive(Debug, Serialize)]
+struct FailedReading<'a> {
+    name: &'a str,
+    label: Option<&'a str>,
+    value: Option<f64>,
+    unit: Option<&'a str>,
+    error: &'a str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace: Option<JsonTrace<'a>>,
+}
+
+/// Thresholds the value is compared against
+#[derive(Debug, Serialize)]
+struct Thresholds {
+    alarm_high: Option<f64>,
+    alarm_low: Option<f64>,
+    warn_high: Option<f64>,
+    warn_low: Option<f64>,
+    alarm_hysteresis: Option<f64>,
+    rate_alarm_high: Option<f64>,
+}
+
+/// Every step of reading the sensor for `--verbose-json`, the value shown and alarm are in the reading
+#[derive(Debug, Serialize)]
+struct JsonTrace<'a> {
+    source: &'a SensorSource,
+
+    #[serde(flatten)]
+    read: &'a ReadTrace,
+
+    /// Kind and divisor were detected from the hwmon file name
+    auto_units: bool,
+
+    thresholds: Thresholds,
+}
+
+impl<'a> JsonTrace<'a> {
+    fn new(sensor: &'a Sensor, read: &'a ReadTrace) -> Self {
+        let thresholds = Thresholds {
+            alarm_high: sensor.alarm_high,
+            alarm_low: sensor.alarm_low,
+            warn_high: sensor.warn_high,
+            warn_low: sensor.warn_low,
+            alarm_hysteresis: sensor.alarm_hysteresis,
+            rate_alarm_high: sensor.rate_alarm_high,
+        };
+
+        Self { source: &sensor.source, read, auto_units: sensor.auto_units, thresholds }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum JsonReading<'a> {
+    Ok {
+        #[serde(flatten)]
+        reading: &'a Reading,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        trace: Option<JsonTrace<'a>>,
+    },
+    Failed(FailedReading<'a>),
+}
+
+/// Time of a tick in the configured format
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Timestamp {
+    Text(String),
+
+    /// Whole milliseconds, json gets a number instead of a string
+    Seconds(f64),
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Text(x) => write!(f, "{x}"),
+            Self::Seconds(x) => write!(f, "{x:.3}"),
+        }
+    }
+}
+
+/// Timestamp of a tick at `time`, `elapsed` since kelvin started, none if disabled
+pub fn timestamp(format: TimestampFormat, utc: bool, time: chrono::DateTime<chrono::Local>, elapsed: std::time::Duration) -> Option<Timestamp> {
+    match format {
+        TimestampFormat::None => None,
+        TimestampFormat::Iso8601 if utc => Some(Timestamp::Text(time.to_utc().to_rfc3339_opts(chrono::SecondsFormat::Millis, true))),
+        TimestampFormat::Iso8601 => Some(Timestamp::Text(time.to_rfc3339_opts(chrono::SecondsFormat::Millis, false))),
+        TimestampFormat::Unix => Some(Timestamp::Seconds(time.timestamp_millis() as f64 / 1000.0)),
+        TimestampFormat::Relative => Some(Timestamp::Seconds(elapsed.as_millis() as f64 / 1000.0)),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonOutput<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<&'a Timestamp>,
+
+    readings: Vec<JsonReading<'a>>,
+
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    outputs: &'a [OutputStatus],
+}
+
+/// All successful readings in config order
+pub fn readings(widgets: &Widgets) -> Vec<&Reading> {
+    widgets.iter().filter_map(|(_, x)| x.reading()).collect()
+}
+
+/// All sensors on a single line, for places like `systemctl status`
+pub fn summary(widgets: &Widgets, unavailable: &str) -> String {
+    widgets.iter()
+        .filter_map(|(_, x)| {
+            let sensor = x.sensor()?;
+            match x.reading() {
+                Some(reading) => Some(sensor.format_labeled(reading.value)),
+                None => Some(format!("{}{unavailable}", sensor.prefix())),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Readings of all sensors, failed sensors have null value and an error
+///
+/// Widgets keep a trace of reading the sensor only with `--verbose-json`
+fn json_readings(widgets: &Widgets) -> Vec<JsonReading<'_>> {
+    widgets.iter()
+        .filter_map(|(_, x)| {
+            let trace = match (x.sensor(), x.trace()) {
+                (Some(sensor), Some(read)) => Some(JsonTrace::new(sensor, read)),
+                _ => None,
+            };
+
+            if let Some(reading) = x.reading() {
+                return Some(JsonReading::Ok { reading, trace });
+            }
+
+            let sensor = x.sensor()?;
+            Some(JsonReading::Failed(FailedReading {
+                name: &sensor.name,
+                label: sensor.label_name(),
+                value: None,
+                unit: sensor.unit(),
+                error: x.error()?,
+                trace,
+            }))
+        })
+        .collect()
+}
+
+/// Single line json object containing all readings, failed sensors have null value and an error
+pub fn json(widgets: &Widgets, outputs: &[OutputStatus], timestamp: Option<&Timestamp>) -> Result<String> {
+    let output = JsonOutput { timestamp, readings: json_readings(widgets), outputs };
+
+    serde_json::to_string(&output)
+        .with_context(|| anyhow!("Unable to serialize readings"))
+}
+
+#[derive(Debug, Serialize)]
+struct StatusOutput<'a> {
+    text: &'a str,
+    alarms: &'a [ActiveAlarm],
+    readings: Vec<JsonReading<'a>>,
+
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    outputs: &'a [OutputStatus],
+}
+
+/// Single line json answer to the status request, has everything needed to print like `--once`
+pub fn status(text: &str, widgets: &Widgets, alarms: &[ActiveAlarm], outputs: &[OutputStatus]) -> Result<String> {
+    let output = StatusOutput {
+        text,
+        alarms,
+        readings: json_readings(widgets),
+        outputs,
+    };
+
+    serde_json::to_string(&output)
+        .with_context(|| anyhow!("Unable to serialize status"))
+}
+
+#[derive(Debug, Serialize)]
+struct WaybarOutput {
+    text: String,
+    tooltip: String,
+    class: &'static str,
+}
+
+/// Waybar custom module output, tooltip lists all sensors
+///
+/// Class is `critical` or `warning` by the worst alarm of all sensors, otherwise `error` if any sensor failed
+pub fn waybar(text: &str, widgets: &Widgets, unavailable: &str) -> Result<String> {
+    let mut tooltip = vec![];
+    let mut severity = Severity::Normal;
+    let mut any_error = false;
+
+    for (_, widget) in widgets {
+        let Some(sensor) = widget.sensor() else {
+            continue;
+        };
+
+        match (widget.reading(), widget.error()) {
+            (Some(reading), _) => {
+                tooltip.push(sensor.format_labeled(reading.value));
+                severity = severity.max(reading.severity);
+            },
+            (None, Some(err)) => {
+                tooltip.push(format!("{}{unavailable} ({err})", sensor.prefix()));
+                any_error = true;
+            },
+            (None, None) => {},
+        }
+    }
+
+    let class = match severity {
+        Severity::Critical => "critical",
+        Severity::Warning => "warning",
+        Severity::Normal if any_error => "error",
+        Severity::Normal => "",
+    };
+
+    let output = WaybarOutput {
+        text: text.to_string(),
+        tooltip: tooltip.join("\n"),
+        class,
+    };
+
+    serde_json::to_string(&output)
+        .with_context(|| anyhow!("Unable to serialize waybar output"))
+}
+
+/// Replace all characters not allowed in prometheus names with underscore
+pub fn sanitize_metric_name(name: &str) -> String {
+    let mut sanitized = name.chars()
+        .map(|x| if x.is_ascii_alphanumeric() || x == '_' || x == ':' { x } else { '_' })
+        .collect::<String>();
+
+    // names cannot start with a digit
+    if sanitized.starts_with(|x: char| x.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+
+    sanitized
+}
+
+/// Escape label value for prometheus text format
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Prometheus text exposition format
+pub fn prometheus(readings: &[&Reading]) -> String {
+    let mut lines = vec![
+        "# HELP kelvin_sensor_value Current value of the sensor".to_string(),
+        "# TYPE kelvin_sensor_value gauge".to_string(),
+    ];
+
+    for reading in readings {
+        lines.push(format!(
+            "kelvin_sensor_value{{name=\"{}\",unit=\"{}\",kind=\"{}\"}} {}",
+            sanitize_metric_name(&reading.name),
+            escape_label(reading.unit.as_deref().unwrap_or("")),
+            reading.kind.map(|x| x.as_str()).unwrap_or_default(),
+            reading.value,
+        ));
+    }
+
+    lines.push("# HELP kelvin_sensor_alarm Whether the sensor is in alarm state".to_string());
+    lines.push("# TYPE kelvin_sensor_alarm gauge".to_string());
+
+    for reading in readings {
+        lines.push(format!(
+            "kelvin_sensor_alarm{{name=\"{}\"}} {}",
+            sanitize_metric_name(&reading.name),
+            u8::from(reading.severity == Severity::Critical),
+        ));
+    }
+
+    lines.push("# HELP kelvin_sensor_severity Alarm severity, 0 normal, 1 warning and 2 critical".to_string());
+    lines.push("# TYPE kelvin_sensor_severity gauge".to_string());
+
+    for reading in readings {
+        lines.push(format!(
+            "kelvin_sensor_severity{{name=\"{}\"}} {}",
+            sanitize_metric_name(&reading.name),
+            reading.severity as u8,
+        ));
+    }
+
+    lines.join("\n")
+}
+
+/// Escape tag value for influx line protocol
+fn escape_influx_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for x in value.chars() {
+        match x {
+            ',' | '=' | ' ' => {
+                escaped.push('\\');
+                escaped.push(x);
+            },
+
+            // line breaks end the point and cannot be escaped
+            '\n' | '\r' => escaped.push(' '),
+            _ => escaped.push(x),
+        }
+    }
+
+    escaped
+}
+
+/// InfluxDB line protocol, a point per reading with the timestamp in nanoseconds
+///
+/// Values that are not finite are left out as the protocol cannot represent them
+pub fn influx(readings: &[&Reading], hostname: &str, timestamp: i64) -> String {
+    let host = escape_influx_tag(hostname);
+
+    readings.iter()
+        .filter(|x| x.value.is_finite())
+        .map(|x| {
+            let mut tags = format!("host={host},sensor={}", escape_influx_tag(&x.name));
+
+            // tags cannot be empty
+            if let Some(unit) = x.unit.as_deref().filter(|x| !x.is_empty()) {
+                tags.push_str(&format!(",unit={}", escape_influx_tag(unit)));
+            }
+
+            format!("kelvin,{tags} value={:?},alarm={}i {timestamp}", x.value, x.severity as u8)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Current time in nanoseconds since the epoch, for influx timestamps
+pub fn influx_timestamp() -> i64 {
+    chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+}
+
+/// Replace characters that would change the metric path in graphite, dots separate its nodes
+pub fn sanitize_graphite_node(name: &str) -> String {
+    name.chars()
+        .map(|x| if x.is_ascii_alphanumeric() || matches!(x, '_' | '-') { x } else { '_' })
+        .collect()
+}
+
+/// Graphite plaintext protocol, a line per reading with the timestamp in seconds
+///
+/// The prefix may contain dots to nest the metrics deeper
+pub fn graphite(readings: &[&Reading], prefix: &str, hostname: &str, timestamp: i64) -> String {
+    let prefix = prefix.trim_matches('.');
+    let host = sanitize_graphite_node(hostname);
+
+    readings.iter()
+        .filter(|x| x.value.is_finite())
+        .map(|x| {
+            let path = [prefix, &host, &sanitize_graphite_node(&x.name)].into_iter()
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<_>>()
+                .join(".");
+
+            format!("{path} {} {timestamp}", x.value)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Result of the check, values are exit codes of `--check` and Nagios plugins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl CheckStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ok => "OK",
+            Self::Warning => "WARNING",
+            Self::Critical => "CRITICAL",
+            Self::Unknown => "UNKNOWN",
+        }
+    }
+
+    /// Status from the worst alarm of all sensors
+    pub fn from_widgets(widgets: &Widgets) -> Self {
+        let severity = widgets.iter()
+            .filter_map(|(_, x)| x.reading())
+            .map(|x| x.severity)
+            .max()
+            .unwrap_or_default();
+
+        match severity {
+            Severity::Normal => Self::Ok,
+            Severity::Warning => Self::Warning,
+            Severity::Critical => Self::Critical,
+        }
+    }
+}
+
+/// Perfdata units may only be letters or percent
+fn nagios_unit(unit: Option<&str>) -> String {
+    unit.unwrap_or_default().chars().filter(|x| x.is_ascii_alphabetic() || *x == '%').collect()
+}
+
+/// Perfdata labels with spaces or special characters have to be quoted
+fn nagios_label(name: &str) -> String {
+    if name.chars().any(|x| x.is_whitespace() || matches!(x, '=' | '\'')) {
+        format!("'{}'", name.replace('\'', "''"))
+    } else {
+        name.to_string()
+    }
+}
+
+/// Nagios plugin output line with perfdata of all sensors, thresholds are `warn_high` and `alarm_high`
+pub fn nagios(widgets: &Widgets, unavailable: &str) -> String {
+    let mut text = vec![];
+    let mut perfdata = vec![];
+
+    for (_, widget) in widgets {
+        let Some(sensor) = widget.sensor() else {
+            continue;
+        };
+
+        let label = nagios_label(&sensor.name);
+        let Some(reading) = widget.reading() else {
+            text.push(format!("{label}={unavailable}"));
+            continue;
+        };
+
+        let unit = nagios_unit(reading.unit.as_deref());
+        let option = |x: Option<f64>| x.map(|x| x.to_string()).unwrap_or_default();
+
+        text.push(format!("{label}={}{unit}", reading.formatted));
+        perfdata.push(format!(
+            "{label}={}{unit};{};{};{};{}",
+            reading.formatted,
+            option(sensor.warn_high),
+            option(sensor.alarm_high),
+            option(sensor.min),
+            option(sensor.max),
+        ));
+    }
+
+    let status = CheckStatus::from_widgets(widgets);
+    format!("KELVIN {} - {} | {}", status.as_str(), text.join(", "), perfdata.join(" "))
+}
+
+/// Nagios output when the check could not be done
+pub fn nagios_unknown(err: &anyhow::Error) -> String {
+    // plugin output has to be a single line
+    format!("KELVIN {} - {}", CheckStatus::Unknown.as_str(), format!("{err:#}").replace('\n', " "))
+}
+
+/// Write the file by writing a temporary file and renaming it, so readers never see partial data
+pub fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    std::fs::write(&tmp, contents)
+        .with_context(|| anyhow!("Unable to write {tmp:?}"))?;
+
+    std::fs::rename(&tmp, path)
+        .with_context(|| anyhow!("Unable to rename {tmp:?} to {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(name: &str, unit: Option<&str>, value: f64, alarm: AlarmState) -> Reading {
+        Reading {
+            name: name.into(),
+            label: None,
+            raw: value,
+            value,
+            formatted: value.to_string(),
+            unit: unit.map(String::from),
+            kind: None,
+            alarm,
+            severity: alarm.severity(),
+            stats: None,
+            rate: None,
+            cached: false,
+            stale: false,
+        }
+    }
+
+    /// Widget that failed to read its sensor
+    struct FailedWidget(Sensor);
+
+    impl crate::Widget for FailedWidget {
+        fn value(&mut self, _ctx: &crate::Context) -> Result<String> {
+            bail!("unreachable")
+        }
+
+        fn sensor(&self) -> Option<&Sensor> {
+            Some(&self.0)
+        }
+
+        fn error(&self) -> Option<&str> {
+            Some("No such file")
+        }
+    }
+
+    #[test]
+    fn test_failed_sensor() {
+        let sensor = Sensor { name: "gpu".into(), ..Default::default() };
+        let widgets: Widgets = vec![("{gpu}".into(), Box::new(FailedWidget(sensor)))];
+
+        assert_eq!(
+            json(&widgets, &[], None).unwrap(),
+            r#"{"readings":[{"name":"gpu","label":null,"value":null,"unit":null,"error":"No such file"}]}"#,
+        );
+
+        assert_eq!(
+            waybar("N/A", &widgets, "N/A").unwrap(),
+            r#"{"text":"N/A","tooltip":"gpu: N/A (No such file)","class":"error"}"#,
+        );
+    }
+
+    /// Widget with a successful reading
+    struct ReadingWidget(Sensor, Reading);
+
+    impl crate::Widget for ReadingWidget {
+        fn value(&mut self, _ctx: &crate::Context) -> Result<String> {
+            bail!("unreachable")
+        }
+
+        fn sensor(&self) -> Option<&Sensor> {
+            Some(&self.0)
+        }
+
+        fn reading(&self) -> Option<&Reading> {
+            Some(&self.1)
+        }
+    }
+
+    #[test]
+    fn test_waybar_severity() {
+        let widget = |name: &str, alarm| -> (String, Box<dyn crate::Widget>) {
+            let sensor = Sensor { name: name.into(), ..Default::default() };
+            (format!("{{{name}}}"), Box::new(ReadingWidget(sensor, reading(name, None, 50.0, alarm))))
+        };
+
+        let class = |widgets: &Widgets| {
+            let output: serde_json::Value = serde_json::from_str(&waybar("", widgets, "N/A").unwrap()).unwrap();
+            output["class"].as_str().unwrap().to_string()
+        };
+
+        let mut widgets: Widgets = vec![widget("cpu", AlarmState::Normal)];
+        assert_eq!(class(&widgets), "");
+
+        widgets.push(widget("gpu", AlarmState::WarnHigh));
+        assert_eq!(class(&widgets), "warning");
+
+        // worst one wins
+        widgets.push(widget("nvme", AlarmState::Low));
+        assert_eq!(class(&widgets), "critical");
+    }
+
+    #[test]
+    fn test_json_stats() {
+        let sensor = Sensor { name: "cpu".into(), ..Default::default() };
+        let mut cpu = reading("cpu", None, 50.0, AlarmState::Normal);
+        cpu.stats = Some(SensorStats { count: 2, min: 40.0, max: 50.0, mean: 45.0 });
+
+        let widgets: Widgets = vec![("{cpu}".into(), Box::new(ReadingWidget(sensor, cpu)))];
+        let output: serde_json::Value = serde_json::from_str(&json(&widgets, &[], None).unwrap()).unwrap();
+
+        assert_eq!(output["readings"][0]["stats"], serde_json::json!({ "count": 2, "min": 40.0, "max": 50.0, "mean": 45.0 }));
+        assert!(output["readings"][0].get("cached").is_none());
+    }
+
+    /// Widget with a trace of reading its sensor
+    struct TracedWidget(Sensor, Option<Reading>, ReadTrace);
+
+    impl crate::Widget for TracedWidget {
+        fn value(&mut self, _ctx: &crate::Context) -> Result<String> {
+            bail!("unreachable")
+        }
+
+        fn sensor(&self) -> Option<&Sensor> {
+            Some(&self.0)
+        }
+
+        fn reading(&self) -> Option<&Reading> {
+            self.1.as_ref()
+        }
+
+        fn error(&self) -> Option<&str> {
+            Some("Could not parse float")
+        }
+
+        fn trace(&self) -> Option<&ReadTrace> {
+            Some(&self.2)
+        }
+    }
+
+    #[test]
+    fn test_json_trace() {
+        let sensor = Sensor { name: "cpu".into(), source: SensorSource::File, alarm_high: Some(90.0), ..Default::default() };
+        let trace = ReadTrace { path: Some("/sys/temp1_input".into()), text: Some("45000\n".into()), raw: Some(45000.0), ..Default::default() };
+        let widgets: Widgets = vec![
+            ("{cpu}".into(), Box::new(TracedWidget(sensor.clone(), Some(reading("cpu", None, 45.0, AlarmState::Normal)), trace.clone()))),
+            ("{gpu}".into(), Box::new(TracedWidget(Sensor { name: "gpu".into(), ..sensor }, None, trace))),
+        ];
+
+        let output: serde_json::Value = serde_json::from_str(&json(&widgets, &[], None).unwrap()).unwrap();
+        let readings = &output["readings"];
+        assert_eq!(readings[0]["value"], 45.0);
+        assert_eq!(readings[0]["trace"]["source"], "file");
+        assert_eq!(readings[0]["trace"]["path"], "/sys/temp1_input");
+        assert_eq!(readings[0]["trace"]["text"], "45000\n");
+        assert_eq!(readings[0]["trace"]["thresholds"]["alarm_high"], 90.0);
+
+        // failed reading still shows how far it got
+        assert_eq!(readings[1]["error"], "Could not parse float");
+        assert_eq!(readings[1]["trace"]["raw"], 45000.0);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let time = chrono::DateTime::parse_from_rfc3339("2024-05-01T12:00:00.25+02:00").unwrap().with_timezone(&chrono::Local);
+        let elapsed = std::time::Duration::from_micros(90_500_900);
+        let timestamp = |format, utc| timestamp(format, utc, time, elapsed);
+
+        assert_eq!(timestamp(TimestampFormat::None, false), None);
+        assert_eq!(timestamp(TimestampFormat::Iso8601, true), Some(Timestamp::Text("2024-05-01T10:00:00.250Z".into())));
+        assert_eq!(timestamp(TimestampFormat::Unix, false), Some(Timestamp::Seconds(1714557600.25)));
+
+        let relative = timestamp(TimestampFormat::Relative, false).unwrap();
+        assert_eq!(relative.to_string(), "90.500");
+
+        let sensor = Sensor { name: "cpu".into(), ..Default::default() };
+        let widgets: Widgets = vec![("{cpu}".into(), Box::new(ReadingWidget(sensor, reading("cpu", None, 50.0, AlarmState::Normal))))];
+        let output: serde_json::Value = serde_json::from_str(&json(&widgets, &[], Some(&relative)).unwrap()).unwrap();
+        assert_eq!(output["timestamp"], 90.5);
+    }
+
+    #[test]
+    fn test_nagios() {
+        let widget = |name: &str, value: f64, alarm| -> (String, Box<dyn crate::Widget>) {
+            let sensor = Sensor {
+                name: name.into(),
+                warn_high: Some(80.0),
+                alarm_high: Some(95.0),
+                min: Some(0.0),
+                ..Default::default()
+            };
+
+            (format!("{{{name}}}"), Box::new(ReadingWidget(sensor, reading(name, Some("°C"), value, alarm))))
+        };
+
+        let mut widgets: Widgets = vec![widget("cpu", 62.5, AlarmState::Normal)];
+        assert_eq!(CheckStatus::from_widgets(&widgets), CheckStatus::Ok);
+        assert_eq!(nagios(&widgets, "N/A"), "KELVIN OK - cpu=62.5C | cpu=62.5C;80;95;0;");
+
+        widgets.push(widget("gpu temp", 85.0, AlarmState::WarnHigh));
+        assert_eq!(CheckStatus::from_widgets(&widgets), CheckStatus::Warning);
+        assert_eq!(
+            nagios(&widgets, "N/A"),
+            "KELVIN WARNING - cpu=62.5C, 'gpu temp'=85C | cpu=62.5C;80;95;0; 'gpu temp'=85C;80;95;0;",
+        );
+
+        let sensor = Sensor { name: "nvme".into(), ..Default::default() };
+        widgets.push(("{nvme}".into(), Box::new(FailedWidget(sensor))));
+        widgets.push(widget("vrm", 99.0, AlarmState::High));
+        assert_eq!(CheckStatus::from_widgets(&widgets), CheckStatus::Critical);
+        assert!(nagios(&widgets, "N/A").starts_with("KELVIN CRITICAL - cpu=62.5C, 'gpu temp'=85C, nvme=N/A, vrm=99C |"));
+
+        assert_eq!(nagios_unknown(&anyhow!("No such file")), "KELVIN UNKNOWN - No such file");
+    }
+
+    #[test]
+    fn test_sanitize_metric_name() {
+        assert_eq!(sanitize_metric_name("cpu"), "cpu");
+        assert_eq!(sanitize_metric_name("cpu temp-1"), "cpu_temp_1");
+        assert_eq!(sanitize_metric_name("0fan"), "_0fan");
+    }
+
+    #[test]
+    fn test_prometheus() {
+        let cpu = Reading { kind: Some(SensorKind::Temp), ..reading("cpu", Some("°C"), 62.5, AlarmState::Normal) };
+        let gpu = reading("gpu.edge", None, 95.0, AlarmState::High);
+        let nvme = reading("nvme", None, 70.0, AlarmState::WarnHigh);
+
+        assert_eq!(prometheus(&[&cpu, &gpu, &nvme]), [
+            "# HELP kelvin_sensor_value Current value of the sensor",
+            "# TYPE kelvin_sensor_value gauge",
+            "kelvin_sensor_value{name=\"cpu\",unit=\"°C\",kind=\"temp\"} 62.5",
+            "kelvin_sensor_value{name=\"gpu_edge\",unit=\"\",kind=\"\"} 95",
+            "kelvin_sensor_value{name=\"nvme\",unit=\"\",kind=\"\"} 70",
+            "# HELP kelvin_sensor_alarm Whether the sensor is in alarm state",
+            "# TYPE kelvin_sensor_alarm gauge",
+            "kelvin_sensor_alarm{name=\"cpu\"} 0",
+            "kelvin_sensor_alarm{name=\"gpu_edge\"} 1",
+            "kelvin_sensor_alarm{name=\"nvme\"} 0",
+            "# HELP kelvin_sensor_severity Alarm severity, 0 normal, 1 warning and 2 critical",
+            "# TYPE kelvin_sensor_severity gauge",
+            "kelvin_sensor_severity{name=\"cpu\"} 0",
+            "kelvin_sensor_severity{name=\"gpu_edge\"} 2",
+            "kelvin_sensor_severity{name=\"nvme\"} 1",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_influx() {
+        let cpu = reading("cpu", Some("°C"), 62.5, AlarmState::Normal);
+        let gpu = reading("gpu edge,hot=1", None, 95.0, AlarmState::High);
+        let broken = reading("broken", None, f64::NAN, AlarmState::Normal);
+
+        assert_eq!(influx(&[&cpu, &gpu, &broken], "my host", 1700000000000000000), [
+            "kelvin,host=my\\ host,sensor=cpu,unit=°C value=62.5,alarm=0i 1700000000000000000",
+            "kelvin,host=my\\ host,sensor=gpu\\ edge\\,hot\\=1 value=95.0,alarm=2i 1700000000000000000",
+        ].join("\n"));
+
+        assert_eq!(influx(&[], "host", 0), "");
+    }
+
+    #[test]
+    fn test_graphite() {
+        let cpu = reading("cpu", Some("°C"), 62.5, AlarmState::Normal);
+        let gpu = reading("gpu.edge temp", None, 95.0, AlarmState::High);
+        let broken = reading("broken", None, f64::INFINITY, AlarmState::Normal);
+
+        assert_eq!(graphite(&[&cpu, &gpu, &broken], "servers.kelvin.", "my.host", 1700000000), [
+            "servers.kelvin.my_host.cpu 62.5 1700000000",
+            "servers.kelvin.my_host.gpu_edge_temp 95 1700000000",
+        ].join("\n"));
+
+        assert_eq!(graphite(&[&cpu], "", "host", 1), "host.cpu 62.5 1");
+    }
+}
@@ -0,0 +1,93 @@
+//! Rate of change of a sensor, alarmed on like a separate sensor
+
+use crate::config::{Sensor, SensorLabel};
+use std::time::Instant;
+
+/// Sensor the rate is shown and alarmed as, named `<name>_rate` with the unit per second
+pub fn rate_sensor(sensor: &Sensor) -> Option<Sensor> {
+    let name = sensor.rate_name()?;
+
+    Some(Sensor {
+        name,
+        label: Some(SensorLabel {
+            name: format!("{} rate", sensor.label_name().unwrap_or(&sensor.name)),
+            unit: Some(format!("{}/s", sensor.unit().unwrap_or_default())),
+        }),
+        round: sensor.rate.and_then(|x| x.round).or(sensor.round),
+        alarm_high: sensor.rate_alarm_high,
+        alarm_low: None,
+        warn_high: None,
+        warn_low: None,
+        min: None,
+        max: None,
+        temperature: false,
+        temperature_unit: None,
+
+        // a rate of 0 is not a fan stopping
+        kind: None,
+        ..sensor.clone()
+    })
+}
+
+/// Computes the rate from consecutive readings using the time between them
+#[derive(Debug, Clone, Default)]
+pub struct RateTracker {
+    last: Option<(f64, Instant)>,
+}
+
+impl RateTracker {
+    /// Add the value read at `now`, there is no rate until there are two readings
+    pub fn update(&mut self, value: f64, now: Instant) -> Option<f64> {
+        let last = self.last.replace((value, now));
+        let (previous, since) = last?;
+
+        let elapsed = now.duration_since(since).as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+
+        Some((value - previous) / elapsed)
+    }
+
+    /// Forget the last reading, so the rate is not computed across a failed read
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rate() {
+        let start = Instant::now();
+        let at = |x: u64| start + Duration::from_millis(x);
+
+        let mut rate = RateTracker::default();
+        assert_eq!(rate.update(40.0, at(0)), None);
+        assert_eq!(rate.update(42.0, at(1000)), Some(2.0));
+
+        // real elapsed time is used, not the poll rate
+        assert_eq!(rate.update(45.0, at(3000)), Some(1.5));
+        assert_eq!(rate.update(44.0, at(3500)), Some(-2.0));
+
+        rate.reset();
+        assert_eq!(rate.update(60.0, at(4500)), None);
+        assert_eq!(rate.update(61.0, at(5500)), Some(1.0));
+    }
+
+    #[test]
+    fn test_rate_sensor() {
+        let sensor = Sensor { name: "cpu".into(), round: Some(1), alarm_high: Some(90.0), ..Default::default() };
+        assert!(rate_sensor(&sensor).is_none());
+
+        let sensor = Sensor { rate_alarm_high: Some(2.0), temperature_unit: Some(Default::default()), ..sensor };
+        let rate = rate_sensor(&sensor).unwrap();
+
+        assert_eq!(rate.name, "cpu_rate");
+        assert_eq!(rate.alarm_high, Some(2.0));
+        assert_eq!(rate.format_labeled(2.0), "cpu rate: 2.0 °C/s");
+    }
+}
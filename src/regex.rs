@@ -0,0 +1,466 @@
+//! Small backtracking regex engine, enough to pull numbers out of command output
+//!
+//! Supports literals, `.`, classes like `[0-9.]` or `[^ ]`, escapes `\d \w \s \D \W \S`, anchors `^ $`, groups
+//! `(...)` and `(?:...)`, alternation `|` and quantifiers `* + ? {n} {n,} {n,m}` with lazy `?` variants
+
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class { ranges: Vec<(char, char)>, negated: bool },
+    Start,
+    End,
+    Group { index: Option<usize>, alternatives: Vec<Vec<Node>> },
+    Repeat { node: Box<Node>, min: usize, max: Option<usize>, greedy: bool },
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class { ranges: Vec<(char, char)>, negated: bool },
+    Start,
+    End,
+    /// Try the first target, on failure the second
+    Split(usize, usize),
+    Jump(usize),
+    Save(usize),
+    Match,
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    groups: usize,
+}
+
+impl Parser<'_> {
+    fn alternatives(&mut self) -> Result<Vec<Vec<Node>>> {
+        let mut alternatives = vec![self.sequence()?];
+
+        while self.chars.next_if_eq(&'|').is_some() {
+            alternatives.push(self.sequence()?);
+        }
+
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>> {
+        let mut nodes = vec![];
+
+        while let Some(&x) = self.chars.peek() {
+            if x == '|' || x == ')' {
+                break;
+            }
+
+            let atom = self.atom()?;
+            nodes.push(self.quantifier(atom)?);
+        }
+
+        Ok(nodes)
+    }
+
+    fn atom(&mut self) -> Result<Node> {
+        let Some(x) = self.chars.next() else {
+            bail!("Unexpected end of pattern");
+        };
+
+        Ok(match x {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '[' => self.class()?,
+            '\\' => self.escape()?,
+            '(' => {
+                let index = if self.chars.next_if_eq(&'?').is_some() {
+                    if self.chars.next() != Some(':') {
+                        bail!("Only non-capturing groups `(?:...)` are supported");
+                    }
+
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+
+                let alternatives = self.alternatives()?;
+                if self.chars.next() != Some(')') {
+                    bail!("Unclosed group");
+                }
+
+                Node::Group { index, alternatives }
+            },
+            '*' | '+' | '?' | '{' => bail!("Nothing to repeat before {x:?}"),
+            x => Node::Char(x),
+        })
+    }
+
+    fn escape(&mut self) -> Result<Node> {
+        let class = |ranges: &[(char, char)], negated| Node::Class { ranges: ranges.to_vec(), negated };
+
+        Ok(match self.chars.next() {
+            Some('d') => class(&[('0', '9')], false),
+            Some('D') => class(&[('0', '9')], true),
+            Some('w') => class(&[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], false),
+            Some('W') => class(&[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], true),
+            Some('s') => class(&[(' ', ' '), ('\t', '\t'), ('\n', '\n'), ('\r', '\r')], false),
+            Some('S') => class(&[(' ', ' '), ('\t', '\t'), ('\n', '\n'), ('\r', '\r')], true),
+            Some('t') => Node::Char('\t'),
+            Some('n') => Node::Char('\n'),
+            Some(x) => Node::Char(x),
+            None => bail!("Pattern ends with a backslash"),
+        })
+    }
+
+    fn class(&mut self) -> Result<Node> {
+        let negated = self.chars.next_if_eq(&'^').is_some();
+        let mut ranges = vec![];
+
+        loop {
+            let start = match self.chars.next() {
+                None => bail!("Unclosed character class"),
+                // `]` right at the start is a literal
+                Some(']') if !ranges.is_empty() => break,
+                Some('\\') => match self.escape()? {
+                    Node::Char(x) => x,
+                    Node::Class { ranges: x, negated: false } => {
+                        ranges.extend(x);
+                        continue;
+                    },
+                    _ => bail!("Negated escapes are not supported in character class"),
+                },
+                Some(x) => x,
+            };
+
+            // range like `a-z`, `-` at the end is a literal
+            let end = match self.chars.peek() {
+                Some('-') => {
+                    self.chars.next();
+                    match self.chars.next() {
+                        Some(']') => {
+                            ranges.push((start, start));
+                            ranges.push(('-', '-'));
+                            break;
+                        },
+                        Some(x) => x,
+                        None => bail!("Unclosed character class"),
+                    }
+                },
+                _ => start,
+            };
+
+            if start > end {
+                bail!("Invalid range {start:?}-{end:?} in character class");
+            }
+
+            ranges.push((start, end));
+        }
+
+        Ok(Node::Class { ranges, negated })
+    }
+
+    fn quantifier(&mut self, node: Node) -> Result<Node> {
+        let (min, max) = match self.chars.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.chars.next();
+                let (min, max) = self.counts()?;
+                let greedy = self.chars.next_if_eq(&'?').is_none();
+                return Ok(Node::Repeat { node: Box::new(node), min, max, greedy });
+            },
+            _ => return Ok(node),
+        };
+
+        self.chars.next();
+        if matches!(node, Node::Start | Node::End) {
+            bail!("Anchors cannot be repeated");
+        }
+
+        let greedy = self.chars.next_if_eq(&'?').is_none();
+        Ok(Node::Repeat { node: Box::new(node), min, max, greedy })
+    }
+
+    /// Parse `n}`, `n,}` or `n,m}` after the opening brace
+    fn counts(&mut self) -> Result<(usize, Option<usize>)> {
+        let mut body = String::new();
+        loop {
+            match self.chars.next() {
+                Some('}') => break,
+                Some(x) => body.push(x),
+                None => bail!("Unclosed repetition"),
+            }
+        }
+
+        let number = |x: &str| x.trim().parse::<usize>()
+            .with_context(|| anyhow!("Invalid repetition count {x:?}"));
+
+        let (min, max) = match body.split_once(',') {
+            None => (number(&body)?, Some(number(&body)?)),
+            Some((min, "")) => (number(min)?, None),
+            Some((min, max)) => (number(min)?, Some(number(max)?)),
+        };
+
+        if max.is_some_and(|x| x < min) {
+            bail!("Invalid repetition {{{body}}}, maximum is lower than minimum");
+        }
+
+        Ok((min, max))
+    }
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Char(x) => program.push(Inst::Char(*x)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class { ranges, negated } => program.push(Inst::Class { ranges: ranges.clone(), negated: *negated }),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Group { index, alternatives } => {
+            if let Some(index) = index {
+                program.push(Inst::Save(index * 2));
+            }
+
+            compile_alternatives(alternatives, program);
+
+            if let Some(index) = index {
+                program.push(Inst::Save(index * 2 + 1));
+            }
+        },
+        Node::Repeat { node, min, max, greedy } => {
+            for _ in 0..*min {
+                compile(node, program);
+            }
+
+            let split = |program: &mut Vec<Inst>, at: usize, next: usize, end: usize| {
+                program[at] = if *greedy { Inst::Split(next, end) } else { Inst::Split(end, next) };
+            };
+
+            match max {
+                // loop back to the split after each repetition
+                None => {
+                    let at = program.len();
+                    program.push(Inst::Match);
+                    compile(node, program);
+                    program.push(Inst::Jump(at));
+                    let end = program.len();
+                    split(program, at, at + 1, end);
+                },
+                Some(max) => {
+                    let mut splits = vec![];
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Match);
+                        compile(node, program);
+                    }
+
+                    let end = program.len();
+                    for at in splits {
+                        split(program, at, at + 1, end);
+                    }
+                },
+            }
+        },
+    }
+}
+
+fn compile_alternatives(alternatives: &[Vec<Node>], program: &mut Vec<Inst>) {
+    let mut jumps = vec![];
+
+    for (i, sequence) in alternatives.iter().enumerate() {
+        let split = program.len();
+        let last = i == alternatives.len() - 1;
+
+        if !last {
+            program.push(Inst::Match);
+        }
+
+        for node in sequence {
+            compile(node, program);
+        }
+
+        if !last {
+            jumps.push(program.len());
+            program.push(Inst::Match);
+            program[split] = Inst::Split(split + 1, program.len());
+        }
+    }
+
+    let end = program.len();
+    for at in jumps {
+        program[at] = Inst::Jump(end);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Regex {
+    pattern: String,
+    program: Vec<Inst>,
+    groups: usize,
+}
+
+impl TryFrom<String> for Regex {
+    type Error = anyhow::Error;
+
+    fn try_from(pattern: String) -> Result<Self> {
+        Self::new(&pattern)
+    }
+}
+
+impl From<Regex> for String {
+    fn from(value: Regex) -> Self {
+        value.pattern
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let mut parser = Parser { chars: pattern.chars().peekable(), groups: 0 };
+
+        let alternatives = parser.alternatives()
+            .with_context(|| anyhow!("Invalid regex {pattern:?}"))?;
+
+        if parser.chars.next().is_some() {
+            bail!("Invalid regex {pattern:?}, unmatched closing parenthesis");
+        }
+
+        let mut program = vec![Inst::Save(0)];
+        compile_alternatives(&alternatives, &mut program);
+        program.push(Inst::Save(1));
+        program.push(Inst::Match);
+
+        Ok(Self { pattern: pattern.to_string(), program, groups: parser.groups })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Find the leftmost match, returns the whole match followed by all capture groups
+    pub fn captures<'a>(&self, text: &'a str) -> Option<Vec<Option<&'a str>>> {
+        // char boundaries so slots can be turned back into string slices
+        let offsets = text.char_indices().map(|x| x.0).chain([text.len()]).collect::<Vec<_>>();
+        let chars = text.chars().collect::<Vec<_>>();
+
+        let mut matcher = Matcher {
+            program: &self.program,
+            chars: &chars,
+            // remembers failed states, makes the matching linear
+            visited: vec![false; self.program.len() * (chars.len() + 1)],
+            slots: vec![None; (self.groups + 1) * 2],
+        };
+
+        for start in 0..=chars.len() {
+            if matcher.run(0, start) {
+                return Some(
+                    matcher.slots
+                        .chunks(2)
+                        .map(|x| match (x[0], x[1]) {
+                            (Some(start), Some(end)) => Some(&text[offsets[start]..offsets[end]]),
+                            _ => None,
+                        })
+                        .collect()
+                );
+            }
+        }
+
+        None
+    }
+
+    /// Text of the first capture group, or the whole match if there are no groups
+    pub fn extract<'a>(&self, text: &'a str) -> Option<&'a str> {
+        let captures = self.captures(text)?;
+
+        if self.groups == 0 {
+            captures[0]
+        } else {
+            captures[1]
+        }
+    }
+}
+
+struct Matcher<'a> {
+    program: &'a [Inst],
+    chars: &'a [char],
+    visited: Vec<bool>,
+    slots: Vec<Option<usize>>,
+}
+
+impl Matcher<'_> {
+    fn run(&mut self, pc: usize, sp: usize) -> bool {
+        let state = pc * (self.chars.len() + 1) + sp;
+        if self.visited[state] {
+            return false;
+        }
+        self.visited[state] = true;
+
+        let current = self.chars.get(sp).copied();
+
+        match &self.program[pc] {
+            Inst::Match => true,
+            Inst::Char(x) => current == Some(*x) && self.run(pc + 1, sp + 1),
+            Inst::Any => current.is_some_and(|x| x != '\n') && self.run(pc + 1, sp + 1),
+            Inst::Class { ranges, negated } => {
+                current.is_some_and(|x| ranges.iter().any(|(start, end)| (*start..=*end).contains(&x)) != *negated)
+                    && self.run(pc + 1, sp + 1)
+            },
+            Inst::Start => sp == 0 && self.run(pc + 1, sp),
+            Inst::End => sp == self.chars.len() && self.run(pc + 1, sp),
+            Inst::Split(first, second) => {
+                let (first, second) = (*first, *second);
+                self.run(first, sp) || self.run(second, sp)
+            },
+            Inst::Jump(x) => self.run(*x, sp),
+            Inst::Save(slot) => {
+                let slot = *slot;
+                let previous = self.slots[slot];
+                self.slots[slot] = Some(sp);
+
+                if self.run(pc + 1, sp) {
+                    return true;
+                }
+
+                self.slots[slot] = previous;
+                false
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(pattern: &str, text: &str) -> Option<String> {
+        Regex::new(pattern).unwrap().extract(text).map(|x| x.to_string())
+    }
+
+    #[test]
+    fn test_extract() {
+        let smartctl = "SMART/Health Information\nTemperature:                        38 Celsius\nAvailable Spare: 100%\n";
+
+        assert_eq!(extract(r"Temperature:\s+(\d+)", smartctl).as_deref(), Some("38"));
+        assert_eq!(extract(r"ups\.load: ([0-9.]+)", "battery.charge: 100\nups.load: 23.5\n").as_deref(), Some("23.5"));
+
+        // whole match without groups
+        assert_eq!(extract(r"\d+\.\d+", "load 0.75 1.20").as_deref(), Some("0.75"));
+
+        assert_eq!(extract(r"^(\d+)$", "42").as_deref(), Some("42"));
+        assert_eq!(extract(r"^(\d+)$", "42 C"), None);
+        assert_eq!(extract(r"(?:fan|pump)(\d)", "pump2").as_deref(), Some("2"));
+        assert_eq!(extract(r"a(b|c)+?", "abcb").as_deref(), Some("b"));
+        assert_eq!(extract(r"x{2,3}(\d)", "xx1 xxxx2").as_deref(), Some("1"));
+        assert_eq!(extract(r"[^ ]+$", "value is -12.5").as_deref(), Some("-12.5"));
+    }
+
+    #[test]
+    fn test_invalid() {
+        for pattern in ["(", "a)", "[a", "*", r"\", "a{3,1}", "(?=a)", "[z-a]"] {
+            assert!(Regex::new(pattern).is_err(), "{pattern}");
+        }
+    }
+}
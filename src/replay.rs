@@ -0,0 +1,209 @@
+//! Inputs of every tick written with `--record` and read back with `--replay`
+//!
+//! A recording is a `.jsonl` file with one tick per line, holding lm_sensors data and the text read for sensors of
+//! other sources, so replaying it goes through the same processing, alarms and outputs
+
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// What reading a sensor gave, the text before it was parsed or the error
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Input {
+    Text(String),
+    Error(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tick {
+    /// Local time in RFC 3339
+    pub time: String,
+
+    /// Milliseconds since the start of the recording
+    pub elapsed: u64,
+
+    /// Output of lm_sensors, if any sensor uses it
+    #[serde(default)]
+    pub sensors: Option<JsonValue>,
+
+    /// Inputs of sensors not using lm_sensors by sensor name
+    #[serde(default)]
+    pub inputs: BTreeMap<String, Input>,
+}
+
+impl Tick {
+    pub fn new(started: Instant, sensors: Option<JsonValue>) -> Self {
+        Self {
+            time: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            elapsed: started.elapsed().as_millis() as u64,
+            sensors,
+            inputs: BTreeMap::new(),
+        }
+    }
+
+    /// Recorded local time, the current one if it is invalid
+    pub fn local_time(&self) -> chrono::DateTime<chrono::Local> {
+        chrono::DateTime::parse_from_rfc3339(&self.time)
+            .map(|x| x.with_timezone(&chrono::Local))
+            .unwrap_or_else(|_| chrono::Local::now())
+    }
+
+    /// Value of the sensor as it was read
+    pub fn read(&self, name: &str, trace: &mut crate::config::ReadTrace) -> Result<f64> {
+        match self.inputs.get(name) {
+            Some(Input::Text(x)) => crate::config::parse_number(trace.text.insert(x.clone())),
+            Some(Input::Error(x)) => Err(anyhow!("{x}")),
+            None => bail!("Sensor {name:?} is not in the recording"),
+        }
+    }
+}
+
+/// Writes a new file for every run, so recordings are never mixed
+#[derive(Debug)]
+pub struct Recorder {
+    path: PathBuf,
+    file: File,
+}
+
+impl Recorder {
+    /// Create file named after the current time in the directory
+    pub fn create(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| anyhow!("Unable to create directory {dir:?}"))?;
+
+        let path = dir.join(format!("kelvin-{}.jsonl", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+        let file = std::fs::OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| anyhow!("Unable to create recording {path:?}"))?;
+
+        crate::log::info!("Recording to {path:?}");
+        Ok(Self { path, file })
+    }
+
+    pub fn write(&mut self, tick: &Tick) -> Result<()> {
+        let mut line = serde_json::to_string(tick).with_context(|| anyhow!("Unable to serialize tick"))?;
+        line.push('\n');
+
+        self.file.write_all(line.as_bytes())
+            .with_context(|| anyhow!("Unable to write to recording {:?}", self.path))
+    }
+}
+
+/// Ticks of a recording in order
+#[derive(Debug)]
+pub struct Replay {
+    ticks: std::vec::IntoIter<Tick>,
+
+    /// Time of the tick returned last
+    elapsed: Option<u64>,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| anyhow!("Unable to open recording {path:?}"))?;
+
+        let mut ticks = vec![];
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.with_context(|| anyhow!("Unable to read recording {path:?}"))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let tick: Tick = serde_json::from_str(&line)
+                .with_context(|| anyhow!("Invalid tick on line {} of recording {path:?}", index + 1))?;
+            ticks.push(tick);
+        }
+
+        if ticks.is_empty() {
+            bail!("Recording {path:?} has no ticks");
+        }
+
+        Ok(Self { ticks: ticks.into_iter(), elapsed: None })
+    }
+
+    /// Time between the last tick and the next one, none at the end
+    pub fn delay(&self) -> Option<Duration> {
+        let next = self.ticks.as_slice().first()?.elapsed;
+        Some(Duration::from_millis(next.saturating_sub(self.elapsed.unwrap_or(next))))
+    }
+}
+
+impl Iterator for Replay {
+    type Item = Tick;
+
+    fn next(&mut self) -> Option<Tick> {
+        let tick = self.ticks.next()?;
+        self.elapsed = Some(tick.elapsed);
+        Some(tick)
+    }
+}
+
+/// Parse replay speed like `10x` or `0.5`, `max` does not wait between ticks at all
+pub fn parse_speed(text: &str) -> Result<f64> {
+    let text = text.trim();
+    if text == "max" {
+        return Ok(f64::INFINITY);
+    }
+
+    match text.strip_suffix('x').unwrap_or(text).parse::<f64>() {
+        Ok(x) if x > 0.0 && x.is_finite() => Ok(x),
+        _ => bail!("Invalid speed {text:?}, expected a positive number like \"10x\" or \"max\""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::config::ReadTrace;
+
+    #[test]
+    fn test_parse_speed() {
+        assert_eq!(parse_speed("10x").unwrap(), 10.0);
+        assert_eq!(parse_speed("0.5").unwrap(), 0.5);
+        assert_eq!(parse_speed("max").unwrap(), f64::INFINITY);
+        assert!(parse_speed("0x").is_err());
+        assert!(parse_speed("-2").is_err());
+        assert!(parse_speed("fast").is_err());
+    }
+
+    #[test]
+    fn test_record_replay() {
+        let dir = TempDir::new("record");
+        let mut recorder = Recorder::create(&dir).unwrap();
+
+        let mut first = Tick::new(Instant::now(), Some(serde_json::json!({ "chip": { "temp1_input": 45.0 } })));
+        first.inputs.insert("gpu".into(), Input::Text("61000\n".into()));
+        first.inputs.insert("nvme".into(), Input::Error("Failed to read path".into()));
+
+        let second = Tick { elapsed: first.elapsed + 2000, sensors: None, ..first.clone() };
+        recorder.write(&first).unwrap();
+        recorder.write(&second).unwrap();
+
+        let mut replay = Replay::open(&recorder.path).unwrap();
+        assert_eq!(replay.delay(), Some(Duration::ZERO));
+        assert_eq!(replay.next().as_ref(), Some(&first));
+        assert_eq!(replay.delay(), Some(Duration::from_secs(2)));
+        assert_eq!(replay.next().as_ref(), Some(&second));
+        assert_eq!(replay.delay(), None);
+        assert!(replay.next().is_none());
+
+        let mut trace = ReadTrace::default();
+        assert_eq!(first.read("gpu", &mut trace).unwrap(), 61000.0);
+        assert_eq!(trace.text.as_deref(), Some("61000\n"));
+        assert_eq!(first.read("nvme", &mut trace).unwrap_err().to_string(), "Failed to read path");
+        assert!(first.read("cpu", &mut trace).is_err());
+
+        std::fs::write(dir.join("broken.jsonl"), "{\"time\": 1}\n").unwrap();
+        let err = format!("{:#}", Replay::open(&dir.join("broken.jsonl")).unwrap_err());
+        assert!(err.contains("line 1"), "{err}");
+    }
+}
@@ -0,0 +1,67 @@
+//! Reading slow sensors less often than every tick
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    /// When the sensor was last read
+    last: Option<Instant>,
+}
+
+impl Schedule {
+    /// Returns true if the sensor has to be read at `now`, always without an interval
+    pub fn due(&self, interval: Option<Duration>, now: Instant) -> bool {
+        match (interval, self.last) {
+            (Some(interval), Some(last)) => now.duration_since(last) >= interval,
+            _ => true,
+        }
+    }
+
+    /// Sensor was read at `now`
+    pub fn read(&mut self, now: Instant) {
+        self.last = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        let start = Instant::now();
+        let intervals = [None, Some(Duration::from_secs(3)), Some(Duration::from_secs(2))];
+        let mut schedules = vec![Schedule::default(); intervals.len()];
+        let mut reads = vec![vec![]; intervals.len()];
+
+        // ticks a second apart, each a bit late like the real loop
+        for tick in 0..8u64 {
+            let now = start + Duration::from_millis(tick * 1010);
+
+            for (i, schedule) in schedules.iter_mut().enumerate() {
+                if schedule.due(intervals[i], now) {
+                    schedule.read(now);
+                    reads[i].push(tick);
+                }
+            }
+        }
+
+        assert_eq!(reads[0], [0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(reads[1], [0, 3, 6]);
+        assert_eq!(reads[2], [0, 2, 4, 6]);
+    }
+
+    #[test]
+    fn test_schedule_shorter_than_tick() {
+        let start = Instant::now();
+        let mut schedule = Schedule::default();
+        let interval = Some(Duration::from_millis(200));
+
+        // cannot be read more often than the loop ticks
+        for tick in 0..3u64 {
+            let now = start + Duration::from_secs(tick);
+            assert!(schedule.due(interval, now));
+            schedule.read(now);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! systemd notification protocol, readiness, status and watchdog pings
+//!
+//! Everything is a no-op unless systemd set `NOTIFY_SOCKET`
+
+use crate::prelude::*;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    ready: bool,
+
+    /// How often to ping the watchdog, half of `WATCHDOG_USEC`
+    watchdog: Option<Duration>,
+    last_ping: Instant,
+}
+
+impl Notifier {
+    /// Connect to the socket from environment, returns `None` if not running under systemd with `Type=notify`
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+
+        // names starting with @ are in the abstract namespace
+        let addr = match path.as_encoded_bytes().strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name),
+            None => SocketAddr::from_pathname(&path),
+        }.with_context(|| anyhow!("Invalid NOTIFY_SOCKET {path:?}"))?;
+
+        let socket = UnixDatagram::unbound()
+            .with_context(|| anyhow!("Unable to create notify socket"))?;
+
+        Ok(Some(Self {
+            socket,
+            addr,
+            ready: false,
+            watchdog: watchdog_interval(),
+            last_ping: Instant::now(),
+        }))
+    }
+
+    fn send(&self, state: &str) -> Result<()> {
+        self.socket.send_to_addr(state.as_bytes(), &self.addr)
+            .with_context(|| anyhow!("Unable to send {state:?} to systemd"))?;
+
+        Ok(())
+    }
+
+    /// Update the status shown in `systemctl status`, first call also signals readiness
+    pub fn status(&mut self, status: &str) -> Result<()> {
+        // newlines separate the variables
+        let status = status.replace('\n', " ");
+
+        if self.ready {
+            self.send(&format!("STATUS={status}"))
+        } else {
+            self.send(&format!("READY=1\nSTATUS={status}"))?;
+            self.ready = true;
+            Ok(())
+        }
+    }
+
+    /// Ping the watchdog if it is due
+    pub fn watchdog(&mut self) -> Result<()> {
+        if let Some(interval) = self.watchdog && self.last_ping.elapsed() >= interval {
+            self.last_ping = Instant::now();
+            self.send("WATCHDOG=1")?;
+        }
+
+        Ok(())
+    }
+
+    /// When the watchdog has to be pinged next, if it is enabled
+    pub fn next_ping(&self) -> Option<Instant> {
+        self.watchdog.map(|x| self.last_ping + x)
+    }
+}
+
+/// Half of the watchdog timeout if it is enabled for this process
+fn watchdog_interval() -> Option<Duration> {
+    // the watchdog may be meant for another process
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") && pid.parse() != Ok(std::process::id()) {
+        return None;
+    }
+
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+
+    Some(Duration::from_micros(usec / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_notify() {
+        let dir = TempDir::new("notify");
+        let path = dir.join("notify.sock");
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        let mut notifier = Notifier {
+            socket: UnixDatagram::unbound().unwrap(),
+            addr: SocketAddr::from_pathname(&path).unwrap(),
+            ready: false,
+            watchdog: Some(Duration::from_millis(200)),
+            last_ping: Instant::now(),
+        };
+
+        let recv = || {
+            let mut buffer = [0u8; 256];
+            let len = server.recv(&mut buffer).unwrap();
+            String::from_utf8_lossy(&buffer[..len]).to_string()
+        };
+
+        notifier.status("cpu: 40°C\ngpu: 50°C").unwrap();
+        assert_eq!(recv(), "READY=1\nSTATUS=cpu: 40°C gpu: 50°C");
+
+        notifier.status("cpu: 41°C").unwrap();
+        assert_eq!(recv(), "STATUS=cpu: 41°C");
+
+        // not due yet
+        notifier.watchdog().unwrap();
+        server.set_nonblocking(true).unwrap();
+        assert!(server.recv(&mut [0u8; 16]).is_err());
+        server.set_nonblocking(false).unwrap();
+
+        std::thread::sleep(notifier.next_ping().unwrap().saturating_duration_since(Instant::now()));
+        notifier.watchdog().unwrap();
+        assert_eq!(recv(), "WATCHDOG=1");
+    }
+}
@@ -0,0 +1,196 @@
+//! Minimal signal handling, handlers only set a flag that the main loop checks
+//!
+//! Caught signals are also forwarded through a pipe so a `Waiter` can be woken up early
+
+use crate::prelude::*;
+use std::io::Read;
+use std::os::fd::IntoRawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGUSR1: i32 = 10;
+pub const SIGTERM: i32 = 15;
+
+/// Returned by `signal` on failure
+const SIG_ERR: usize = usize::MAX;
+
+const F_SETFL: i32 = 4;
+const O_NONBLOCK: i32 = 0o4000;
+
+unsafe extern "C" {
+    fn signal(signum: i32, handler: usize) -> usize;
+    fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+    fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+}
+
+/// Flags of received signals, indexed by signal number
+static RECEIVED: [AtomicBool; 32] = [const { AtomicBool::new(false) }; 32];
+
+/// Write end of the pipe signals are forwarded to, -1 if there is none
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handler(signum: i32) {
+    // only async-signal-safe operations are allowed here
+    if let Some(x) = RECEIVED.get(signum as usize) {
+        x.store(true, Ordering::SeqCst);
+    }
+
+    let fd = PIPE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let byte = signum as u8;
+
+        // SAFETY: write is async-signal-safe, if the pipe is full the waiter is woken up anyway
+        unsafe { write(fd, &byte, 1) };
+    }
+}
+
+/// Catch the signal instead of the default action, check for it with `take`
+pub fn catch(signum: i32) -> Result<()> {
+    if signum as usize >= RECEIVED.len() {
+        bail!("Signal {signum} cannot be caught");
+    }
+
+    let handler: extern "C" fn(i32) = handler;
+
+    // SAFETY: the handler only stores to an atomic and writes to a pipe
+    if unsafe { signal(signum, handler as usize) } == SIG_ERR {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| anyhow!("Unable to set handler for signal {signum}"));
+    }
+
+    Ok(())
+}
+
+/// Returns true if the signal was received since the last call
+pub fn take(signum: i32) -> bool {
+    RECEIVED.get(signum as usize).is_some_and(|x| x.swap(false, Ordering::SeqCst))
+}
+
+/// Sleep that is cut short by caught signals or a message on its channel
+#[derive(Debug)]
+pub struct Waiter {
+    rx: Receiver<i32>,
+
+    /// Signal that cut `pause` short, returned by the next `wait`
+    pending: std::cell::Cell<Option<i32>>,
+}
+
+impl Waiter {
+    pub fn new(rx: Receiver<i32>) -> Self {
+        Self { rx, pending: Default::default() }
+    }
+
+    /// Waiter woken up by all caught signals, can only be created once
+    pub fn from_signals() -> Result<Self> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        forward_signals(tx)?;
+
+        Ok(Self::new(rx))
+    }
+
+    /// Sleep for the duration, returns the signal if woken up early
+    pub fn wait(&self, duration: Duration) -> Option<i32> {
+        if let Some(signum) = self.pending.take() {
+            return Some(signum);
+        }
+
+        let deadline = Instant::now() + duration;
+
+        match self.rx.recv_timeout(duration) {
+            Ok(signum) => Some(signum),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                // nothing can wake it up anymore
+                std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                None
+            },
+        }
+    }
+
+    /// Sleep in the middle of a tick, returns false if it was cut short
+    ///
+    /// The signal is kept for the next `wait`, so the loop still handles it
+    pub fn pause(&self, duration: Duration) -> bool {
+        if self.pending.get().is_some() {
+            return false;
+        }
+
+        match self.wait(duration) {
+            Some(signum) => {
+                self.pending.set(Some(signum));
+                false
+            },
+            None => true,
+        }
+    }
+}
+
+/// Forward caught signals to the channel from a background thread
+fn forward_signals(tx: Sender<i32>) -> Result<()> {
+    let (mut reader, writer) = std::io::pipe()
+        .with_context(|| anyhow!("Unable to create signal pipe"))?;
+
+    let fd = writer.into_raw_fd();
+
+    // the handler must never block
+    // SAFETY: fd is owned by this function until it is stored
+    if unsafe { fcntl(fd, F_SETFL, O_NONBLOCK) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| anyhow!("Unable to make signal pipe non-blocking"));
+    }
+
+    if PIPE.compare_exchange(-1, fd, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+        bail!("Signals are already forwarded");
+    }
+
+    std::thread::spawn(move || {
+        let mut buffer = [0u8; 16];
+
+        while let Ok(len) = reader.read(&mut buffer) {
+            for signum in &buffer[..len] {
+                if tx.send(i32::from(*signum)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_waiter() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let waiter = Waiter::new(rx);
+
+        let start = Instant::now();
+        assert_eq!(waiter.wait(Duration::from_millis(20)), None);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // woken up immediately
+        tx.send(SIGTERM).unwrap();
+        let start = Instant::now();
+        assert_eq!(waiter.wait(Duration::from_secs(10)), Some(SIGTERM));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // signal cutting a pause short is handled by the next wait
+        tx.send(SIGTERM).unwrap();
+        assert!(!waiter.pause(Duration::from_secs(10)));
+        assert!(!waiter.pause(Duration::from_secs(10)));
+        assert_eq!(waiter.wait(Duration::from_secs(10)), Some(SIGTERM));
+        assert!(waiter.pause(Duration::from_millis(1)));
+
+        // still sleeps with nobody to wake it up
+        drop(tx);
+        let start = Instant::now();
+        assert_eq!(waiter.wait(Duration::from_millis(20)), None);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,81 @@
+//! Smoothing of noisy sensor values across ticks
+
+use crate::config::{Smoothing, SmoothingMethod};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Default)]
+pub struct Smoother {
+    /// Last values for `average`, newest at the back
+    window: VecDeque<f64>,
+
+    /// Current value of `ema`
+    ema: Option<f64>,
+}
+
+impl Smoother {
+    /// Add the value and return the smoothed one
+    pub fn smooth(&mut self, smoothing: &Smoothing, value: f64) -> f64 {
+        match smoothing.method {
+            SmoothingMethod::Average => {
+                // validation rejects zero, but do not divide by zero anyway
+                let size = smoothing.window.max(1);
+
+                self.window.push_back(value);
+                while self.window.len() > size {
+                    self.window.pop_front();
+                }
+
+                self.window.iter().sum::<f64>() / self.window.len() as f64
+            },
+            SmoothingMethod::Ema => {
+                let alpha = smoothing.alpha.clamp(0.0, 1.0);
+
+                // start from the first value, not from zero
+                let ema = match self.ema {
+                    Some(x) => alpha * value + (1.0 - alpha) * x,
+                    None => value,
+                };
+
+                self.ema = Some(ema);
+                ema
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(smoothing: Smoothing, values: &[f64]) -> Vec<f64> {
+        let mut smoother = Smoother::default();
+        values.iter()
+            // rounded so float noise does not matter
+            .map(|x| (smoother.smooth(&smoothing, *x) * 1000.0).round() / 1000.0)
+            .collect()
+    }
+
+    #[test]
+    fn test_average() {
+        let smoothing = Smoothing { method: SmoothingMethod::Average, window: 3, alpha: 0.0 };
+
+        assert_eq!(
+            run(smoothing, &[12.0, 12.3, 11.7, 12.6, 11.4, 12.0]),
+            [12.0, 12.15, 12.0, 12.2, 11.9, 12.0],
+        );
+
+        // window of one is the same as no smoothing
+        let smoothing = Smoothing { window: 1, ..smoothing };
+        assert_eq!(run(smoothing, &[1.0, 5.0, 3.0]), [1.0, 5.0, 3.0]);
+    }
+
+    #[test]
+    fn test_ema() {
+        let smoothing = Smoothing { method: SmoothingMethod::Ema, window: 0, alpha: 0.5 };
+
+        assert_eq!(run(smoothing, &[10.0, 20.0, 20.0, 0.0]), [10.0, 15.0, 17.5, 8.75]);
+
+        let smoothing = Smoothing { alpha: 1.0, ..smoothing };
+        assert_eq!(run(smoothing, &[10.0, 20.0, 0.0]), [10.0, 20.0, 0.0]);
+    }
+}
@@ -0,0 +1,84 @@
+//! Ordering of sensors in the output
+
+use crate::config::{Sensor, SortOrder};
+use std::cmp::Ordering;
+
+/// Compare two sensors with their values, failed sensors have no value and come last
+///
+/// Sensors with `order` come before the rest regardless of `sort`
+pub fn compare(sort: SortOrder, a: (&Sensor, Option<f64>), b: (&Sensor, Option<f64>)) -> Ordering {
+    let manual = match (a.0.order, b.0.order) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    };
+
+    manual.then_with(|| match sort {
+        SortOrder::Config => Ordering::Equal,
+        SortOrder::Name => a.0.name.cmp(&b.0.name),
+        SortOrder::Value | SortOrder::ValueDesc => match (a.1, b.1) {
+            (Some(x), Some(y)) if sort == SortOrder::Value => x.total_cmp(&y),
+            (Some(x), Some(y)) => y.total_cmp(&x),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        },
+    })
+}
+
+/// Sort the items in place, items without a sensor are kept after the sensors
+///
+/// Sort is stable, so sensors with equal values stay where they were on the last tick
+pub fn sort<T>(items: &mut [T], sort: SortOrder, key: impl Fn(&T) -> Option<(&Sensor, Option<f64>)>) {
+    items.sort_by(|a, b| match (key(a), key(b)) {
+        (Some(a), Some(b)) => compare(sort, a, b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(sort: SortOrder, items: &[(&str, Option<i64>, Option<f64>)]) -> Vec<String> {
+        let mut items = items.iter()
+            .map(|(name, order, value)| (Sensor { name: name.to_string(), order: *order, ..Default::default() }, *value))
+            .collect::<Vec<_>>();
+
+        super::sort(&mut items, sort, |(sensor, value)| Some((sensor, *value)));
+        items.into_iter().map(|(x, _)| x.name).collect()
+    }
+
+    #[test]
+    fn test_sort() {
+        let items = [
+            ("gpu", None, Some(50.0)),
+            ("cpu", None, None),
+            ("nvme", None, Some(40.0)),
+            ("board", None, Some(50.0)),
+        ];
+
+        assert_eq!(sorted(SortOrder::Config, &items), ["gpu", "cpu", "nvme", "board"]);
+        assert_eq!(sorted(SortOrder::Name, &items), ["board", "cpu", "gpu", "nvme"]);
+
+        // failed sensors are last in both directions, equal values keep their order
+        assert_eq!(sorted(SortOrder::Value, &items), ["nvme", "gpu", "board", "cpu"]);
+        assert_eq!(sorted(SortOrder::ValueDesc, &items), ["gpu", "board", "nvme", "cpu"]);
+    }
+
+    #[test]
+    fn test_sort_manual_order() {
+        let items = [
+            ("gpu", None, Some(50.0)),
+            ("cpu", Some(2), None),
+            ("nvme", None, Some(40.0)),
+            ("board", Some(1), Some(30.0)),
+        ];
+
+        assert_eq!(sorted(SortOrder::Value, &items), ["board", "cpu", "nvme", "gpu"]);
+        assert_eq!(sorted(SortOrder::Config, &items), ["board", "cpu", "gpu", "nvme"]);
+    }
+}
@@ -0,0 +1,85 @@
+//! Audible alarm for machines where nobody sees the notifications
+
+use crate::prelude::*;
+use crate::config::AlarmSound;
+use crate::exec;
+use std::io::Write;
+
+/// Plays the alarm sound, only one playback at a time
+#[derive(Debug, Default)]
+pub struct Player {
+    playing: Option<exec::Background>,
+}
+
+impl Player {
+    /// Returns true if the previous playback has not finished yet
+    fn is_playing(&mut self) -> bool {
+        let Some(playing) = &mut self.playing else {
+            return false;
+        };
+
+        match playing.finished() {
+            Ok(None) => return true,
+            Ok(Some(status)) if !status.success() =>
+                crate::log::warning!("Alarm sound {:?} exited with {status}", playing.name()),
+            Ok(Some(_)) => {},
+            Err(err) => crate::log::error!("{err:#}"),
+        }
+
+        self.playing = None;
+        false
+    }
+
+    /// Play the sound, alarms that happen during playback are coalesced into it
+    pub fn play(&mut self, sound: &AlarmSound) -> Result<()> {
+        if self.is_playing() {
+            crate::log::debug!("Alarm sound is already playing");
+            return Ok(());
+        }
+
+        match sound {
+            AlarmSound::Bell => {
+                // goes to the pc speaker if there is one
+                let mut console = std::fs::OpenOptions::new()
+                    .write(true)
+                    .open("/dev/console")
+                    .with_context(|| anyhow!("Unable to open /dev/console for the alarm bell"))?;
+
+                console.write_all(b"\x07")
+                    .with_context(|| anyhow!("Unable to ring the alarm bell"))?;
+            },
+            AlarmSound::Command(command) => self.playing = Some(exec::spawn(command, &[])?),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_coalesce() {
+        let dir = TempDir::new("sound");
+        let path = dir.join("played");
+        let script = format!("echo played >> {path:?}; sleep 0.2");
+        let sound = AlarmSound::Command(vec!["sh".to_string(), "-c".to_string(), script]);
+
+        let mut player = Player::default();
+        player.play(&sound).unwrap();
+        player.play(&sound).unwrap();
+
+        while player.is_playing() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        player.play(&sound).unwrap();
+        while player.is_playing() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "played\nplayed\n");
+    }
+}
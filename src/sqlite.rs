@@ -0,0 +1,276 @@
+//! Minimal SQLite bindings, the library is loaded at runtime so kelvin still works without it
+
+use crate::prelude::*;
+use std::ffi::{CStr, CString, c_char, c_double, c_int, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const LIBRARY: &CStr = c"libsqlite3.so.0";
+const RTLD_NOW: c_int = 2;
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_ROW: c_int = 100;
+const SQLITE_DONE: c_int = 101;
+
+const SQLITE_OPEN_READONLY: c_int = 0x1;
+const SQLITE_OPEN_READWRITE: c_int = 0x2;
+const SQLITE_OPEN_CREATE: c_int = 0x4;
+
+/// Makes SQLite copy bound strings
+const SQLITE_TRANSIENT: isize = -1;
+
+/// How long to wait for a lock held by another process
+const BUSY_TIMEOUT_MS: c_int = 5000;
+
+unsafe extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+type OpenFn = unsafe extern "C" fn(*const c_char, *mut *mut c_void, c_int, *const c_char) -> c_int;
+type CloseFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+type ErrmsgFn = unsafe extern "C" fn(*mut c_void) -> *const c_char;
+type BusyTimeoutFn = unsafe extern "C" fn(*mut c_void, c_int) -> c_int;
+type PrepareFn = unsafe extern "C" fn(*mut c_void, *const c_char, c_int, *mut *mut c_void, *mut *const c_char) -> c_int;
+type BindTextFn = unsafe extern "C" fn(*mut c_void, c_int, *const c_char, c_int, isize) -> c_int;
+type BindDoubleFn = unsafe extern "C" fn(*mut c_void, c_int, c_double) -> c_int;
+type BindInt64Fn = unsafe extern "C" fn(*mut c_void, c_int, i64) -> c_int;
+type StepFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+type ResetFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+type FinalizeFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+type ColumnTextFn = unsafe extern "C" fn(*mut c_void, c_int) -> *const c_char;
+type ColumnDoubleFn = unsafe extern "C" fn(*mut c_void, c_int) -> c_double;
+type ColumnInt64Fn = unsafe extern "C" fn(*mut c_void, c_int) -> i64;
+
+/// Functions loaded from the library
+struct Library {
+    open: OpenFn,
+    close: CloseFn,
+    errmsg: ErrmsgFn,
+    busy_timeout: BusyTimeoutFn,
+    prepare: PrepareFn,
+    bind_text: BindTextFn,
+    bind_double: BindDoubleFn,
+    bind_int64: BindInt64Fn,
+    step: StepFn,
+    reset: ResetFn,
+    finalize: FinalizeFn,
+    column_text: ColumnTextFn,
+    column_double: ColumnDoubleFn,
+    column_int64: ColumnInt64Fn,
+}
+
+static LIBRARY_INSTANCE: OnceLock<Option<Library>> = OnceLock::new();
+
+impl Library {
+    fn load() -> Option<Self> {
+        // SAFETY: symbols are cast to their signatures from sqlite3.h
+        unsafe {
+            let handle = dlopen(LIBRARY.as_ptr(), RTLD_NOW);
+            if handle.is_null() {
+                return None;
+            }
+
+            macro_rules! symbol {
+                ($name:literal, $type:ty) => {{
+                    let ptr = dlsym(handle, $name.as_ptr());
+                    if ptr.is_null() {
+                        return None;
+                    }
+                    std::mem::transmute::<*mut c_void, $type>(ptr)
+                }};
+            }
+
+            Some(Self {
+                open: symbol!(c"sqlite3_open_v2", OpenFn),
+                close: symbol!(c"sqlite3_close", CloseFn),
+                errmsg: symbol!(c"sqlite3_errmsg", ErrmsgFn),
+                busy_timeout: symbol!(c"sqlite3_busy_timeout", BusyTimeoutFn),
+                prepare: symbol!(c"sqlite3_prepare_v2", PrepareFn),
+                bind_text: symbol!(c"sqlite3_bind_text", BindTextFn),
+                bind_double: symbol!(c"sqlite3_bind_double", BindDoubleFn),
+                bind_int64: symbol!(c"sqlite3_bind_int64", BindInt64Fn),
+                step: symbol!(c"sqlite3_step", StepFn),
+                reset: symbol!(c"sqlite3_reset", ResetFn),
+                finalize: symbol!(c"sqlite3_finalize", FinalizeFn),
+                column_text: symbol!(c"sqlite3_column_text", ColumnTextFn),
+                column_double: symbol!(c"sqlite3_column_double", ColumnDoubleFn),
+                column_int64: symbol!(c"sqlite3_column_int64", ColumnInt64Fn),
+            })
+        }
+    }
+
+    fn get() -> Result<&'static Self> {
+        LIBRARY_INSTANCE.get_or_init(Self::load).as_ref()
+            .with_context(|| anyhow!("Unable to load {LIBRARY:?}, is SQLite installed?"))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Value<'a> {
+    Integer(i64),
+    Real(f64),
+    Text(&'a str),
+}
+
+pub struct Connection {
+    lib: &'static Library,
+    db: *mut c_void,
+}
+
+impl Connection {
+    /// Open the database, creating it unless `read_only` is set
+    pub fn open(path: &Path, read_only: bool) -> Result<Self> {
+        let lib = Library::get()?;
+        let path_c = CString::new(path.as_os_str().as_bytes())
+            .with_context(|| anyhow!("Invalid path {path:?}"))?;
+
+        let flags = if read_only {
+            SQLITE_OPEN_READONLY
+        } else {
+            SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE
+        };
+
+        let mut db = std::ptr::null_mut();
+
+        // SAFETY: handle is closed even if opening fails, as sqlite requires
+        let result = unsafe { (lib.open)(path_c.as_ptr(), &mut db, flags, std::ptr::null()) };
+        let connection = Self { lib, db };
+
+        if result != SQLITE_OK {
+            bail!("Unable to open database {path:?}: {}", connection.error());
+        }
+
+        unsafe { (lib.busy_timeout)(db, BUSY_TIMEOUT_MS) };
+
+        Ok(connection)
+    }
+
+    /// Last error message of the connection
+    fn error(&self) -> String {
+        // SAFETY: message is owned by sqlite and copied right away
+        unsafe { CStr::from_ptr((self.lib.errmsg)(self.db)).to_string_lossy().into_owned() }
+    }
+
+    pub fn prepare(&self, sql: &str) -> Result<Statement<'_>> {
+        let sql_c = CString::new(sql).with_context(|| anyhow!("Invalid SQL {sql:?}"))?;
+        let mut stmt = std::ptr::null_mut();
+
+        // SAFETY: statement is finalized when dropped
+        let result = unsafe { (self.lib.prepare)(self.db, sql_c.as_ptr(), -1, &mut stmt, std::ptr::null_mut()) };
+        if result != SQLITE_OK {
+            bail!("Unable to prepare {sql:?}: {}", self.error());
+        }
+
+        Ok(Statement { connection: self, stmt })
+    }
+
+    /// Run statement that returns no rows
+    pub fn execute(&self, sql: &str, values: &[Value]) -> Result<()> {
+        let mut stmt = self.prepare(sql)?;
+        stmt.bind(values)?;
+        while stmt.step()? {}
+
+        Ok(())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        unsafe { (self.lib.close)(self.db) };
+    }
+}
+
+pub struct Statement<'a> {
+    connection: &'a Connection,
+    stmt: *mut c_void,
+}
+
+impl Statement<'_> {
+    /// Reset the statement and bind the values to parameters in order
+    pub fn bind(&mut self, values: &[Value]) -> Result<()> {
+        let lib = self.connection.lib;
+
+        // SAFETY: strings are copied by sqlite because of SQLITE_TRANSIENT
+        unsafe {
+            (lib.reset)(self.stmt);
+
+            for (i, value) in values.iter().enumerate() {
+                let index = i as c_int + 1;
+                let result = match value {
+                    Value::Integer(x) => (lib.bind_int64)(self.stmt, index, *x),
+                    Value::Real(x) => (lib.bind_double)(self.stmt, index, *x),
+                    Value::Text(x) => (lib.bind_text)(self.stmt, index, x.as_ptr().cast(), x.len() as c_int, SQLITE_TRANSIENT),
+                };
+
+                if result != SQLITE_OK {
+                    bail!("Unable to bind parameter {index}: {}", self.connection.error());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if there is a row to read
+    pub fn step(&mut self) -> Result<bool> {
+        match unsafe { (self.connection.lib.step)(self.stmt) } {
+            SQLITE_ROW => Ok(true),
+            SQLITE_DONE => Ok(false),
+            _ => bail!("Query failed: {}", self.connection.error()),
+        }
+    }
+
+    pub fn integer(&self, column: c_int) -> i64 {
+        unsafe { (self.connection.lib.column_int64)(self.stmt, column) }
+    }
+
+    pub fn real(&self, column: c_int) -> f64 {
+        unsafe { (self.connection.lib.column_double)(self.stmt, column) }
+    }
+
+    pub fn text(&self, column: c_int) -> String {
+        // SAFETY: text is valid until the next step and is copied right away
+        unsafe {
+            let ptr = (self.connection.lib.column_text)(self.stmt, column);
+            if ptr.is_null() {
+                return String::new();
+            }
+
+            CStr::from_ptr(ptr).to_string_lossy().into_owned()
+        }
+    }
+}
+
+impl Drop for Statement<'_> {
+    fn drop(&mut self) {
+        unsafe { (self.connection.lib.finalize)(self.stmt) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_sqlite() {
+        let dir = TempDir::new("sqlite");
+        let path = dir.join("test.db");
+
+        let db = Connection::open(&path, false).unwrap();
+        db.execute("CREATE TABLE test (a INTEGER, b REAL, c TEXT)", &[]).unwrap();
+        db.execute("INSERT INTO test VALUES (?, ?, ?)", &[Value::Integer(1), Value::Real(2.5), Value::Text("three")]).unwrap();
+        assert!(db.execute("INSERT INTO missing VALUES (1)", &[]).is_err());
+
+        let mut stmt = db.prepare("SELECT a, b, c FROM test WHERE a = ?").unwrap();
+        stmt.bind(&[Value::Integer(1)]).unwrap();
+        assert!(stmt.step().unwrap());
+        assert_eq!((stmt.integer(0), stmt.real(1), stmt.text(2)), (1, 2.5, "three".to_string()));
+        assert!(!stmt.step().unwrap());
+
+        drop(stmt);
+        drop(db);
+    }
+}
@@ -0,0 +1,48 @@
+//! Running statistics of a sensor since the start of the session
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SensorStats {
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl SensorStats {
+    pub fn update(&mut self, value: f64) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+
+        // welford, does not lose precision over a long session like a running sum would
+        self.count += 1;
+        self.mean += (value - self.mean) / self.count as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let mut stats = SensorStats::default();
+        for x in [41.0, 88.2, 62.5, 50.3] {
+            stats.update(x);
+        }
+
+        assert_eq!((stats.count, stats.min, stats.max), (4, 41.0, 88.2));
+        assert!((stats.mean - 60.5).abs() < 1e-9, "{}", stats.mean);
+
+        // negative values are not hidden by the zeroed default
+        let mut stats = SensorStats::default();
+        stats.update(-5.0);
+        assert_eq!((stats.min, stats.max, stats.mean), (-5.0, -5.0, -5.0));
+    }
+}
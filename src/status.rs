@@ -0,0 +1,190 @@
+//! Query the running daemon over a unix socket
+//!
+//! Protocol is line based, client sends a request and the daemon answers with a single json line
+
+use crate::prelude::*;
+use crate::alarm::ActiveAlarm;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const REQUEST_STATUS: &str = "status";
+const REQUEST_RESET_STATS: &str = "reset-stats";
+
+/// How long to wait for the daemon to answer
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn socket_path() -> PathBuf {
+    crate::daemon::runtime_dir().join("kelvin.sock")
+}
+
+/// Answer of the daemon to the status request
+#[derive(Debug, Deserialize)]
+pub struct StatusReply {
+    /// Output formatted the same as `--once`
+    pub text: String,
+    pub alarms: Vec<ActiveAlarm>,
+    pub readings: JsonValue,
+
+    /// Fan outputs, missing when there are none
+    #[serde(default)]
+    pub outputs: Vec<crate::fan::OutputStatus>,
+}
+
+/// Serves the latest status in a background thread
+#[derive(Debug)]
+pub struct StatusServer {
+    path: PathBuf,
+    shared: Arc<Shared>,
+}
+
+/// State shared with the server thread
+#[derive(Debug, Default)]
+struct Shared {
+    latest: Mutex<Option<String>>,
+    reset_stats: AtomicBool,
+}
+
+impl StatusServer {
+    /// Bind the socket, any existing socket is removed so only call it when holding the daemon lock
+    pub fn start(path: &Path) -> Result<Self> {
+        if path.exists() {
+            std::fs::remove_file(path)
+                .with_context(|| anyhow!("Unable to remove stale socket {path:?}"))?;
+        }
+
+        let listener = UnixListener::bind(path)
+            .with_context(|| anyhow!("Unable to bind status socket {path:?}"))?;
+
+        let shared = Arc::new(Shared::default());
+        let thread_shared = shared.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream
+                    .with_context(|| anyhow!("Unable to accept status connection"))
+                    .and_then(|x| answer(x, &thread_shared));
+
+                if let Err(err) = result {
+                    crate::log::warning!("Status request failed: {err:#}");
+                }
+            }
+        });
+
+        Ok(Self { path: path.to_path_buf(), shared })
+    }
+
+    /// Replace the status with output of the latest tick
+    pub fn update(&self, status: String) {
+        *self.shared.latest.lock().unwrap() = Some(status);
+    }
+
+    /// Returns true if a client asked to reset the statistics since the last call
+    pub fn take_reset_stats(&self) -> bool {
+        self.shared.reset_stats.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Drop for StatusServer {
+    fn drop(&mut self) {
+        // nothing to do if it fails, next daemon removes it anyway
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn answer(stream: UnixStream, shared: &Shared) -> Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)
+        .with_context(|| anyhow!("Unable to read request"))?;
+
+    let reply = match request.trim() {
+        REQUEST_STATUS => match shared.latest.lock().unwrap().clone() {
+            Some(x) => x,
+            None => serde_json::json!({ "error": "No readings yet" }).to_string(),
+        },
+        REQUEST_RESET_STATS => {
+            shared.reset_stats.store(true, Ordering::SeqCst);
+            serde_json::json!({}).to_string()
+        },
+        x => serde_json::json!({ "error": format!("Unknown request {x:?}") }).to_string(),
+    };
+
+    (&stream).write_all(format!("{reply}\n").as_bytes())
+        .with_context(|| anyhow!("Unable to send reply"))
+}
+
+/// Send the request to the daemon listening on the socket, errors it replies with are returned as errors
+fn request(path: &Path, request: &str) -> Result<JsonValue> {
+    let mut stream = UnixStream::connect(path)
+        .with_context(|| anyhow!("No daemon is reachable at {path:?}"))?;
+
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    stream.write_all(format!("{request}\n").as_bytes())
+        .with_context(|| anyhow!("Unable to send request to the daemon"))?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)
+        .with_context(|| anyhow!("Daemon did not answer"))?;
+
+    let reply: JsonValue = serde_json::from_str(&reply)
+        .with_context(|| anyhow!("Invalid reply from the daemon"))?;
+
+    if let Some(err) = reply.get("error").and_then(|x| x.as_str()) {
+        bail!("Daemon replied with error: {err}");
+    }
+
+    Ok(reply)
+}
+
+/// Ask the daemon for its latest readings
+pub fn query(path: &Path) -> Result<StatusReply> {
+    serde_json::from_value(request(path, REQUEST_STATUS)?)
+        .with_context(|| anyhow!("Invalid reply from the daemon"))
+}
+
+/// Ask the daemon to reset the statistics, done before its next tick
+pub fn reset_stats(path: &Path) -> Result<()> {
+    request(path, REQUEST_RESET_STATS).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_status() {
+        let dir = TempDir::new("status");
+        let path = dir.join("status.sock");
+
+        assert!(query(&path).is_err());
+
+        let server = StatusServer::start(&path).unwrap();
+        let err = query(&path).unwrap_err();
+        assert!(format!("{err:#}").contains("No readings yet"), "{err:#}");
+
+        server.update(r#"{"text":"cpu 45","alarms":[{"severity":"critical","message":"cpu is hot"}],"readings":[]}"#.to_string());
+        let reply = query(&path).unwrap();
+        assert_eq!(reply.text, "cpu 45");
+        assert_eq!(reply.alarms.len(), 1);
+        assert_eq!(reply.alarms[0].message, "cpu is hot");
+
+        assert!(!server.take_reset_stats());
+        reset_stats(&path).unwrap();
+        assert!(server.take_reset_stats());
+        assert!(!server.take_reset_stats());
+
+        drop(server);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,149 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const HWMON_ROOT: &str = "/sys/class/hwmon";
+pub const THERMAL_ROOT: &str = "/sys/class/thermal";
+pub const POWER_SUPPLY_ROOT: &str = "/sys/class/power_supply";
+
+/// Resolved device directories keyed by class root and device name
+static DEVICE_CACHE: Mutex<Option<HashMap<(PathBuf, String), PathBuf>>> = Mutex::new(None);
+
+/// Find device directory in `root` whose `name_file` contains `name`
+///
+/// For example hwmon chips have their name in `name`, thermal zones in `type`
+pub fn find_device(root: &Path, name_file: &str, name: &str) -> Result<PathBuf> {
+    let mut candidates = std::fs::read_dir(root)
+        .with_context(|| anyhow!("Unable to read {root:?}"))?
+        .filter_map(|x| x.ok().map(|x| x.path()))
+        .filter(|x| std::fs::read_to_string(x.join(name_file)).is_ok_and(|x| x.trim() == name))
+        .collect::<Vec<_>>();
+    candidates.sort();
+
+    match candidates.len() {
+        0 => bail!("No device named {name:?} found in {root:?}"),
+        1 => Ok(candidates.remove(0)),
+        _ => bail!("Multiple devices named {name:?} found in {root:?}: {candidates:?}"),
+    }
+}
+
+/// Same as `find_device` but the result is cached, so the directory is not scanned every tick
+///
+/// Cached path is dropped if it disappears, as device numbering may change when devices come and go
+pub fn find_device_cached(root: &Path, name_file: &str, name: &str) -> Result<PathBuf> {
+    let key = (root.to_path_buf(), name.to_string());
+
+    let mut cache = DEVICE_CACHE.lock().unwrap();
+    let cache = cache.get_or_insert_with(HashMap::new);
+
+    if let Some(path) = cache.get(&key) {
+        if path.exists() {
+            return Ok(path.clone());
+        }
+
+        cache.remove(&key);
+    }
+
+    let path = find_device(root, name_file, name)?;
+    cache.insert(key, path.clone());
+
+    Ok(path)
+}
+
+/// Resolve `<chip-name>/<file>` path into absolute hwmon path
+pub fn resolve_hwmon(root: &Path, path: &Path) -> Result<PathBuf> {
+    let mut components = path.components();
+
+    let chip = components.next()
+        .map(|x| x.as_os_str().to_string_lossy().to_string())
+        .with_context(|| anyhow!("Hwmon path {path:?} is empty"))?;
+
+    let file = components.as_path();
+    if file.as_os_str().is_empty() {
+        bail!("Hwmon path {path:?} is missing the file, expected <chip-name>/<file>");
+    }
+
+    Ok(find_device_cached(root, "name", &chip)?.join(file))
+}
+
+/// Resolve thermal zone type like `acpitz` into the path of its `temp` file
+pub fn resolve_thermal(root: &Path, zone: &Path) -> Result<PathBuf> {
+    let zone = zone.to_string_lossy();
+    if zone.is_empty() {
+        bail!("Thermal zone type is empty, expected a type like \"acpitz\"");
+    }
+
+    Ok(find_device_cached(root, "type", &zone)?.join("temp"))
+}
+
+/// Resolve `<supply-name>/<attribute>` path like `BAT0/capacity` into absolute power supply path
+///
+/// Supplies are named by their directory, so unlike hwmon nothing has to be searched
+pub fn resolve_power_supply(root: &Path, path: &Path) -> Result<PathBuf> {
+    let mut components = path.components();
+
+    let supply = components.next()
+        .with_context(|| anyhow!("Power supply path {path:?} is empty"))?;
+
+    let attribute = components.as_path();
+    if attribute.as_os_str().is_empty() {
+        bail!("Power supply path {path:?} is missing the attribute, expected <supply-name>/<attribute>");
+    }
+
+    let dir = root.join(supply);
+    if !dir.exists() {
+        bail!("No power supply named {:?} found in {root:?}", supply.as_os_str());
+    }
+
+    Ok(dir.join(attribute))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_resolve_hwmon() {
+        let root = TempDir::new("sysfs");
+
+        for (dir, name) in [("hwmon0", "k10temp"), ("hwmon1", "amdgpu"), ("hwmon2", "nvme"), ("hwmon3", "nvme")] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(root.join(dir).join("name"), format!("{name}\n")).unwrap();
+        }
+
+        assert_eq!(
+            resolve_hwmon(&root, Path::new("amdgpu/temp1_input")).unwrap(),
+            root.join("hwmon1/temp1_input"),
+        );
+
+        // ambiguous name lists all candidates
+        let err = resolve_hwmon(&root, Path::new("nvme/temp1_input")).unwrap_err().to_string();
+        assert!(err.contains("hwmon2") && err.contains("hwmon3"));
+
+        assert!(resolve_hwmon(&root, Path::new("coretemp/temp1_input")).is_err());
+        assert!(resolve_hwmon(&root, Path::new("amdgpu")).is_err());
+
+        // chip moved to a different number after a reboot
+        std::fs::rename(root.join("hwmon1"), root.join("hwmon4")).unwrap();
+        assert_eq!(
+            resolve_hwmon(&root, Path::new("amdgpu/temp1_input")).unwrap(),
+            root.join("hwmon4/temp1_input"),
+        );
+    }
+
+    #[test]
+    fn test_resolve_thermal() {
+        let root = TempDir::new("thermal");
+
+        for (dir, name) in [("thermal_zone0", "acpitz"), ("thermal_zone1", "x86_pkg_temp")] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(root.join(dir).join("type"), format!("{name}\n")).unwrap();
+        }
+
+        assert_eq!(resolve_thermal(&root, Path::new("x86_pkg_temp")).unwrap(), root.join("thermal_zone1/temp"));
+        assert!(resolve_thermal(&root, Path::new("iwlwifi_1")).is_err());
+        assert!(resolve_thermal(&root, Path::new("")).is_err());
+    }
+}
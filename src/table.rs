@@ -0,0 +1,178 @@
+//! Aligned table of all sensors for the terminal
+
+use crate::alarm::Severity;
+use crate::config::Colors;
+use crate::Widgets;
+
+/// Width of the character in a terminal, wide east asian characters take two columns
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        // combining marks and zero width characters
+        0x0300..=0x036F | 0x200B..=0x200F | 0xFE00..=0xFE0F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+/// Number of terminal columns the text takes
+pub fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// Pad the text with spaces on the right up to the width
+fn pad(text: &str, width: usize) -> String {
+    format!("{text}{}", " ".repeat(width.saturating_sub(display_width(text))))
+}
+
+/// Split the value on the decimal point, fraction keeps the point
+fn split_decimal(value: &str) -> (&str, &str) {
+    match value.find('.') {
+        Some(x) => value.split_at(x),
+        None => (value, ""),
+    }
+}
+
+struct Row {
+    label: String,
+    value: String,
+    unit: String,
+    severity: Severity,
+}
+
+/// Table with label, value and unit columns, values are aligned on the decimal point
+///
+/// Widths are computed from the rows, marker column is only there if any sensor has an alarm
+pub fn table(widgets: &Widgets, unavailable: &str, colors: Option<&Colors>) -> String {
+    let rows = widgets.iter()
+        .filter_map(|(_, widget)| {
+            let sensor = widget.sensor()?;
+            let label = sensor.label_name().unwrap_or(&sensor.name).to_string();
+
+            Some(match widget.reading() {
+                Some(reading) => Row {
+                    label,
+                    value: if reading.stale { format!("{}*", reading.formatted) } else { reading.formatted.clone() },
+                    unit: reading.unit.clone().unwrap_or_default(),
+                    severity: reading.severity,
+                },
+                None => Row {
+                    label,
+                    value: unavailable.to_string(),
+                    unit: String::new(),
+                    severity: Severity::Normal,
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let label_width = rows.iter().map(|x| display_width(&x.label)).max().unwrap_or(0);
+    let integer_width = rows.iter().map(|x| display_width(split_decimal(&x.value).0)).max().unwrap_or(0);
+    let fraction_width = rows.iter().map(|x| display_width(split_decimal(&x.value).1)).max().unwrap_or(0);
+    let unit_width = rows.iter().map(|x| display_width(&x.unit)).max().unwrap_or(0);
+    let any_alarm = rows.iter().any(|x| x.severity != Severity::Normal);
+
+    rows.iter()
+        .map(|row| {
+            let (integer, fraction) = split_decimal(&row.value);
+            let mut value = format!(
+                "{}{integer}{}",
+                " ".repeat(integer_width.saturating_sub(display_width(integer))),
+                pad(fraction, fraction_width),
+            );
+
+            // colored after padding so escapes do not count into the width
+            if let Some(colors) = colors {
+                value = crate::color::paint(&value, colors.severity(row.severity));
+            }
+
+            let mut line = format!("{}  {value}  {}", pad(&row.label, label_width), pad(&row.unit, unit_width));
+
+            if any_alarm {
+                line.push_str(match row.severity {
+                    Severity::Normal => "",
+                    Severity::Warning => "  !",
+                    Severity::Critical => "  !!",
+                });
+            }
+
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::alarm::AlarmState;
+    use crate::config::{Sensor, SensorLabel};
+    use crate::output::Reading;
+
+    struct TestWidget(Sensor, Option<Reading>);
+
+    impl crate::Widget for TestWidget {
+        fn value(&mut self, _ctx: &crate::Context) -> Result<String> {
+            bail!("unreachable")
+        }
+
+        fn sensor(&self) -> Option<&Sensor> {
+            Some(&self.0)
+        }
+
+        fn reading(&self) -> Option<&Reading> {
+            self.1.as_ref()
+        }
+    }
+
+    fn widget(label: &str, unit: Option<&str>, round: u8, value: Option<f64>, alarm: AlarmState) -> (String, Box<dyn crate::Widget>) {
+        let sensor = Sensor {
+            name: label.to_lowercase(),
+            label: Some(SensorLabel { name: label.into(), unit: unit.map(String::from) }),
+            round: Some(round),
+            ..Default::default()
+        };
+
+        let reading = value.map(|x| Reading::new(&sensor, x, x, alarm));
+        (String::new(), Box::new(TestWidget(sensor, reading)))
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("°C"), 2);
+        assert_eq!(display_width("温度"), 4);
+        assert_eq!(display_width("e\u{301}"), 1);
+    }
+
+    #[test]
+    fn test_table() {
+        let widgets: Widgets = vec![
+            widget("CPU", Some("°C"), 1, Some(61.3), AlarmState::Normal),
+            widget("温度", Some("°C"), 0, Some(105.0), AlarmState::High),
+            widget("Fan", Some("RPM"), 0, Some(1200.0), AlarmState::WarnHigh),
+            widget("Voltage", None, 3, Some(1.2), AlarmState::Normal),
+            widget("GPU", Some("°C"), 1, None, AlarmState::Normal),
+        ];
+
+        assert_eq!(table(&widgets, "N/A", None), [
+            "CPU        61.3    °C",
+            "温度      105      °C   !!",
+            "Fan      1200      RPM  !",
+            "Voltage     1.200",
+            "GPU       N/A",
+        ].join("\n"));
+    }
+}
@@ -0,0 +1,50 @@
+//! Helpers shared by tests
+
+use crate::{Context, Widgets};
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Directory for files of a test, removed when dropped even if the test panics
+///
+/// Every one is unique, so tests running in parallel never share files
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let count = COUNT.fetch_add(1, Ordering::SeqCst);
+        let path = std::env::temp_dir().join(format!("kelvin-test-{name}-{}-{count}", std::process::id()));
+
+        // left over by a run that was killed
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+
+        Self(path)
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Context and widgets of `kelvin` started with the arguments
+pub fn context(args: &[&str]) -> (Context, Widgets) {
+    let args = crate::cli::Cli::parse_from(std::iter::once("kelvin").chain(args.iter().copied()));
+    let mut config = crate::load_config(&args).unwrap();
+    let widgets = crate::create_widgets(&args, &mut config);
+
+    (Context::new(args, config), widgets)
+}
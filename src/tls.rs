@@ -0,0 +1,288 @@
+//! Minimal TLS client on top of OpenSSL, the library is loaded at runtime so kelvin still works without it
+
+use crate::prelude::*;
+use std::ffi::{CStr, CString, c_char, c_int, c_long, c_ulong, c_void};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const LIBRARY: &CStr = c"libssl.so.3";
+const RTLD_NOW: c_int = 2;
+
+const SSL_VERIFY_PEER: c_int = 1;
+const SSL_CTRL_SET_TLSEXT_HOSTNAME: c_int = 55;
+const TLSEXT_NAMETYPE_HOST_NAME: c_long = 0;
+
+const SSL_ERROR_WANT_READ: c_int = 2;
+const SSL_ERROR_WANT_WRITE: c_int = 3;
+const SSL_ERROR_SYSCALL: c_int = 5;
+const SSL_ERROR_ZERO_RETURN: c_int = 6;
+
+/// Result of the certificate verification when it passed
+const X509_V_OK: c_long = 0;
+
+unsafe extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+type MethodFn = unsafe extern "C" fn() -> *const c_void;
+type CtxNewFn = unsafe extern "C" fn(*const c_void) -> *mut c_void;
+type CtxFreeFn = unsafe extern "C" fn(*mut c_void);
+type CtxDefaultPathsFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+type CtxLoadLocationsFn = unsafe extern "C" fn(*mut c_void, *const c_char, *const c_char) -> c_int;
+type CtxSetVerifyFn = unsafe extern "C" fn(*mut c_void, c_int, *const c_void);
+type NewFn = unsafe extern "C" fn(*mut c_void) -> *mut c_void;
+type FreeFn = unsafe extern "C" fn(*mut c_void);
+type SetFdFn = unsafe extern "C" fn(*mut c_void, c_int) -> c_int;
+type CtrlFn = unsafe extern "C" fn(*mut c_void, c_int, c_long, *mut c_void) -> c_long;
+type SetHostFn = unsafe extern "C" fn(*mut c_void, *const c_char) -> c_int;
+type ConnectFn = unsafe extern "C" fn(*mut c_void) -> c_int;
+type IoFn = unsafe extern "C" fn(*mut c_void, *mut c_void, c_int) -> c_int;
+type GetErrorFn = unsafe extern "C" fn(*const c_void, c_int) -> c_int;
+type VerifyResultFn = unsafe extern "C" fn(*const c_void) -> c_long;
+type VerifyStringFn = unsafe extern "C" fn(c_long) -> *const c_char;
+type ErrGetFn = unsafe extern "C" fn() -> c_ulong;
+type ErrStringFn = unsafe extern "C" fn(c_ulong, *mut c_char, usize);
+
+/// Functions loaded from the library, the ones from libcrypto are found through its dependencies
+struct Library {
+    method: MethodFn,
+    ctx_new: CtxNewFn,
+    ctx_free: CtxFreeFn,
+    ctx_default_paths: CtxDefaultPathsFn,
+    ctx_load_locations: CtxLoadLocationsFn,
+    ctx_set_verify: CtxSetVerifyFn,
+    new: NewFn,
+    free: FreeFn,
+    set_fd: SetFdFn,
+    ctrl: CtrlFn,
+    set_host: SetHostFn,
+    connect: ConnectFn,
+    read: IoFn,
+    write: IoFn,
+    shutdown: ConnectFn,
+    get_error: GetErrorFn,
+    verify_result: VerifyResultFn,
+    verify_string: VerifyStringFn,
+    err_get: ErrGetFn,
+    err_string: ErrStringFn,
+}
+
+static LIBRARY_INSTANCE: OnceLock<Option<Library>> = OnceLock::new();
+
+impl Library {
+    fn load() -> Option<Self> {
+        // SAFETY: symbols are cast to their signatures from ssl.h and err.h
+        unsafe {
+            let handle = dlopen(LIBRARY.as_ptr(), RTLD_NOW);
+            if handle.is_null() {
+                return None;
+            }
+
+            macro_rules! symbol {
+                ($name:literal, $type:ty) => {{
+                    let ptr = dlsym(handle, $name.as_ptr());
+                    if ptr.is_null() {
+                        return None;
+                    }
+                    std::mem::transmute::<*mut c_void, $type>(ptr)
+                }};
+            }
+
+            Some(Self {
+                method: symbol!(c"TLS_client_method", MethodFn),
+                ctx_new: symbol!(c"SSL_CTX_new", CtxNewFn),
+                ctx_free: symbol!(c"SSL_CTX_free", CtxFreeFn),
+                ctx_default_paths: symbol!(c"SSL_CTX_set_default_verify_paths", CtxDefaultPathsFn),
+                ctx_load_locations: symbol!(c"SSL_CTX_load_verify_locations", CtxLoadLocationsFn),
+                ctx_set_verify: symbol!(c"SSL_CTX_set_verify", CtxSetVerifyFn),
+                new: symbol!(c"SSL_new", NewFn),
+                free: symbol!(c"SSL_free", FreeFn),
+                set_fd: symbol!(c"SSL_set_fd", SetFdFn),
+                ctrl: symbol!(c"SSL_ctrl", CtrlFn),
+                set_host: symbol!(c"SSL_set1_host", SetHostFn),
+                connect: symbol!(c"SSL_connect", ConnectFn),
+                read: symbol!(c"SSL_read", IoFn),
+                write: symbol!(c"SSL_write", IoFn),
+                shutdown: symbol!(c"SSL_shutdown", ConnectFn),
+                get_error: symbol!(c"SSL_get_error", GetErrorFn),
+                verify_result: symbol!(c"SSL_get_verify_result", VerifyResultFn),
+                verify_string: symbol!(c"X509_verify_cert_error_string", VerifyStringFn),
+                err_get: symbol!(c"ERR_get_error", ErrGetFn),
+                err_string: symbol!(c"ERR_error_string_n", ErrStringFn),
+            })
+        }
+    }
+
+    fn get() -> Result<&'static Self> {
+        LIBRARY_INSTANCE.get_or_init(Self::load).as_ref()
+            .with_context(|| anyhow!("Unable to load {LIBRARY:?}, is OpenSSL installed?"))
+    }
+
+    /// Oldest error in the queue of the thread, the queue is cleared
+    fn error(&self) -> String {
+        let mut message = String::new();
+
+        // SAFETY: buffer length is passed so the string is always terminated
+        unsafe {
+            loop {
+                let code = (self.err_get)();
+                if code == 0 {
+                    break;
+                }
+
+                if message.is_empty() {
+                    let mut buffer = [0 as c_char; 256];
+                    (self.err_string)(code, buffer.as_mut_ptr(), buffer.len());
+                    message = CStr::from_ptr(buffer.as_ptr()).to_string_lossy().into_owned();
+                }
+            }
+        }
+
+        if message.is_empty() {
+            message = "unknown error".to_string();
+        }
+
+        message
+    }
+}
+
+/// TLS connection over TCP, the certificate and host name of the server are verified
+pub struct TlsStream {
+    lib: &'static Library,
+    ctx: *mut c_void,
+    ssl: *mut c_void,
+
+    // closed after the connection is freed
+    stream: TcpStream,
+}
+
+// SAFETY: the connection is only used by the thread that owns it
+unsafe impl Send for TlsStream {}
+
+impl TlsStream {
+    /// Handshake over the connected stream, `ca_file` replaces the system certificates
+    pub fn connect(stream: TcpStream, host: &str, ca_file: Option<&Path>) -> Result<Self> {
+        let lib = Library::get()?;
+        let host_c = CString::new(host).with_context(|| anyhow!("Invalid host {host:?}"))?;
+
+        // SAFETY: context and connection are freed when dropped, also when the handshake fails
+        unsafe {
+            let ctx = (lib.ctx_new)((lib.method)());
+            if ctx.is_null() {
+                bail!("Unable to create TLS context: {}", lib.error());
+            }
+
+            let mut tls = Self { lib, ctx, ssl: std::ptr::null_mut(), stream };
+
+            let loaded = match ca_file {
+                Some(path) => {
+                    let path_c = CString::new(path.as_os_str().as_encoded_bytes())
+                        .with_context(|| anyhow!("Invalid path {path:?}"))?;
+                    (lib.ctx_load_locations)(ctx, path_c.as_ptr(), std::ptr::null())
+                },
+                None => (lib.ctx_default_paths)(ctx),
+            };
+
+            if loaded != 1 {
+                bail!("Unable to load certificates{}: {}", ca_file.map(|x| format!(" from {x:?}")).unwrap_or_default(), lib.error());
+            }
+
+            (lib.ctx_set_verify)(ctx, SSL_VERIFY_PEER, std::ptr::null());
+
+            tls.ssl = (lib.new)(ctx);
+            if tls.ssl.is_null() {
+                bail!("Unable to create TLS connection: {}", lib.error());
+            }
+
+            // name for the server to pick the certificate, then the name the certificate must match
+            (lib.ctrl)(tls.ssl, SSL_CTRL_SET_TLSEXT_HOSTNAME, TLSEXT_NAMETYPE_HOST_NAME, host_c.as_ptr() as *mut c_void);
+            if (lib.set_host)(tls.ssl, host_c.as_ptr()) != 1 || (lib.set_fd)(tls.ssl, tls.stream.as_raw_fd()) != 1 {
+                bail!("Unable to set up TLS connection to {host:?}: {}", lib.error());
+            }
+
+            if (lib.connect)(tls.ssl) != 1 {
+                let verify = (lib.verify_result)(tls.ssl);
+                if verify != X509_V_OK {
+                    let reason = CStr::from_ptr((lib.verify_string)(verify)).to_string_lossy();
+                    bail!("Certificate of {host:?} is not trusted: {reason}");
+                }
+
+                bail!("TLS handshake with {host:?} failed: {}", lib.error());
+            }
+
+            Ok(tls)
+        }
+    }
+
+    /// Underlying TCP stream, for timeouts
+    #[cfg(feature = "mqtt")]
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Result of a read or write that did not transfer anything
+    fn failed(&self, result: c_int) -> std::io::Result<usize> {
+        // SAFETY: connection is valid for the lifetime of self
+        let code = unsafe { (self.lib.get_error)(self.ssl, result) };
+
+        match code {
+            SSL_ERROR_ZERO_RETURN => Ok(0),
+
+            // the socket timed out, it can be retried
+            SSL_ERROR_WANT_READ | SSL_ERROR_WANT_WRITE => Err(std::io::ErrorKind::WouldBlock.into()),
+            SSL_ERROR_SYSCALL => match std::io::Error::last_os_error() {
+                x if x.raw_os_error() == Some(0) => Err(std::io::ErrorKind::UnexpectedEof.into()),
+                x => Err(x),
+            },
+            _ => Err(std::io::Error::other(self.lib.error())),
+        }
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+
+        // SAFETY: length is at most the length of the buffer
+        let result = unsafe { (self.lib.read)(self.ssl, buf.as_mut_ptr().cast(), len) };
+        match result > 0 {
+            true => Ok(result as usize),
+            false => self.failed(result),
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+
+        // SAFETY: length is at most the length of the buffer, the pointer is only read from
+        let result = unsafe { (self.lib.write)(self.ssl, buf.as_ptr() as *mut c_void, len) };
+        match result > 0 {
+            true => Ok(result as usize),
+            false => self.failed(result),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        // SAFETY: pointers are either valid or null, freeing null does nothing
+        unsafe {
+            if !self.ssl.is_null() {
+                (self.lib.shutdown)(self.ssl);
+                (self.lib.free)(self.ssl);
+            }
+
+            (self.lib.ctx_free)(self.ctx);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::config::{Config, SensorSource, SmoothingMethod, format_placeholders, get_by_path};
+use serde_json::Value as JsonValue;
+use std::path::Path;
+
+/// Anything above this many decimals is most likely a mistake
+const MAX_SANE_ROUND: u8 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+
+    /// Config key the problem is about, like `sensors.cpu.min`
+    pub key: String,
+
+    pub message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        write!(f, "{severity}: {}: {}", self.key, self.message)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Problems(Vec<Problem>);
+
+impl Problems {
+    fn error(&mut self, key: impl Into<String>, message: impl Into<String>) {
+        self.0.push(Problem { severity: Severity::Error, key: key.into(), message: message.into() });
+    }
+
+    fn warning(&mut self, key: impl Into<String>, message: impl Into<String>) {
+        self.0.push(Problem { severity: Severity::Warning, key: key.into(), message: message.into() });
+    }
+}
+
+/// Check the config for semantic mistakes, returns all problems found
+///
+/// Paths of lm_sensors sensors are only checked if `sensors` is provided
+pub fn validate(config: &Config, sensors: Option<&JsonValue>, builtins: &[&str]) -> Vec<Problem> {
+    let mut problems = Problems::default();
+
+    if config.poll_rate < crate::MINIMAL_POLL_RATE {
+        problems.error("poll_rate", format!("must be at least {}", crate::duration::format(crate::MINIMAL_POLL_RATE)));
+    }
+
+    if config.idle.is_some() {
+        match config.idle_poll_rate {
+            None => problems.error("idle_poll_rate", "is required when idle detection is enabled"),
+            Some(x) if x < crate::MINIMAL_POLL_RATE =>
+                problems.error("idle_poll_rate", format!("must be at least {}", crate::duration::format(crate::MINIMAL_POLL_RATE))),
+            Some(_) => {},
+        }
+    }
+
+    if let Some(format) = &config.format {
+        for var in format_placeholders(format) {
+            if !builtins.contains(&var) && !config.has_placeholder(var) {
+                problems.error("format", format!("placeholder {{{var}}} does not match any sensor"));
+            }
+        }
+    }
+
+    for sensor in &config.sensors {
+        let key = |x: &str| format!("sensors.{}.{x}", sensor.name);
+
+        if let (Some(min), Some(max)) = (sensor.min, sensor.max) && min >= max {
+            problems.error(key("min"), format!("min ({min}) must be lower than max ({max})"));
+        }
+
+        if let (Some(low), Some(high)) = (sensor.alarm_low, sensor.alarm_high) && low >= high {
+            problems.error(key("alarm_low"), format!("alarm_low ({low}) must be lower than alarm_high ({high})"));
+        }
+
+        if let (Some(warn), Some(alarm)) = (sensor.warn_high, sensor.alarm_high) && warn >= alarm {
+            problems.warning(key("warn_high"), format!("warn_high ({warn}) is not lower than alarm_high ({alarm}), it will never be shown"));
+        }
+
+        if let (Some(warn), Some(alarm)) = (sensor.warn_low, sensor.alarm_low) && warn <= alarm {
+            problems.warning(key("warn_low"), format!("warn_low ({warn}) is not higher than alarm_low ({alarm}), it will never be shown"));
+        }
+
+        if sensor.alarm_hysteresis.is_some_and(|x| x < 0.0) {
+            problems.error(key("alarm_hysteresis"), "must not be negative");
+        }
+
+        for (name, threshold) in [("alarm_low", sensor.alarm_low), ("alarm_high", sensor.alarm_high)] {
+            let Some(threshold) = threshold else {
+                continue;
+            };
+
+            if sensor.min.is_some_and(|x| threshold < x) || sensor.max.is_some_and(|x| threshold > x) {
+                problems.warning(key(name), format!("threshold {threshold} is outside of min/max range so it may never trigger"));
+            }
+        }
+
+        if let Some(smoothing) = &sensor.smoothing {
+            match smoothing.method {
+                SmoothingMethod::Average if smoothing.window == 0 =>
+                    problems.error(key("smoothing.window"), "must be at least 1"),
+                SmoothingMethod::Ema if !(smoothing.alpha > 0.0 && smoothing.alpha <= 1.0) =>
+                    problems.error(key("smoothing.alpha"), format!("alpha ({}) must be above 0 and at most 1", smoothing.alpha)),
+                _ => {},
+            }
+        } else if sensor.alarm_on_raw.is_some() {
+            problems.warning(key("alarm_on_raw"), "does nothing without smoothing");
+        }
+
+        match (&sensor.source, sensor.retries, sensor.refetch_on_retry) {
+            (SensorSource::Sensors, 1.., false) => problems.warning(key("retries"), "does nothing for lm_sensors without refetch_on_retry"),
+            (SensorSource::Sensors, 0, true) => problems.warning(key("refetch_on_retry"), "does nothing without retries"),
+            (SensorSource::Sensors, _, _) => {},
+            (_, _, true) => problems.warning(key("refetch_on_retry"), "only applies to lm_sensors"),
+            _ => {},
+        }
+
+        if sensor.clamp && (sensor.map.is_some() || sensor.curve.is_some()) {
+            problems.warning(key("clamp"), "does nothing, map and curve always keep the value inside their output range");
+        }
+
+        if let Some(round) = sensor.round && round > MAX_SANE_ROUND {
+            problems.warning(key("round"), format!("rounding to {round} decimals is more precision than any sensor has"));
+        }
+
+        match sensor.source {
+            SensorSource::Sensors => {
+                if let Some(sensors) = sensors && let Err(err) = get_by_path(sensors, &sensor.path, sensor.first_match) {
+                    problems.error(key("path"), format!("{:?} not found in lm_sensors output, {err:#}", sensor.path));
+                }
+            },
+            SensorSource::File => {
+                // devices may be powered down so this is not necessarily an error
+                if !sensor.path.exists() {
+                    problems.warning(key("path"), format!("file {:?} does not exist", sensor.path));
+                }
+            },
+            SensorSource::Hwmon => {
+                match crate::sysfs::resolve_hwmon(Path::new(crate::sysfs::HWMON_ROOT), &sensor.path) {
+                    Ok(path) if !path.exists() => problems.warning(key("path"), format!("file {path:?} does not exist")),
+                    Ok(_) => {},
+                    Err(err) => problems.warning(key("path"), format!("{err:#}")),
+                }
+            },
+            SensorSource::Thermal => {
+                match crate::sysfs::resolve_thermal(Path::new(crate::sysfs::THERMAL_ROOT), &sensor.path) {
+                    Ok(path) if !path.exists() => problems.warning(key("path"), format!("file {path:?} does not exist")),
+                    Ok(_) => {},
+                    Err(err) => problems.warning(key("path"), format!("{err:#}")),
+                }
+            },
+            SensorSource::PowerSupply => {
+                match crate::sysfs::resolve_power_supply(Path::new(crate::sysfs::POWER_SUPPLY_ROOT), &sensor.path) {
+                    Ok(path) if !path.exists() => problems.warning(key("path"), format!("file {path:?} does not exist")),
+                    Ok(_) => {},
+                    Err(err) => problems.warning(key("path"), format!("{err:#}")),
+                }
+            },
+            // nvidia-smi is not run, the GPU may not be there yet
+            SensorSource::Nvidia => {
+                if let Err(err) = crate::nvidia::parse_path(&sensor.path) {
+                    problems.error(key("path"), format!("{err:#}"));
+                }
+            },
+            SensorSource::Command => {
+                if sensor.command.is_empty() {
+                    problems.error(key("command"), "is required for command source");
+                }
+            },
+            // inputs are checked when the config is loaded as it cannot work otherwise
+            SensorSource::Virtual => {},
+        }
+    }
+
+    problems.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(config: &str) -> Vec<(Severity, String)> {
+        let config: Config = toml::from_str(config).unwrap();
+        let sensors: JsonValue = serde_json::from_str(include_str!("../tests/fixtures/sensors.json")).unwrap();
+
+        validate(&config, Some(&sensors), &["time"])
+            .into_iter()
+            .map(|x| (x.severity, x.key))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid() {
+        assert!(problems(r#"
+            format = "{cpu} {time}"
+
+            [[sensors]]
+            name = "cpu"
+            min = 0
+            max = 100
+            alarm_high = 90
+            source = "sensors"
+            path = "k10temp-pci-00c3/Tctl/temp1_input"
+        "#).is_empty());
+    }
+
+    #[test]
+    fn test_all_problems_reported() {
+        assert_eq!(problems(r#"
+            format = "{gpu}"
+            poll_rate = 10
+
+            [[sensors]]
+            name = "cpu"
+            min = 100
+            max = 0
+            alarm_low = 50
+            alarm_high = 40
+            round = 20
+            retries = 2
+            curve = [[0, 0], [100, 50]]
+            clamp = true
+            source = "sensors"
+            path = "k10temp-pci-00c3/Tctl/temp9_input"
+        "#), [
+            (Severity::Error, "poll_rate".to_string()),
+            (Severity::Error, "format".to_string()),
+            (Severity::Error, "sensors.cpu.min".to_string()),
+            (Severity::Error, "sensors.cpu.alarm_low".to_string()),
+            (Severity::Warning, "sensors.cpu.alarm_low".to_string()),
+            (Severity::Warning, "sensors.cpu.alarm_high".to_string()),
+            (Severity::Warning, "sensors.cpu.retries".to_string()),
+            (Severity::Warning, "sensors.cpu.clamp".to_string()),
+            (Severity::Warning, "sensors.cpu.round".to_string()),
+            (Severity::Error, "sensors.cpu.path".to_string()),
+        ]);
+    }
+}
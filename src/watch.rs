@@ -0,0 +1,156 @@
+//! Watch the config file for changes using inotify
+//!
+//! The parent directory is watched so editors that write a temporary file and rename it over the config, or
+//! deleting and recreating the file, are all noticed
+
+use crate::prelude::*;
+use std::ffi::{CString, c_char, c_int};
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::FromRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+const IN_NONBLOCK: c_int = 0o4000;
+const IN_CLOEXEC: c_int = 0o2000000;
+
+const IN_CLOSE_WRITE: u32 = 0x8;
+const IN_MOVED_FROM: u32 = 0x40;
+const IN_MOVED_TO: u32 = 0x80;
+const IN_DELETE: u32 = 0x200;
+
+/// Size of `struct inotify_event` without the name
+const EVENT_SIZE: usize = 16;
+
+unsafe extern "C" {
+    fn inotify_init1(flags: c_int) -> c_int;
+    fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// File was written or replaced
+    Changed,
+
+    /// File was deleted or moved away
+    Deleted,
+}
+
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    inotify: File,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &Path) -> Result<Self> {
+        let path = std::path::absolute(path)
+            .with_context(|| anyhow!("Unable to get absolute path of {path:?}"))?;
+
+        let Some(dir) = path.parent() else {
+            bail!("Config path {path:?} has no parent directory");
+        };
+
+        let dir_c = CString::new(dir.as_os_str().as_bytes())
+            .with_context(|| anyhow!("Invalid path {dir:?}"))?;
+
+        // SAFETY: plain syscalls, the fd is owned by the file from here on
+        let inotify = unsafe {
+            let fd = inotify_init1(IN_NONBLOCK | IN_CLOEXEC);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| anyhow!("Unable to initialize inotify"));
+            }
+
+            let inotify = File::from_raw_fd(fd);
+
+            if inotify_add_watch(fd, dir_c.as_ptr(), IN_CLOSE_WRITE | IN_MOVED_FROM | IN_MOVED_TO | IN_DELETE) < 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| anyhow!("Unable to watch directory {dir:?}"));
+            }
+
+            inotify
+        };
+
+        Ok(Self { path, inotify })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Drain pending events without blocking, events since the last call are merged into one
+    ///
+    /// This debounces editors that write the file multiple times or write and then rename it
+    pub fn poll(&mut self) -> Result<Option<WatchEvent>> {
+        let file_name = self.path.file_name().map(|x| x.as_bytes()).unwrap_or_default();
+        let mut touched = false;
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            let len = match self.inotify.read(&mut buffer) {
+                Ok(x) => x,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err).with_context(|| anyhow!("Unable to read inotify events")),
+            };
+
+            let mut offset = 0;
+            while offset + EVENT_SIZE <= len {
+                let name_len = u32::from_ne_bytes(buffer[offset + 12..offset + 16].try_into().unwrap()) as usize;
+                let name = &buffer[offset + EVENT_SIZE..offset + EVENT_SIZE + name_len];
+
+                // name is padded with nul bytes
+                let name = name.split(|x| *x == 0).next().unwrap_or_default();
+                touched |= name == file_name;
+
+                offset += EVENT_SIZE + name_len;
+            }
+        }
+
+        if !touched {
+            return Ok(None);
+        }
+
+        // final state of the file is what matters
+        if self.path.exists() {
+            Ok(Some(WatchEvent::Changed))
+        } else {
+            Ok(Some(WatchEvent::Deleted))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_watch() {
+        let dir = TempDir::new("watch");
+
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "sensors = []").unwrap();
+
+        let mut watcher = ConfigWatcher::new(&path).unwrap();
+        assert_eq!(watcher.poll().unwrap(), None);
+
+        // other files are ignored
+        std::fs::write(dir.join("other.toml"), "").unwrap();
+        assert_eq!(watcher.poll().unwrap(), None);
+
+        // write then rename like editors do, reported only once
+        std::fs::write(&path, "sensors = []").unwrap();
+        std::fs::write(dir.join("config.toml.tmp"), "sensors = []").unwrap();
+        std::fs::rename(dir.join("config.toml.tmp"), &path).unwrap();
+        assert_eq!(watcher.poll().unwrap(), Some(WatchEvent::Changed));
+        assert_eq!(watcher.poll().unwrap(), None);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(watcher.poll().unwrap(), Some(WatchEvent::Deleted));
+
+        // recreating it is noticed
+        std::fs::write(&path, "sensors = []").unwrap();
+        assert_eq!(watcher.poll().unwrap(), Some(WatchEvent::Changed));
+    }
+}
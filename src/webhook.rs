@@ -0,0 +1,222 @@
+//! Requests sent when the alarm state of a sensor changes
+//!
+//! Each delivery runs in its own thread, so a slow or failing server never delays polling
+
+use crate::prelude::*;
+use crate::alarm::{AlarmState, Severity};
+use crate::config::{Sensor, WebhookConfig};
+use crate::net::Url;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Wait before the first retry, doubled for each one after it
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Alarm state transition, sent as the json body
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub hostname: String,
+    pub sensor: String,
+    pub value: f64,
+
+    /// Threshold that was crossed, for recovery the one of the cleared alarm
+    pub threshold: Option<f64>,
+    pub state: AlarmState,
+    pub severity: Severity,
+    pub message: String,
+
+    /// RFC 3339 local time
+    pub timestamp: String,
+}
+
+impl Event {
+    pub fn new(sensor: &Sensor, previous: AlarmState, state: AlarmState, value: f64) -> Self {
+        let threshold = match state {
+            AlarmState::Normal => previous.threshold(sensor),
+            _ => state.threshold(sensor),
+        };
+
+        let message = match crate::alarm::alarm_message(sensor, state, value) {
+            Some(x) => x.message,
+            None => format!("{} is back to normal", sensor.format_labeled(value)),
+        };
+
+        Self {
+            hostname: crate::config::get_hostname().unwrap_or_default(),
+            sensor: sensor.name.clone(),
+            value,
+            threshold,
+            state,
+            severity: state.severity(),
+            message,
+            timestamp: chrono::Local::now().to_rfc3339(),
+        }
+    }
+
+    /// Body of the request, the template if there is one otherwise json
+    ///
+    /// Placeholders of the event win over sensors with the same name
+    pub fn body(&self, webhook: &WebhookConfig, values: &HashMap<String, f64>) -> Result<String> {
+        let Some(template) = &webhook.template else {
+            return serde_json::to_string(self).with_context(|| anyhow!("Unable to serialize alarm event"));
+        };
+
+        let placeholders = [
+            ("sensor", self.sensor.clone()),
+            ("value", self.value.to_string()),
+            ("threshold", self.threshold.map(|x| x.to_string()).unwrap_or_default()),
+            ("state", self.state.as_str().to_string()),
+            ("severity", self.severity.as_str().to_string()),
+            ("message", self.message.clone()),
+            ("hostname", self.hostname.clone()),
+            ("timestamp", self.timestamp.clone()),
+        ];
+
+        let mut body = template.clone();
+        for (name, value) in placeholders {
+            body = body.replace(&crate::format_var(name), &value);
+        }
+
+        for (name, value) in values {
+            body = body.replace(&crate::format_var(name), &value.to_string());
+        }
+
+        Ok(body)
+    }
+}
+
+/// Send the body, retrying failed connections and server errors, the last error is returned
+pub fn deliver(webhook: &WebhookConfig, body: &str) -> Result<crate::http::Response> {
+    let url = Url::parse(&webhook.url)?;
+    let content_type = match webhook.template {
+        Some(_) => "text/plain; charset=utf-8",
+        None => "application/json",
+    };
+
+    let mut headers = webhook.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect::<Vec<_>>();
+    if !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("content-type")) {
+        headers.push(("Content-Type", content_type));
+    }
+
+    let mut attempt = 0;
+    loop {
+        let error = match crate::http::request(&webhook.method, &url, &headers, body.as_bytes(), webhook.timeout, webhook.ca_file.as_deref()) {
+            Ok(x) if x.is_success() => return Ok(x),
+
+            // the request itself is wrong, sending it again would not help
+            Ok(x) if x.status < 500 && x.status != 429 => bail!("Webhook {} answered with status {}: {}", url.address(), x.status, x.body.trim()),
+            Ok(x) => anyhow!("Webhook {} answered with status {}: {}", url.address(), x.status, x.body.trim()),
+            Err(err) => err,
+        };
+
+        if attempt >= webhook.retries {
+            return Err(error);
+        }
+
+        crate::log::debug!("{error:#}, retrying");
+        std::thread::sleep(RETRY_DELAY * 2u32.saturating_pow(attempt));
+        attempt += 1;
+    }
+}
+
+/// Deliver the event in the background, failures are logged
+pub fn send(webhook: &WebhookConfig, event: &Event, values: &HashMap<String, f64>) {
+    let body = match event.body(webhook, values) {
+        Ok(x) => x,
+        Err(err) => {
+            crate::log::error!("{err:#}");
+            return;
+        },
+    };
+
+    let webhook = webhook.clone();
+    let sensor = event.sensor.clone();
+    let result = std::thread::Builder::new()
+        .name("webhook".into())
+        .spawn(move || {
+            if let Err(err) = deliver(&webhook, &body) {
+                crate::log::error!("Unable to send alarm webhook of sensor {sensor:?}: {err:#}");
+            }
+        });
+
+    if let Err(err) = result {
+        crate::log::error!("Unable to start webhook thread: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn event() -> Event {
+        let sensor = Sensor { name: "cpu".into(), alarm_high: Some(80.0), ..Default::default() };
+        Event { timestamp: "2024-01-01T00:00:00+00:00".into(), hostname: "host".into(), ..Event::new(&sensor, AlarmState::Normal, AlarmState::High, 85.0) }
+    }
+
+    #[test]
+    fn test_body() {
+        let mut webhook: WebhookConfig = toml::from_str("url = \"https://ntfy.sh/alarms\"").unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&event().body(&webhook, &HashMap::new()).unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "hostname": "host",
+            "sensor": "cpu",
+            "value": 85.0,
+            "threshold": 80.0,
+            "state": "high",
+            "severity": "critical",
+            "message": event().message,
+            "timestamp": "2024-01-01T00:00:00+00:00",
+        }));
+
+        webhook.template = Some("{hostname}: {sensor} is {value} over {threshold} ({severity}), gpu {gpu}, {missing}".into());
+        let values = HashMap::from([("gpu".to_string(), 60.5), ("sensor".to_string(), 1.0)]);
+        assert_eq!(event().body(&webhook, &values).unwrap(), "host: cpu is 85 over 80 (critical), gpu 60.5, {missing}");
+
+        let sensor = Sensor { name: "cpu".into(), alarm_high: Some(80.0), ..Default::default() };
+        let recovery = Event::new(&sensor, AlarmState::High, AlarmState::Normal, 70.0);
+        assert_eq!((recovery.threshold, recovery.severity), (Some(80.0), Severity::Normal));
+        assert!(recovery.message.ends_with("is back to normal"), "{}", recovery.message);
+    }
+
+    #[test]
+    fn test_deliver() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // fails once, then takes the request
+        let server = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for status in ["503 Service Unavailable", "200 OK"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut buffer = [0u8; 1024];
+                while !request.ends_with("alarm!") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    request.push_str(std::str::from_utf8(&buffer[..read]).unwrap());
+                }
+
+                stream.write_all(format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").as_bytes()).unwrap();
+                requests.push(request);
+            }
+
+            requests
+        });
+
+        let webhook: WebhookConfig = toml::from_str(&format!("url = \"http://127.0.0.1:{port}/hook\"\nmethod = \"PUT\"\nheaders = {{ Title = \"kelvin\" }}\ntemplate = \"\"")).unwrap();
+        assert_eq!(deliver(&webhook, "alarm!").unwrap().status, 200);
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].starts_with("PUT /hook HTTP/1.1\r\n"), "{}", requests[1]);
+        assert!(requests[1].contains("\r\nTitle: kelvin\r\n"), "{}", requests[1]);
+        assert!(requests[1].contains("\r\nContent-Type: text/plain; charset=utf-8\r\n"), "{}", requests[1]);
+
+        // nothing listens anymore
+        let webhook = WebhookConfig { retries: 0, ..webhook };
+        assert!(deliver(&webhook, "alarm!").is_err());
+    }
+}
@@ -0,0 +1,583 @@
+//! YAML subset used for configs, parsed into json value and deserialized from it
+//!
+//! Supports block mappings and sequences, flow collections, quoted and block scalars and comments,
+//! anchors, tags and multiple documents are not supported
+
+use crate::prelude::*;
+use serde_json::{Map, Value as JsonValue};
+
+#[derive(Debug)]
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+
+    /// Text after the indentation, without comments
+    text: &'a str,
+
+    /// Text after the indentation as written, used by block scalars
+    raw: &'a str,
+}
+
+/// Remove comment, `#` starts one at the start or after whitespace outside of quotes
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some('\'') if c == '\'' => quote = None,
+            Some('"') if c == '"' && previous != '\\' => quote = None,
+            Some(_) => {},
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return &text[..i],
+            None => {},
+        }
+
+        previous = c;
+    }
+
+    text
+}
+
+/// Position of `: ` or trailing `:` separating a key from the value, outside of quotes and brackets
+fn find_colon(text: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut depth = 0;
+    let bytes = text.as_bytes();
+
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '[' || c == '{' => depth += 1,
+            None if c == ']' || c == '}' => depth -= 1,
+            None if c == ':' && depth == 0 && bytes.get(i + 1).is_none_or(|x| *x == b' ') => return Some(i),
+            None => {},
+        }
+    }
+
+    None
+}
+
+fn is_sequence_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+fn double_quoted(text: &str, number: usize) -> Result<String> {
+    let mut result = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        result.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('/') => '/',
+            Some(x) => bail!("line {number}: unknown escape \\{x}"),
+            None => bail!("line {number}: string ends with \\"),
+        });
+    }
+
+    Ok(result)
+}
+
+/// Split flow collection on commas outside of quotes and nested collections
+fn split_flow(text: &str) -> Vec<&str> {
+    let mut items = vec![];
+    let mut quote = None;
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '[' || c == '{' => depth += 1,
+            None if c == ']' || c == '}' => depth -= 1,
+            None if c == ',' && depth == 0 => {
+                items.push(text[start..i].trim());
+                start = i + 1;
+            },
+            None => {},
+        }
+    }
+
+    // trailing comma is allowed
+    let last = text[start..].trim();
+    if !last.is_empty() {
+        items.push(last);
+    }
+
+    items
+}
+
+/// Parse value written on a single line
+fn scalar(text: &str, number: usize) -> Result<JsonValue> {
+    let text = text.trim();
+
+    if let Some(inner) = text.strip_prefix('"') {
+        let Some(inner) = inner.strip_suffix('"') else {
+            bail!("line {number}: unterminated string");
+        };
+
+        return double_quoted(inner, number).map(JsonValue::String);
+    }
+
+    if let Some(inner) = text.strip_prefix('\'') {
+        let Some(inner) = inner.strip_suffix('\'') else {
+            bail!("line {number}: unterminated string");
+        };
+
+        return Ok(JsonValue::String(inner.replace("''", "'")));
+    }
+
+    if let Some(inner) = text.strip_prefix('[') {
+        let Some(inner) = inner.strip_suffix(']') else {
+            bail!("line {number}: unterminated sequence");
+        };
+
+        return split_flow(inner).into_iter().map(|x| scalar(x, number)).collect::<Result<Vec<_>>>().map(JsonValue::Array);
+    }
+
+    if let Some(inner) = text.strip_prefix('{') {
+        let Some(inner) = inner.strip_suffix('}') else {
+            bail!("line {number}: unterminated mapping");
+        };
+
+        let mut map = Map::new();
+        for item in split_flow(inner) {
+            let Some(colon) = find_colon(item) else {
+                bail!("line {number}: expected `key: value` in {item:?}");
+            };
+
+            map.insert(key(&item[..colon], number)?, scalar(&item[colon + 1..], number)?);
+        }
+
+        return Ok(JsonValue::Object(map));
+    }
+
+    if text.starts_with(['&', '*', '!']) {
+        bail!("line {number}: anchors, aliases and tags are not supported");
+    }
+
+    Ok(match text {
+        "" | "~" | "null" | "Null" | "NULL" => JsonValue::Null,
+        "true" | "True" | "TRUE" => JsonValue::Bool(true),
+        "false" | "False" | "FALSE" => JsonValue::Bool(false),
+        _ => {
+            let number = text.strip_prefix('+').unwrap_or(text);
+
+            if let Ok(x) = number.parse::<i64>() {
+                x.into()
+            } else if let Ok(x) = number.parse::<u64>() {
+                x.into()
+            } else if number.starts_with(|x: char| x.is_ascii_digit() || x == '-' || x == '.')
+                && let Ok(x) = number.parse::<f64>()
+                && x.is_finite() {
+                x.into()
+            } else {
+                JsonValue::String(text.to_string())
+            }
+        },
+    })
+}
+
+fn key(text: &str, number: usize) -> Result<String> {
+    match scalar(text, number)? {
+        JsonValue::String(x) => Ok(x),
+        JsonValue::Null => bail!("line {number}: empty key"),
+        x => Ok(x.to_string()),
+    }
+}
+
+struct Parser<'a> {
+    lines: Vec<Line<'a>>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Result<Self> {
+        let mut lines = vec![];
+
+        for (i, line) in text.lines().enumerate() {
+            let number = i + 1;
+            let trimmed = line.trim_start_matches(' ');
+
+            if trimmed.starts_with('\t') {
+                bail!("line {number}: tabs cannot be used for indentation");
+            }
+
+            lines.push(Line {
+                number,
+                indent: line.len() - trimmed.len(),
+                text: strip_comment(trimmed).trim_end(),
+                raw: trimmed,
+            });
+        }
+
+        Ok(Self { lines, pos: 0 })
+    }
+
+    /// Skip empty lines and return the next one
+    fn peek(&mut self) -> Option<&Line<'a>> {
+        while self.pos < self.lines.len() {
+            let line = &self.lines[self.pos];
+            if !line.text.is_empty() && !(line.indent == 0 && (line.text == "---" || line.text == "...")) {
+                break;
+            }
+
+            self.pos += 1;
+        }
+
+        self.lines.get(self.pos)
+    }
+
+    fn document(&mut self) -> Result<JsonValue> {
+        let Some(line) = self.peek() else {
+            return Ok(JsonValue::Null);
+        };
+
+        let indent = line.indent;
+        let value = self.node(indent)?;
+
+        if let Some(line) = self.peek() {
+            bail!("line {}: unexpected indentation", line.number);
+        }
+
+        Ok(value)
+    }
+
+    /// Parse block collection or scalar of the next line with the indent
+    fn node(&mut self, indent: usize) -> Result<JsonValue> {
+        let line = self.peek().unwrap();
+
+        if is_sequence_item(line.text) {
+            self.sequence(indent)
+        } else if find_colon(line.text).is_some() {
+            self.mapping(indent)
+        } else {
+            // multi line plain scalars are folded into one line
+            let mut parts = vec![];
+            while let Some(line) = self.peek() && line.indent >= indent && !is_sequence_item(line.text) && find_colon(line.text).is_none() {
+                parts.push(line.text.trim());
+                self.pos += 1;
+            }
+
+            let number = self.lines[self.pos - 1].number;
+            scalar(&parts.join(" "), number)
+        }
+    }
+
+    fn sequence(&mut self, indent: usize) -> Result<JsonValue> {
+        let mut items = vec![];
+
+        while let Some(line) = self.peek() && line.indent == indent && is_sequence_item(line.text) {
+            let rest = line.text[1..].trim_start();
+            let offset = line.text.len() - rest.len();
+
+            if rest.is_empty() {
+                self.pos += 1;
+                items.push(self.nested(indent, false)?);
+            } else if is_sequence_item(rest) || find_colon(rest).is_some() {
+                // collection starting on the same line, continued at the column of the first item
+                let line = &mut self.lines[self.pos];
+                line.indent += offset;
+                line.text = rest;
+                line.raw = &line.raw[offset..];
+
+                let indent = line.indent;
+                items.push(self.node(indent)?);
+            } else {
+                items.push(self.value(rest, indent)?);
+            }
+        }
+
+        if let Some(line) = self.peek() && line.indent > indent {
+            bail!("line {}: unexpected indentation", line.number);
+        }
+
+        Ok(JsonValue::Array(items))
+    }
+
+    fn mapping(&mut self, indent: usize) -> Result<JsonValue> {
+        let mut map = Map::new();
+
+        while let Some(line) = self.peek() && line.indent == indent && !is_sequence_item(line.text) {
+            let (number, text) = (line.number, line.text);
+
+            let Some(colon) = find_colon(text) else {
+                bail!("line {number}: expected `key: value`");
+            };
+
+            let key = key(&text[..colon], number)?;
+            if map.contains_key(&key) {
+                bail!("line {number}: duplicate key {key:?}");
+            }
+
+            let rest = text[colon + 1..].trim();
+            let value = if rest.is_empty() {
+                self.pos += 1;
+                self.nested(indent, true)?
+            } else {
+                self.value(rest, indent)?
+            };
+
+            map.insert(key, value);
+        }
+
+        if let Some(line) = self.peek() && line.indent > indent {
+            bail!("line {}: unexpected indentation", line.number);
+        }
+
+        Ok(JsonValue::Object(map))
+    }
+
+    /// Value on the following lines, sequence of a mapping key can have the same indent
+    fn nested(&mut self, indent: usize, same_indent_sequence: bool) -> Result<JsonValue> {
+        match self.peek() {
+            Some(line) if line.indent > indent => {
+                let indent = line.indent;
+                self.node(indent)
+            },
+            Some(line) if same_indent_sequence && line.indent == indent && is_sequence_item(line.text) => self.sequence(indent),
+            _ => Ok(JsonValue::Null),
+        }
+    }
+
+    /// Value written after `key:` or `-`, the current line is consumed
+    fn value(&mut self, text: &str, indent: usize) -> Result<JsonValue> {
+        let number = self.lines[self.pos].number;
+        self.pos += 1;
+
+        match text {
+            "|" | "|-" | "|+" | ">" | ">-" | ">+" => Ok(JsonValue::String(self.block_scalar(text, indent))),
+            _ => scalar(text, number),
+        }
+    }
+
+    /// Literal `|` keeps newlines, folded `>` joins lines with spaces, `-` strips the final newline
+    fn block_scalar(&mut self, style: &str, indent: usize) -> String {
+        let mut lines = vec![];
+        let mut block_indent = None;
+
+        while let Some(line) = self.lines.get(self.pos) {
+            // comments are part of the text, so the raw line is used
+            if line.raw.trim().is_empty() {
+                lines.push(String::new());
+                self.pos += 1;
+                continue;
+            }
+
+            let block_indent = *block_indent.get_or_insert(line.indent);
+            if line.indent <= indent || line.indent < block_indent {
+                break;
+            }
+
+            // indentation past the first line is kept
+            lines.push(format!("{}{}", " ".repeat(line.indent - block_indent), line.raw.trim_end()));
+            self.pos += 1;
+        }
+
+        let trailing = lines.iter().rev().take_while(|x| x.is_empty()).count();
+        lines.truncate(lines.len() - trailing);
+
+        let mut text = if style.starts_with('|') {
+            lines.join("\n")
+        } else {
+            lines.iter().fold(String::new(), |mut text, line| {
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push(if line.is_empty() { '\n' } else { ' ' });
+                }
+
+                text.push_str(line);
+                text
+            })
+        };
+
+        match style.chars().nth(1) {
+            Some('-') => {},
+            Some('+') => text.push_str(&"\n".repeat(trailing + 1)),
+            _ => text.push('\n'),
+        }
+
+        text
+    }
+}
+
+/// Parse the YAML into a json value
+pub fn parse(text: &str) -> Result<JsonValue> {
+    Parser::new(text)?.document()
+}
+
+/// Parse and deserialize the YAML
+pub fn from_str<T: serde::de::DeserializeOwned>(text: &str) -> Result<T> {
+    Ok(serde_json::from_value(parse(text)?)?)
+}
+
+/// Quote strings that would be read back as something else
+fn emit_string(text: &str) -> String {
+    let plain = matches!(scalar(text, 0), Ok(JsonValue::String(ref x)) if x == text)
+        && !text.starts_with([' ', '-', '[', '{', '"', '\'', '|', '>', '#', '&', '*', '!', '%', '@', '`', '?', ':'])
+        && !text.ends_with([' ', ':'])
+        && !text.contains(": ")
+        && !text.contains(" #")
+        && !text.contains(['\n', '\t', '\r']);
+
+    if plain {
+        text.to_string()
+    } else {
+        serde_json::Value::String(text.to_string()).to_string()
+    }
+}
+
+fn emit_scalar(value: &JsonValue) -> Option<String> {
+    match value {
+        JsonValue::Null => Some("null".to_string()),
+        JsonValue::Bool(x) => Some(x.to_string()),
+        JsonValue::Number(x) => Some(x.to_string()),
+        JsonValue::String(x) => Some(emit_string(x)),
+        JsonValue::Array(x) if x.is_empty() => Some("[]".to_string()),
+        JsonValue::Object(x) if x.is_empty() => Some("{}".to_string()),
+        _ => None,
+    }
+}
+
+fn emit(value: &JsonValue, indent: usize, out: &mut Vec<String>) {
+    let pad = " ".repeat(indent);
+
+    match value {
+        JsonValue::Object(map) => {
+            for (key, value) in map {
+                let key = emit_string(key);
+                match emit_scalar(value) {
+                    Some(x) => out.push(format!("{pad}{key}: {x}")),
+                    None => {
+                        out.push(format!("{pad}{key}:"));
+                        emit(value, indent + 2, out);
+                    },
+                }
+            }
+        },
+        JsonValue::Array(items) => {
+            for item in items {
+                match emit_scalar(item) {
+                    Some(x) => out.push(format!("{pad}- {x}")),
+                    None => {
+                        // first line of the nested collection goes after the dash
+                        let mut nested = vec![];
+                        emit(item, indent + 2, &mut nested);
+                        out.push(format!("{pad}- {}", &nested[0][indent + 2..]));
+                        out.extend(nested.into_iter().skip(1));
+                    },
+                }
+            }
+        },
+        x => out.push(format!("{pad}{}", emit_scalar(x).unwrap())),
+    }
+}
+
+/// Serialize the value as block YAML
+pub fn to_string<T: serde::Serialize>(value: &T) -> Result<String> {
+    let mut lines = vec![];
+    emit(&serde_json::to_value(value)?, 0, &mut lines);
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse() {
+        let value = parse(r#"
+# comment
+format: "{cpu} # not a comment"
+poll_rate: 5s   # comment
+idle_poll_rate: 1500
+temperature_unit: 'Fahrenheit'
+flag: true
+nothing: ~
+ratio: -0.5
+inputs: [cpu, "gpu, edge", 3]
+label: { name: CPU, unit: C }
+sensors:
+- name: cpu
+  path: k10temp-pci-00c3/Tctl/temp1_input
+  command:
+    - sh
+    - -c
+    - cat /tmp/value
+-   name: gpu
+    max: 100
+nested:
+  - - 1
+    - 2
+  - []
+"#).unwrap();
+
+        assert_eq!(value, json!({
+            "format": "{cpu} # not a comment",
+            "poll_rate": "5s",
+            "idle_poll_rate": 1500,
+            "temperature_unit": "Fahrenheit",
+            "flag": true,
+            "nothing": null,
+            "ratio": -0.5,
+            "inputs": ["cpu", "gpu, edge", 3],
+            "label": { "name": "CPU", "unit": "C" },
+            "sensors": [
+                {
+                    "name": "cpu",
+                    "path": "k10temp-pci-00c3/Tctl/temp1_input",
+                    "command": ["sh", "-c", "cat /tmp/value"],
+                },
+                { "name": "gpu", "max": 100 },
+            ],
+            "nested": [[1, 2], []],
+        }));
+    }
+
+    #[test]
+    fn test_block_scalar() {
+        let value = parse("literal: |\n  CPU {cpu}\n\n    GPU {gpu}\n\nfolded: >-\n  one\n  two\nnext: x\n").unwrap();
+        assert_eq!(value, json!({ "literal": "CPU {cpu}\n\n  GPU {gpu}\n", "folded": "one two", "next": "x" }));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |text: &str| parse(text).unwrap_err().to_string();
+
+        assert_eq!(err("a: 1\nb: 2\na: 3"), "line 3: duplicate key \"a\"");
+        assert_eq!(err("a: 1\n   b: 2"), "line 2: unexpected indentation");
+        assert_eq!(err("a:\n\tb: 2"), "line 2: tabs cannot be used for indentation");
+        assert_eq!(err("a: \"open"), "line 1: unterminated string");
+        assert_eq!(err("a: *ref"), "line 1: anchors, aliases and tags are not supported");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let value = json!({
+            "format": "{cpu}: {gpu}",
+            "sensors": [
+                { "name": "cpu", "path": "a/b", "round": 1, "command": ["sh", "-c", "echo 1"] },
+                { "name": "true", "label": { "name": "- dash", "unit": "°C" } },
+            ],
+            "empty": [],
+            "text": "multi\nline",
+        });
+
+        let text = to_string(&value).unwrap();
+        assert_eq!(parse(&text).unwrap(), value, "{text}");
+    }
+}